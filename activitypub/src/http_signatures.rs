@@ -6,10 +6,9 @@ use cgi::http::header;
 use rsa::{
     RsaPrivateKey,
     pkcs1::DecodeRsaPrivateKey,
-    pkcs1v15::{self, SigningKey, VerifyingKey},
-    pkcs8::DecodePublicKey,
+    pkcs1v15::SigningKey,
     sha2::{Digest, Sha256},
-    signature::{RandomizedSigner, SignatureEncoding, Verifier},
+    signature::{RandomizedSigner, SignatureEncoding},
 };
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
@@ -28,8 +27,8 @@ struct SignatureHeader {
 
 pub async fn validate(
     request: &cgi::Request,
-    connection: &PgPool,
-    settings: &Settings,
+    _connection: &PgPool,
+    _settings: &Settings,
 ) -> anyhow::Result<String> {
     let signature = request.headers().get("signature");
     let digest = request.headers().get("digest");
@@ -189,6 +188,7 @@ pub fn sign_and_call(request: Request, settings: &Settings) -> anyhow::Result<Re
         .call()?)
 }
 
+#[allow(dead_code)]
 async fn get_or_update_actor_public_key(
     actor_or_key_id: &str,
     connection: &PgPool,
@@ -230,7 +230,7 @@ async fn process_delete(
 }
 
 async fn is_blocked(actor: String, connection: &PgPool) -> anyhow::Result<bool> {
-    let server = actor.split('@').last().unwrap_or("");
+    let server = actor.split('@').next_back().unwrap_or("");
     let result = query!("SELECT COUNT(*) FROM activitypub_blocked WHERE (target_type = 'actor' AND target = $1) OR (target_type = 'server' AND target = $2)", actor, server)
         .fetch_optional(connection)
         .await?;
@@ -306,17 +306,17 @@ async fn process_like(
     .fetch_optional(connection)
     .await?;
 
-    if let Some(source) = activity {
-        if let Some(source_id) = source.source_post {
-            query!(
+    if let Some(source) = activity
+        && let Some(source_id) = source.source_post
+    {
+        query!(
             "INSERT INTO activitypub_likes(post_id, inbox_item_id, actor_id) VALUES($1, $2, $3)",
             source_id,
             item_id,
             actor.id
         )
-            .execute(connection)
-            .await?;
-        }
+        .execute(connection)
+        .await?;
     }
     Ok(())
 }
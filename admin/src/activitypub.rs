@@ -49,64 +49,8 @@ pub async fn publish_posts_from_request(globals: PageGlobals) -> anyhow::Result<
 }
 
 pub async fn publish_posts(globals: PageGlobals, push: bool) -> anyhow::Result<()> {
-    let follower_rows =
-        query!("SELECT actor FROM activitypub_known_actors aka WHERE EXISTS (SELECT 1 FROM activitypub_followers af WHERE af.actor_id = aka.id AND af.site_id=$1)", globals.site_id)
-            .fetch_all(&globals.connection_pool)
-            .await?;
-    let followers: Vec<String> = follower_rows
-        .into_iter()
-        .map(|r| r.actor.unwrap())
-        .collect();
-
-    let to_post = query!("SELECT id, title, summary, url_slug, post_date FROM posts p WHERE p.site_id = $1 AND NOT EXISTS (SELECT 1 FROM activitypub_outbox o WHERE o.source_post = p.id) AND p.state = 'published'", globals.site_id)
-        .fetch_all(&globals.connection_pool)
+    shared::generator::activitypub::publish_posts(&globals.connection_pool, globals.site_id, push)
         .await?;
-
-    let settings = get_settings_struct(&globals.connection_pool, globals.site_id).await?;
-
-    for post in to_post {
-        let post_url = blog_post_url(
-            post.url_slug,
-            post.post_date,
-            settings.timezone,
-            settings.base_url.clone(),
-        )?;
-
-        let summary = post.summary.unwrap_or("New post!".into());
-        let content = format!(
-            r#"<p>{}</p><p><a href="{}">{}</a></p>"#,
-            summary, post_url, post.title
-        );
-        let note = Activity::note(
-            content,
-            post_url.clone(),
-            post.post_date,
-            vec![activities::PUBLIC_TIMELINE.into()],
-            vec![],
-        );
-        let create = Activity::create(
-            settings.activitypub_actor_uri(),
-            note,
-            vec![activities::PUBLIC_TIMELINE.into()],
-            vec![],
-        );
-
-        let inserted = query!(
-            "INSERT INTO activitypub_outbox(activity_id, activity, source_post, site_id) VALUES($1, $2, $3, $4) RETURNING id",
-            post_url,
-            Json(create) as _,
-            post.id,
-			globals.site_id
-        )
-        .fetch_one(&globals.connection_pool)
-        .await?;
-        if push {
-            for f in &followers {
-                query!("INSERT INTO activitypub_outbox_target(activitypub_outbox_id, target) VALUES ($1, $2)", inserted.id, f).execute(&globals.connection_pool).await?;
-            }
-        }
-    }
-
     Ok(())
 }
 
@@ -32,7 +32,7 @@ impl fmt::Display for Follower {
             if let Some(username) = &self.username {
                 write!(f, "{}@{}", username, server)
             } else {
-                match self.actor.split('/').last() {
+                match self.actor.split('/').next_back() {
                     Some(name) => write!(f, "{}@{}", name, server),
                     _ => write!(f, "{}", self.actor),
                 }
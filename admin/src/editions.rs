@@ -0,0 +1,254 @@
+use anyhow::{anyhow, bail};
+use askama::Template;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use serde::Deserialize;
+use shared::utils::{parse_into, post_body, render_html, render_redirect};
+use sqlx::{PgPool, query, query_as};
+
+use crate::{
+    common::{Common, get_common},
+    filters,
+    types::{AdminMenuPages, EditionRequest, PageGlobals},
+};
+
+struct EditionListItem {
+    id: i32,
+    number: i32,
+    date: DateTime<Utc>,
+    post_count: i64,
+}
+
+struct EditionPost {
+    id: i32,
+    title: String,
+    post_date: DateTime<Utc>,
+}
+
+#[derive(Deserialize)]
+struct EditionDelete {
+    id: i32,
+}
+
+#[derive(Template)]
+#[template(path = "editions.html")]
+struct EditionsPage {
+    common: Common,
+    editions: Vec<EditionListItem>,
+    next_number: i32,
+    date: NaiveDateTime,
+    message: Option<String>,
+}
+
+#[derive(Template)]
+#[template(path = "edit_edition.html")]
+struct EditEditionPage {
+    common: Common,
+    number: i32,
+    date: NaiveDateTime,
+    posts: Vec<EditionPost>,
+    selected: Vec<i32>,
+    message: Option<String>,
+}
+
+async fn number_taken(
+    connection: &PgPool,
+    site_id: i32,
+    number: i32,
+    except: Option<i32>,
+) -> anyhow::Result<bool> {
+    let existing = query!(
+        "SELECT id FROM editions WHERE site_id=$1 AND number=$2 AND id IS DISTINCT FROM $3",
+        site_id,
+        number,
+        except
+    )
+    .fetch_optional(connection)
+    .await?;
+    Ok(existing.is_some())
+}
+
+fn to_utc(date: NaiveDateTime, common: &Common) -> anyhow::Result<DateTime<Utc>> {
+    Ok(date
+        .and_local_timezone(common.settings.timezone)
+        .earliest()
+        .ok_or(anyhow!("Could not set timezone on edition date"))?
+        .to_utc())
+}
+
+pub async fn editions(
+    request: &cgi::Request,
+    globals: PageGlobals,
+) -> anyhow::Result<cgi::Response> {
+    let common = get_common(&globals, AdminMenuPages::Editions).await?;
+    let mut message = None;
+
+    if request.method() == "POST" {
+        let req: EditionRequest = post_body(request)?;
+        if number_taken(&globals.connection_pool, globals.site_id, req.number, None).await? {
+            message = Some(format!("Edition {} already exists", req.number));
+        } else {
+            let date = to_utc(req.date, &common)?;
+            let row = query!(
+                "INSERT INTO editions(site_id, number, date) VALUES ($1, $2, $3) RETURNING id",
+                globals.site_id,
+                req.number,
+                date
+            )
+            .fetch_one(&globals.connection_pool)
+            .await?;
+            return render_redirect(&format!("edit_edition&id={}", row.id), globals.site_id);
+        }
+    }
+
+    let editions = query_as!(
+        EditionListItem,
+        r#"
+SELECT
+    e.id,
+    e.number,
+    e.date,
+    COUNT(ep.post_id) AS "post_count!"
+FROM editions e
+LEFT JOIN edition_posts ep
+ON ep.edition_id = e.id
+WHERE e.site_id=$1
+GROUP BY e.id
+ORDER BY e.number DESC"#,
+        globals.site_id
+    )
+    .fetch_all(&globals.connection_pool)
+    .await?;
+
+    let next_number = editions.iter().map(|e| e.number).max().unwrap_or(0) + 1;
+    let date = Utc::now()
+        .with_timezone(&common.settings.timezone)
+        .naive_local();
+
+    render_html(EditionsPage {
+        common,
+        editions,
+        next_number,
+        date,
+        message,
+    })
+}
+
+pub async fn edit_edition(
+    request: &cgi::Request,
+    globals: PageGlobals,
+) -> anyhow::Result<cgi::Response> {
+    let id: i32 = globals
+        .query
+        .get("id")
+        .ok_or(anyhow!("Could not find id"))
+        .and_then(|s| parse_into(s))?;
+    let common = get_common(&globals, AdminMenuPages::Editions).await?;
+    let mut message = None;
+
+    if request.method() == "POST" {
+        let req: EditionRequest = post_body(request)?;
+        if number_taken(
+            &globals.connection_pool,
+            globals.site_id,
+            req.number,
+            Some(id),
+        )
+        .await?
+        {
+            message = Some(format!("Edition {} already exists", req.number));
+        } else {
+            let date = to_utc(req.date, &common)?;
+            let mut transaction = globals.connection_pool.begin().await?;
+            query!(
+                "UPDATE editions SET number=$1, date=$2 WHERE id=$3 AND site_id=$4",
+                req.number,
+                date,
+                id,
+                globals.site_id
+            )
+            .execute(&mut *transaction)
+            .await?;
+            query!("DELETE FROM edition_posts WHERE edition_id=$1", id)
+                .execute(&mut *transaction)
+                .await?;
+            query!(
+                "INSERT INTO edition_posts(edition_id, post_id) SELECT $1, id FROM posts WHERE site_id=$2 AND id = ANY($3)",
+                id,
+                globals.site_id,
+                &req.posts.unwrap_or_default()
+            )
+            .execute(&mut *transaction)
+            .await?;
+            transaction.commit().await?;
+            return render_redirect("editions", globals.site_id);
+        }
+    }
+
+    let edition = query!(
+        r#"
+SELECT
+    number,
+    date,
+    array_agg(ep.post_id) FILTER (WHERE ep.post_id IS NOT NULL) AS "posts?"
+FROM editions e
+LEFT JOIN edition_posts ep
+ON ep.edition_id = e.id
+WHERE e.id=$1 AND e.site_id=$2
+GROUP BY e.id"#,
+        id,
+        globals.site_id
+    )
+    .fetch_one(&globals.connection_pool)
+    .await?;
+
+    let posts = query_as!(
+        EditionPost,
+        "SELECT id, title, post_date FROM posts WHERE site_id=$1 AND state <> 'draft' ORDER BY post_date DESC",
+        globals.site_id
+    )
+    .fetch_all(&globals.connection_pool)
+    .await?;
+
+    let date = edition
+        .date
+        .with_timezone(&common.settings.timezone)
+        .naive_local();
+
+    render_html(EditEditionPage {
+        common,
+        number: edition.number,
+        date,
+        posts,
+        selected: edition.posts.unwrap_or_default(),
+        message,
+    })
+}
+
+/// Deletes an edition. Its posts stay published, they just no longer belong
+/// to an edition.
+pub async fn delete_edition(
+    request: &cgi::Request,
+    globals: PageGlobals,
+) -> anyhow::Result<cgi::Response> {
+    if request.method() != "POST" {
+        bail!("Bad request");
+    }
+    let body: EditionDelete = post_body(request)?;
+    let mut transaction = globals.connection_pool.begin().await?;
+    query!(
+        "DELETE FROM edition_posts WHERE edition_id=(SELECT id FROM editions WHERE id=$1 AND site_id=$2)",
+        body.id,
+        globals.site_id
+    )
+    .execute(&mut *transaction)
+    .await?;
+    query!(
+        "DELETE FROM editions WHERE id=$1 AND site_id=$2",
+        body.id,
+        globals.site_id
+    )
+    .execute(&mut *transaction)
+    .await?;
+    transaction.commit().await?;
+    render_redirect("editions", globals.site_id)
+}
@@ -1,28 +1,22 @@
 use crate::response::redirect_response;
 use crate::types::{PageGlobals, PostRequest};
-use shared::generator::feeds::{generate_atom_feed, generate_rss_feed};
-use shared::generator::get_common;
-use shared::generator::index::{generate_index_pages, generate_tag_indexes};
-use shared::generator::month_index::generate_month_index_pages;
-use shared::generator::pages::generate_pages;
-use shared::generator::posts::{generate_post_html, generate_post_page};
-use shared::generator::static_content::generate_static;
-use shared::generator::templates::load_templates;
+use shared::generator::navigation::PostNavigation;
+use shared::generator::posts::generate_post_html;
 use shared::generator::types::Generator;
-use shared::generator::year_index::generate_year_index_pages;
+use shared::generator::{get_common, get_published_posts, regenerate_site};
 use shared::types::{CommonData, HydratedPost};
 use shared::utils::post_body;
 
-use tokio::fs::{create_dir, try_exists};
-
-use sqlx::{query, query_as};
+use chrono::Utc;
+use sqlx::query;
 use std::env;
 
 pub async fn preview_page(
     request: &cgi::Request,
     globals: PageGlobals,
 ) -> anyhow::Result<cgi::Response> {
-    let common = get_common(&globals.connection_pool, globals.site_id).await?;
+    let mut common = get_common(&globals.connection_pool, globals.site_id).await?;
+    common.preview = true;
     let user = query!(
         "SELECT display_name FROM users WHERE id=$1",
         globals.session.user_id
@@ -56,6 +50,7 @@ pub async fn preview_page(
     let post = HydratedPost {
         id: 0,
         post_date,
+        updated_date: Utc::now(),
         url_slug: "preview".into(),
         title: data.title,
         body: data.body,
@@ -66,18 +61,13 @@ pub async fn preview_page(
         comment_count: Some(0),
         tags: Some(tags),
         site_id: globals.site_id,
+        edition: None,
     };
 
-    let tera = load_templates(&globals.connection_pool, globals.site_id, &common).await?;
-
-    let generator = Generator {
-        output_path: "",
-        pool: &globals.connection_pool,
-        common: &common,
-        tera,
-        site_id: globals.site_id,
-    };
-    let post_page = generate_post_html(&generator, &post).await?;
+    let posts = get_published_posts(&globals.connection_pool, globals.site_id).await?;
+    let navigation = PostNavigation::new(&post, &posts, &common)?;
+    let generator = Generator::new("", &globals.connection_pool, &common, globals.site_id).await?;
+    let post_page = generate_post_html(&generator, &post, &navigation, None, &[]).await?;
 
     Ok(cgi::html_response(200, post_page))
 }
@@ -88,34 +78,7 @@ pub struct PageContent {
 }
 
 pub async fn get_content(globals: &PageGlobals) -> anyhow::Result<PageContent> {
-    let posts = query_as!(
-        HydratedPost,
-        r#"
-SELECT
-    posts.id as id,
-    post_date,
-    url_slug,
-    title,
-    body,
-    song,
-    mood,
-    summary,
-    users.display_name AS author_name,
-    (SELECT COUNT(*) FROM comments WHERE comments.post_id = posts.id) AS comment_count,
-    (SELECT array_agg(t.name) FROM tags t INNER JOIN post_tag pt ON pt.tag_id = t.id WHERE pt.post_id = posts.id) AS tags,
-	site_id
-FROM posts
-INNER JOIN users
-ON users.id = posts.author_id
-WHERE state = 'published'
-AND posts.site_id = $1
-AND posts.post_date <= CURRENT_TIMESTAMP
-ORDER BY post_date DESC
-"#, globals.site_id
-    )
-    .fetch_all(&globals.connection_pool)
-    .await?;
-
+    let posts = get_published_posts(&globals.connection_pool, globals.site_id).await?;
     let common = get_common(&globals.connection_pool, globals.site_id).await?;
 
     Ok(PageContent { posts, common })
@@ -124,44 +87,15 @@ ORDER BY post_date DESC
 pub async fn regenerate_blog(globals: &PageGlobals) -> anyhow::Result<cgi::Response> {
     let output_path_base =
         env::var("BLOG_OUTPUT_PATH").expect("Environment variable BLOG_OUTPUT_PATH is required");
-    let output_path = format!("{}/{}", output_path_base, globals.site_id);
-    let static_output_path = format!("{}/{}", output_path, "static");
-
-    if !(try_exists(&static_output_path).await?) {
-        create_dir(&static_output_path).await?;
-    }
-
-    let PageContent { posts, common } = get_content(globals).await?;
-    if posts.is_empty() {
-        return Ok(redirect_response("dashboard", globals.site_id));
-    }
+    let force = globals.query.get("force").is_some_and(|f| f == "true");
 
-    let tera = load_templates(&globals.connection_pool, globals.site_id, &common).await?;
-    let generator = Generator {
-        output_path: &output_path,
-        pool: &globals.connection_pool,
-        common: &common,
-        tera,
-        site_id: globals.site_id,
-    };
-
-    for post in &posts {
-        generate_post_page(&generator, post).await?;
-    }
-
-    generate_index_pages(
-        posts.iter().collect::<Vec<&HydratedPost>>().into_iter(),
-        &generator,
+    regenerate_site(
+        &globals.connection_pool,
+        globals.site_id,
+        &output_path_base,
+        force,
     )
     .await?;
 
-    generate_month_index_pages(&posts, &generator).await?;
-    generate_year_index_pages(&posts, &generator).await?;
-    generate_rss_feed(&posts, &generator).await?;
-    generate_atom_feed(&posts, &generator).await?;
-    generate_tag_indexes(&posts, &generator).await?;
-    generate_pages(&generator).await?;
-    generate_static(&generator, &static_output_path).await?;
-
     Ok(redirect_response("dashboard", globals.site_id))
 }
@@ -48,22 +48,20 @@ pub async fn render(request: &cgi::Request, globals: PageGlobals) -> anyhow::Res
                 .execute(&globals.connection_pool)
                 .await?;
             }
-            (Some("up"), Ok(id), Ok(position)) => {
-                if position >= 0 {
-                    query!(
+            (Some("up"), Ok(id), Ok(position)) if position >= 0 => {
+                query!(
                         "UPDATE external_links SET position = position + 1 WHERE position = $1 AND site_id=$2",
                         position - 1,
 							globals.site_id
                     )
                     .execute(&globals.connection_pool)
                     .await?;
-                    query!(
+                query!(
                         "UPDATE external_links SET position = position - 1 WHERE id = $1 AND site_id=$2",
                         id, globals.site_id
                     )
                     .execute(&globals.connection_pool)
                     .await?;
-                }
             }
             (Some("down"), Ok(id), Ok(position)) => {
                 let count = query!(
@@ -22,6 +22,7 @@ mod activitypub;
 mod comments;
 mod common;
 mod dashboard;
+mod editions;
 mod filters;
 mod generator;
 mod links;
@@ -29,6 +30,7 @@ mod media;
 mod page;
 mod post;
 mod prepublished;
+mod references;
 mod response;
 mod session;
 mod settings;
@@ -36,6 +38,7 @@ mod tags;
 mod templates;
 mod types;
 mod utils;
+mod validation;
 
 static ADMIN_CSS: &str = include_str!("../../static/admin.css");
 static PLAYFAIR_DISPLAY: &[u8] =
@@ -185,6 +188,11 @@ async fn process_inner(
                 "dashboard" => dashboard::render(request, page_request).await,
                 "new-post" => post::new_post(request, page_request).await,
                 "regenerate" => {
+                    if let Some(problems) =
+                        validation::check_before_regenerating(&page_request).await?
+                    {
+                        return Ok(problems);
+                    }
                     generator::regenerate_blog(&page_request).await?;
                     activitypub::publish_posts(page_request, true).await?;
                     render_redirect("dashboard", site_id)
@@ -192,6 +200,7 @@ async fn process_inner(
                 "account" => account::render(request, page_request).await,
                 "settings" => settings::render(request, page_request).await,
                 "links" => links::render(request, page_request).await,
+                "references" => references::render(request, page_request).await,
                 "edit_post" => post::edit_post(request, page_request).await,
                 "manage_posts" => post::manage_posts(page_request).await,
                 "comments" => comments::comment_list(page_request).await,
@@ -211,6 +220,10 @@ async fn process_inner(
                 "image" => image(page_request),
                 "edit_template" => templates::edit_template(request, page_request).await,
                 "delete_template" => templates::delete_template(request, page_request).await,
+                "editions" => editions::editions(request, page_request).await,
+                "edit_edition" => editions::edit_edition(request, page_request).await,
+                "delete_edition" => editions::delete_edition(request, page_request).await,
+                "validation" => validation::validation(page_request).await,
                 _ => do_404().await,
             }
         }
@@ -94,12 +94,25 @@ pub async fn manage_media(
 
             let disk_name = format!("{}_orig.{}", result.id, ext);
             let thumbnail_name = format!("{}_thumb.{}", result.id, ext);
+
+            image.save(format!("{}{}", media_path, disk_name))?;
+
+            let thumb = if image.width() > 128 || image.height() > 128 {
+                image.resize(128, 128, FilterType::Lanczos3)
+            } else {
+                image.clone()
+            };
+            thumb.save(format!("{}{}", media_path, thumbnail_name))?;
+
             let metadata = ImageMetadata {
                 width: image.width(),
                 height: image.height(),
                 content_type: type_str.into(),
                 fullsize_name: disk_name.clone(),
                 thumbnail_name: thumbnail_name.clone(),
+                // Resized copies are made by `publish`, as encoding them
+                // takes too long for an upload request.
+                variants: Vec::new(),
             };
 
             query!(
@@ -110,16 +123,6 @@ pub async fn manage_media(
             )
             .execute(&globals.connection_pool)
             .await?;
-
-            image.save(format!("{}{}", media_path, disk_name))?;
-
-            let thumb = if image.width() > 128 || image.height() > 128 {
-                image.resize(128, 128, FilterType::Lanczos3)
-            } else {
-                image
-            };
-
-            thumb.save(format!("{}{}", media_path, thumbnail_name))?;
         }
     }
 
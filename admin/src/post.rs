@@ -16,13 +16,18 @@ use anyhow::anyhow;
 use askama::Template;
 use chrono::{NaiveDateTime, offset::Utc};
 use regex::Regex;
-use sqlx::{PgPool, query, query_as};
+use sqlx::{PgConnection, PgPool, query, query_as};
 
 struct DisplayTag {
     id: i32,
     name: String,
 }
 
+struct DisplaySeries {
+    id: i32,
+    name: String,
+}
+
 #[derive(Template)]
 #[template(path = "new_post.html")]
 struct NewPost<'a> {
@@ -37,6 +42,9 @@ struct NewPost<'a> {
     status: PostStatus,
     tags: Vec<i32>,
     all_tags: Vec<DisplayTag>,
+    series: Option<i32>,
+    series_part: Option<i32>,
+    all_series: Vec<DisplaySeries>,
 }
 
 #[derive(Template)]
@@ -53,6 +61,9 @@ struct EditPost<'a> {
     status: PostStatus,
     tags: Vec<i32>,
     all_tags: Vec<DisplayTag>,
+    series: Option<i32>,
+    series_part: Option<i32>,
+    all_series: Vec<DisplaySeries>,
 }
 
 #[derive(Template)]
@@ -75,6 +86,121 @@ async fn get_tags(connection: &PgPool) -> anyhow::Result<Vec<DisplayTag>> {
     )
 }
 
+async fn get_series(connection: &PgPool, site_id: i32) -> anyhow::Result<Vec<DisplaySeries>> {
+    Ok(query_as!(
+        DisplaySeries,
+        "SELECT id, name FROM series WHERE site_id=$1 ORDER BY name",
+        site_id
+    )
+    .fetch_all(connection)
+    .await?)
+}
+
+fn make_slug(source: &str) -> anyhow::Result<String> {
+    let invalid_chars = Regex::new(r"[^a-z0-9_-]+")?;
+    Ok(invalid_chars
+        .replace_all(&source.to_ascii_lowercase(), " ")
+        .trim()
+        .replace(" ", "_"))
+}
+
+fn non_blank(value: &Option<String>) -> Option<&str> {
+    value.as_deref().map(str::trim).filter(|v| !v.is_empty())
+}
+
+/// Finds or starts a series whose name has nothing a slug can be made from,
+/// like one written entirely in another script. It gets a slug from its id
+/// so it can't be merged into another series with an empty slug.
+async fn save_unsluggable_series(
+    connection: &mut PgConnection,
+    site_id: i32,
+    name: &str,
+) -> anyhow::Result<i32> {
+    if let Some(existing) = query!(
+        "SELECT id FROM series WHERE site_id=$1 AND name=$2",
+        site_id,
+        name
+    )
+    .fetch_optional(&mut *connection)
+    .await?
+    {
+        return Ok(existing.id);
+    }
+
+    let id = query!(
+        "INSERT INTO series(site_id, name, url_slug) VALUES ($1, $2, gen_random_uuid()::text) RETURNING id",
+        site_id,
+        name
+    )
+    .fetch_one(&mut *connection)
+    .await?
+    .id;
+    query!("UPDATE series SET url_slug='series-' || id WHERE id=$1", id)
+        .execute(connection)
+        .await?;
+    Ok(id)
+}
+
+/// Puts the post in the series chosen on the form, starting a new series if
+/// a name was given. Without a part number it goes on the end. It's all one
+/// transaction, so a failure can't leave the post out of its series.
+async fn save_series(
+    connection: &PgPool,
+    site_id: i32,
+    post_id: i32,
+    req: &PostRequest,
+) -> anyhow::Result<()> {
+    let mut transaction = connection.begin().await?;
+    query!("DELETE FROM series_posts WHERE post_id=$1", post_id)
+        .execute(&mut *transaction)
+        .await?;
+
+    let series_id: i32 = match (non_blank(&req.new_series), non_blank(&req.series)) {
+        (Some(name), _) => match make_slug(name)? {
+            slug if slug.is_empty() => save_unsluggable_series(&mut transaction, site_id, name).await?,
+            slug => {
+                query!(
+                    "INSERT INTO series(site_id, name, url_slug) VALUES ($1, $2, $3) ON CONFLICT (site_id, url_slug) DO UPDATE SET name=series.name RETURNING id",
+                    site_id,
+                    name,
+                    slug
+                )
+                .fetch_one(&mut *transaction)
+                .await?
+                .id
+            }
+        },
+        (None, Some(id)) => parse_into(id)?,
+        (None, None) => {
+            transaction.commit().await?;
+            return Ok(());
+        }
+    };
+
+    let position: i32 = match non_blank(&req.series_part) {
+        Some(part) => parse_into(part)?,
+        None => query!(
+            r#"SELECT COALESCE(MAX(position), 0) + 1 AS "position!" FROM series_posts WHERE series_id=$1"#,
+            series_id
+        )
+        .fetch_one(&mut *transaction)
+        .await?
+        .position,
+    };
+
+    query!(
+        "INSERT INTO series_posts(post_id, series_id, position) SELECT $1, id, $2 FROM series WHERE id=$3 AND site_id=$4",
+        post_id,
+        position,
+        series_id,
+        site_id
+    )
+    .execute(&mut *transaction)
+    .await?;
+    transaction.commit().await?;
+    Ok(())
+}
+
 pub async fn new_post(
     request: &cgi::Request,
     globals: PageGlobals,
@@ -84,17 +210,11 @@ pub async fn new_post(
     if request.method() == "POST" {
         let req: PostRequest = post_body(request)?;
 
-        let invalid_chars = Regex::new(r"[^a-z0-9_-]+")?;
-        let mut initial_slug = if req.slug.is_empty() {
-            req.title.clone()
+        let slug = make_slug(if req.slug.is_empty() {
+            &req.title
         } else {
-            req.slug.clone()
-        };
-        initial_slug.make_ascii_lowercase();
-        let slug = invalid_chars
-            .replace_all(&initial_slug, " ")
-            .trim()
-            .replace(" ", "_");
+            &req.slug
+        })?;
         let final_slug: &str = &slug.to_owned();
 
         let post_date = req
@@ -126,7 +246,7 @@ RETURNING id"#,
         .fetch_optional(&globals.connection_pool)
         .await?;
         return if let Some(row) = result {
-            if let Some(tags) = req.tags {
+            if let Some(tags) = &req.tags {
                 for i in tags {
                     query!(
                         "INSERT INTO post_tag(post_id, tag_id) VALUES($1, $2)",
@@ -137,6 +257,7 @@ RETURNING id"#,
                     .await?;
                 }
             }
+            save_series(&globals.connection_pool, globals.site_id, row.id, &req).await?;
             Ok(response::redirect_response("dashboard", globals.site_id))
         } else {
             let content = NewPost {
@@ -149,8 +270,11 @@ RETURNING id"#,
                 mood: req.mood.as_deref(),
                 song: req.song.as_deref(),
                 summary: req.song.as_deref(),
-                tags: req.tags.unwrap_or_default(),
+                tags: req.tags.clone().unwrap_or_default(),
                 all_tags: get_tags(&globals.connection_pool).await?,
+                series: non_blank(&req.series).and_then(|s| s.parse().ok()),
+                series_part: non_blank(&req.series_part).and_then(|s| s.parse().ok()),
+                all_series: get_series(&globals.connection_pool, globals.site_id).await?,
             };
             render_html(content)
         };
@@ -172,6 +296,9 @@ RETURNING id"#,
         date,
         tags: vec![],
         all_tags: get_tags(&globals.connection_pool).await?,
+        series: None,
+        series_part: None,
+        all_series: get_series(&globals.connection_pool, globals.site_id).await?,
     };
     render_html(content)
 }
@@ -198,10 +325,10 @@ pub async fn edit_post(
             .ok_or(anyhow!("Could not set timezone on post time"))?
             .to_utc();
         query!(
-            "UPDATE posts SET title=$1, body=$2, state=$3, post_date = $4, url_slug=$5, song=$6, mood=$7, summary=$8 WHERE id=$9 AND site_id=$10",
+            "UPDATE posts SET title=$1, body=$2, state=$3, post_date = $4, url_slug=$5, song=$6, mood=$7, summary=$8, updated_date=CURRENT_TIMESTAMP WHERE id=$9 AND site_id=$10",
             req.title,
             req.body,
-            req.status as PostStatus,
+            &req.status as &PostStatus,
             post_date,
             req.slug,
             req.song,
@@ -216,7 +343,7 @@ pub async fn edit_post(
         query!("DELETE FROM post_tag WHERE post_id=$1", id)
             .execute(&globals.connection_pool)
             .await?;
-        if let Some(tags) = req.tags {
+        if let Some(tags) = &req.tags {
             for i in tags {
                 query!(
                     "INSERT INTO post_tag(post_id, tag_id) VALUES($1, $2)",
@@ -227,6 +354,7 @@ pub async fn edit_post(
                 .await?;
             }
         }
+        save_series(&globals.connection_pool, globals.site_id, id, &req).await?;
         if status == PostStatus::Published {
             return render_redirect("manage_posts", globals.site_id);
         }
@@ -251,6 +379,13 @@ GROUP BY posts.id"#,
         .with_timezone(&common.settings.timezone)
         .naive_local();
 
+    let series = query!(
+        "SELECT series_id, position FROM series_posts WHERE post_id=$1",
+        id
+    )
+    .fetch_optional(&globals.connection_pool)
+    .await?;
+
     let content = EditPost {
         common,
         title: &post.title,
@@ -263,6 +398,9 @@ GROUP BY posts.id"#,
         summary: post.summary.as_deref(),
         tags: post.tags.unwrap_or(vec![]),
         all_tags: get_tags(&globals.connection_pool).await?,
+        series: series.as_ref().map(|s| s.series_id),
+        series_part: series.map(|s| s.position),
+        all_series: get_series(&globals.connection_pool, globals.site_id).await?,
     };
     render_html(content)
 }
@@ -4,11 +4,10 @@ use anyhow::{anyhow, bail};
 use cgi::{binary_response, html_response, text_response};
 use chrono::Datelike;
 use serde_json::{Value, from_value};
+use shared::generator::feeds::feed_links;
 use shared::generator::index::index_content;
 use shared::generator::static_content::{StaticContent, get_static_content};
-use shared::generator::{
-    get_common, pages::generate_single_page, templates::load_templates, types::Generator,
-};
+use shared::generator::{get_common, pages::generate_single_page, types::Generator};
 use shared::types::{CommonData, HydratedPost};
 use shared::utils::parse_into;
 use tera::Function;
@@ -33,19 +32,14 @@ async fn get_generator<'a>(
     globals: &'a PageGlobals,
     common: &'a CommonData,
 ) -> anyhow::Result<Generator<'a>> {
-    let mut tera = load_templates(&globals.connection_pool, globals.site_id, common).await?;
+    let mut generator =
+        Generator::new("", &globals.connection_pool, common, globals.site_id).await?;
 
     let site_url = SiteUrlBuilder {};
-    tera.register_filter("posturl", page_url);
-    tera.register_function("buildurl", site_url);
+    generator.tera.register_filter("posturl", page_url);
+    generator.tera.register_function("buildurl", site_url);
 
-    Ok(Generator {
-        output_path: "",
-        pool: &globals.connection_pool,
-        common,
-        tera,
-        site_id: globals.site_id,
-    })
+    Ok(generator)
 }
 
 async fn page(globals: PageGlobals) -> anyhow::Result<cgi::Response> {
@@ -73,7 +67,15 @@ async fn index(globals: PageGlobals) -> anyhow::Result<cgi::Response> {
         .collect();
     let generator = get_generator(&globals, &common).await?;
 
-    let result = index_content(chunk, &generator, page_number, total_pages, "index.html")?;
+    let feeds = feed_links(&common, "", &common.blog_name);
+    let result = index_content(
+        chunk,
+        &generator,
+        page_number,
+        total_pages,
+        "index.html",
+        &feeds,
+    )?;
     Ok(html_response(200, result))
 }
 
@@ -0,0 +1,138 @@
+use anyhow::anyhow;
+use askama::Template;
+use bytes::Bytes;
+use futures_util::stream::once;
+use multer::Multipart;
+use shared::{
+    referencing::{Reference, import_references},
+    settings::get_settings_struct,
+    utils::render_html,
+};
+use sqlx::{query, types::Json};
+use std::convert::Infallible;
+
+use crate::{
+    common::{Common, get_common},
+    types::{AdminMenuPages, PageGlobals},
+};
+
+#[derive(Template)]
+#[template(path = "references.html")]
+struct References {
+    common: Common,
+    references: Vec<LibraryEntry>,
+    imported: Option<usize>,
+    errors: Vec<String>,
+}
+
+struct LibraryEntry {
+    id: i32,
+    name: String,
+    formatted: String,
+}
+
+/// The site's reference library. Files are imported as BibTeX or CSL-JSON,
+/// replacing entries with the same name.
+pub async fn render(request: &cgi::Request, globals: PageGlobals) -> anyhow::Result<cgi::Response> {
+    let mut imported = None;
+    let mut errors = Vec::new();
+    if request.method() == "POST" {
+        let content_type = request
+            .headers()
+            .get("x-cgi-content-type")
+            .ok_or(anyhow!("No content type! Must be multipart.form_data!"))
+            .and_then(|x| x.to_str().map_err(|e| anyhow!(e)))?;
+        let boundary = multer::parse_boundary(content_type)?;
+
+        let slice = request.body().to_owned();
+        let stream = once(async move { Result::<Bytes, Infallible>::Ok(Bytes::from(slice)) });
+
+        let mut action = String::new();
+        let mut id = None;
+        let mut sources = Vec::new();
+        let mut uploaded = Multipart::new(stream, boundary);
+        while let Some(field) = uploaded.next_field().await? {
+            match field.name() {
+                Some("action") => action = field.text().await?,
+                Some("id") => id = field.text().await?.parse::<i32>().ok(),
+                Some("file") | Some("source") => {
+                    let text = field.text().await?;
+                    if !text.trim().is_empty() {
+                        sources.push(text);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        match (action.as_str(), id) {
+            ("delete", Some(id)) => {
+                query!(
+                    "DELETE FROM reference_library WHERE id=$1 AND site_id=$2",
+                    id,
+                    globals.site_id
+                )
+                .execute(&globals.connection_pool)
+                .await?;
+            }
+            ("import", _) => {
+                let mut count = 0;
+                for source in sources {
+                    let references = match import_references(&source) {
+                        Ok(references) => references,
+                        Err(e) => {
+                            errors.push(format!("Could not read the file: {}", e));
+                            continue;
+                        }
+                    };
+                    for reference in references {
+                        match reference {
+                            Ok(reference) => {
+                                save_reference(&globals, &reference).await?;
+                                count += 1;
+                            }
+                            Err(e) => errors.push(e.to_string()),
+                        }
+                    }
+                }
+                imported = Some(count);
+            }
+            _ => {}
+        }
+    }
+
+    let settings = get_settings_struct(&globals.connection_pool, globals.site_id).await?;
+    let references = query!(
+        r#"SELECT id, name, reference AS "reference: Json<Reference>" FROM reference_library WHERE site_id=$1 ORDER BY name"#,
+        globals.site_id
+    )
+    .fetch_all(&globals.connection_pool)
+    .await?
+    .into_iter()
+    .map(|r| LibraryEntry {
+        id: r.id,
+        name: r.name,
+        formatted: r.reference.format_reference(settings.citation_style),
+    })
+    .collect();
+
+    let common = get_common(&globals, AdminMenuPages::References).await?;
+    render_html(References {
+        common,
+        references,
+        imported,
+        errors,
+    })
+}
+
+async fn save_reference(globals: &PageGlobals, reference: &Reference) -> anyhow::Result<()> {
+    query!(
+        "INSERT INTO reference_library(site_id, name, reference) VALUES($1, $2, $3) ON CONFLICT (site_id, name) DO UPDATE SET reference = EXCLUDED.reference",
+        globals.site_id,
+        reference.name(),
+        Json(reference) as _
+    )
+    .execute(&globals.connection_pool)
+    .await?;
+    Ok(())
+}
@@ -13,7 +13,10 @@ use futures_util::stream::once;
 use multer::Multipart;
 use shared::{
     errors::BlogError,
+    generator::highlight::theme_names,
+    referencing::CitationStyle,
     settings::{SettingNames, Settings as SettingsStruct, get_settings_struct},
+    types::MarkdownExtension,
 };
 use sqlx::query;
 use std::convert::Infallible;
@@ -24,8 +27,11 @@ use tokio::{fs::File, io::AsyncWriteExt};
 struct Settings {
     common: Common,
     settings: SettingsStruct,
+    code_themes: Vec<String>,
+    extensions: [MarkdownExtension; 6],
+    citation_styles: [CitationStyle; 4],
 }
-const STRING_FIELDS: [&str; 14] = [
+const STRING_FIELDS: [&str; 18] = [
     "blog_name",
     "actor_name",
     "base_url",
@@ -40,8 +46,16 @@ const STRING_FIELDS: [&str; 14] = [
     "timezone",
     "bsky_username",
     "bsky_password",
+    "image_widths",
+    "code_theme",
+    "code_theme_dark",
+    "citation_style",
 ];
 
+/// Checkboxes, which browsers leave out of the form entirely when they're
+/// unticked.
+const BOOLEAN_FIELDS: [&str; 3] = ["code_line_numbers", "heading_anchors", "sidenotes"];
+
 const FILE_FIELDS: [&str; 2] = ["fedi_avatar", "fedi_header"];
 const IMAGE_TYPES: [&str; 2] = ["image/jpeg", "image/png"];
 
@@ -58,6 +72,8 @@ pub async fn render(request: &cgi::Request, globals: PageGlobals) -> anyhow::Res
         let stream = once(async move { Result::<Bytes, Infallible>::Ok(Bytes::from(slice)) });
 
         let mut editions_enabled = false;
+        let mut ticked = Vec::new();
+        let mut extensions = Vec::new();
         let mut uploaded = Multipart::new(stream, boundary);
         while let Some(field) = uploaded.next_field().await? {
             let n = field.name().ok_or(anyhow!("No field name!"))?.to_owned();
@@ -73,6 +89,10 @@ pub async fn render(request: &cgi::Request, globals: PageGlobals) -> anyhow::Res
                     .await?;
             } else if n.as_str() == "editions" {
                 editions_enabled = true;
+            } else if BOOLEAN_FIELDS.contains(&n.as_str()) {
+                ticked.push(n.clone());
+            } else if n.as_str() == "markdown_extensions" {
+                extensions.push(field.text().await?);
             } else if FILE_FIELDS.contains(&n.as_str()) {
                 let content_type = field
                     .content_type()
@@ -122,6 +142,27 @@ pub async fn render(request: &cgi::Request, globals: PageGlobals) -> anyhow::Res
         .execute(&globals.connection_pool)
         .await?;
 
+        for field in BOOLEAN_FIELDS {
+            query!(
+                "INSERT INTO blog_settings VALUES($1, $2, $3) ON CONFLICT (setting_name, site_id) DO UPDATE SET value = EXCLUDED.value",
+                field,
+                ticked.iter().any(|t| t == field).to_string(),
+                globals.site_id
+            )
+            .execute(&globals.connection_pool)
+            .await?;
+        }
+
+        // Each ticked extension is a separate field with the same name.
+        query!(
+            "INSERT INTO blog_settings VALUES($1, $2, $3) ON CONFLICT (setting_name, site_id) DO UPDATE SET value = EXCLUDED.value",
+            SettingNames::MarkdownExtensions.to_string(),
+            extensions.join(","),
+            globals.site_id
+        )
+        .execute(&globals.connection_pool)
+        .await?;
+
         query!(
             "UPDATE sites SET editions_enabled=$1 WHERE id=$2",
             editions_enabled,
@@ -135,6 +176,9 @@ pub async fn render(request: &cgi::Request, globals: PageGlobals) -> anyhow::Res
     let page = Settings {
         common,
         settings: get_settings_struct(&globals.connection_pool, globals.site_id).await?,
+        code_themes: theme_names(),
+        extensions: MarkdownExtension::ALL,
+        citation_styles: CitationStyle::ALL,
     };
     Ok(cgi::html_response(200, page.render().unwrap()))
 }
@@ -15,12 +15,14 @@ pub enum AdminMenuPages {
     NewPost,
     Settings,
     Links,
+    References,
     Comments,
     Pages,
     Media,
     Fediverse,
     Tags,
     Templates,
+    Editions,
 }
 impl fmt::Display for AdminMenuPages {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -31,12 +33,14 @@ impl fmt::Display for AdminMenuPages {
             AdminMenuPages::NewPost => write!(f, "newpost"),
             AdminMenuPages::Settings => write!(f, "settings"),
             AdminMenuPages::Links => write!(f, "links"),
+            AdminMenuPages::References => write!(f, "references"),
             AdminMenuPages::Comments => write!(f, "comments"),
             AdminMenuPages::Pages => write!(f, "pages"),
             AdminMenuPages::Media => write!(f, "media"),
             AdminMenuPages::Fediverse => write!(f, "fediverse"),
             AdminMenuPages::Tags => write!(f, "tags"),
             AdminMenuPages::Templates => write!(f, "templates"),
+            AdminMenuPages::Editions => write!(f, "editions"),
         }
     }
 }
@@ -61,6 +65,17 @@ pub struct PostRequest {
     pub mood: Option<String>,
     pub summary: Option<String>,
     pub tags: Option<Vec<i32>>,
+    pub series: Option<String>,
+    pub new_series: Option<String>,
+    pub series_part: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct EditionRequest {
+    pub number: i32,
+    #[serde(deserialize_with = "deserialize_from_form_datetime")]
+    pub date: NaiveDateTime,
+    pub posts: Option<Vec<i32>>,
 }
 
 fn deserialize_from_form_datetime<'de, D>(deserializer: D) -> Result<NaiveDateTime, D::Error>
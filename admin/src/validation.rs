@@ -0,0 +1,75 @@
+use askama::Template;
+use shared::{
+    generator::{
+        get_common as get_site_data,
+        validation::{ContentKind, Diagnostic, validate_site},
+    },
+    utils::render_html,
+};
+
+use crate::{
+    common::{Common, get_common},
+    types::{AdminMenuPages, PageGlobals},
+    utils::link_to,
+};
+
+#[derive(Template)]
+#[template(path = "validation.html")]
+struct ValidationPage {
+    common: Common,
+    diagnostics: Vec<Diagnostic>,
+    regenerating: bool,
+    force: bool,
+}
+
+pub fn edit_link(diagnostic: &Diagnostic, common: &Common) -> String {
+    let action = match diagnostic.kind {
+        ContentKind::Post => "edit_post",
+        ContentKind::Page => "edit_page",
+    };
+    link_to(action, &[("id", diagnostic.id)], common)
+}
+
+async fn diagnostics(globals: &PageGlobals) -> anyhow::Result<Vec<Diagnostic>> {
+    let site = get_site_data(&globals.connection_pool, globals.site_id).await?;
+    validate_site(&globals.connection_pool, &site).await
+}
+
+async fn render_diagnostics(
+    globals: &PageGlobals,
+    diagnostics: Vec<Diagnostic>,
+    regenerating: bool,
+) -> anyhow::Result<cgi::Response> {
+    let common = get_common(globals, AdminMenuPages::Dashboard).await?;
+    render_html(ValidationPage {
+        common,
+        diagnostics,
+        regenerating,
+        force: globals.query.get("force").is_some_and(|f| f == "true"),
+    })
+}
+
+/// Lists every problem in the site's posts and pages.
+pub async fn validation(globals: PageGlobals) -> anyhow::Result<cgi::Response> {
+    let diagnostics = diagnostics(&globals).await?;
+    render_diagnostics(&globals, diagnostics, false).await
+}
+
+/// Checks the site before it's regenerated. If there are problems the page
+/// listing them is returned instead, unless they've already been seen and
+/// the author asked to regenerate anyway.
+pub async fn check_before_regenerating(
+    globals: &PageGlobals,
+) -> anyhow::Result<Option<cgi::Response>> {
+    if globals.query.get("anyway").is_some_and(|a| a == "true") {
+        return Ok(None);
+    }
+    let diagnostics = diagnostics(globals).await?;
+    if diagnostics.is_empty() {
+        Ok(None)
+    } else {
+        render_diagnostics(globals, diagnostics, true)
+            .await
+            .map(Some)
+    }
+}
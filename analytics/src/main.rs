@@ -105,7 +105,7 @@ ON CONFLICT (source_ip, date_time, path) DO NOTHING
     }
 }
 
-fn none_if_dash(input: Option<Match>) -> Option<&str> {
+fn none_if_dash(input: Option<Match<'_>>) -> Option<&str> {
     input.map(|m| m.as_str()).and_then(|s| match s {
         "-" => None,
         a => Some(a),
@@ -113,12 +113,14 @@ pub struct CreateRecord<'a, T> {
     pub record: T,
 }
 
+#[allow(dead_code)]
 #[derive(Debug, Deserialize)]
 pub struct CreateRecordOutput {
     pub cid: String,
     pub uri: String,
 }
 
+#[allow(dead_code)]
 #[derive(Debug, Deserialize)]
 struct ApiError {
     pub error: String,
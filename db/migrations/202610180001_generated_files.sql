@@ -0,0 +1,7 @@
+CREATE TABLE IF NOT EXISTS generated_files (
+	   site_id INT NOT NULL REFERENCES sites(id) ON DELETE CASCADE,
+	   path VARCHAR NOT NULL,
+	   input_hash VARCHAR(64) NOT NULL,
+	   generated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	   PRIMARY KEY(site_id, path)
+);
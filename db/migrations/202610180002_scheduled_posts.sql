@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS post_publications (
+	   post_id INT NOT NULL PRIMARY KEY REFERENCES posts(id) ON DELETE CASCADE,
+	   site_id INT NOT NULL REFERENCES sites(id) ON DELETE CASCADE,
+	   published_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+INSERT INTO post_publications(post_id, site_id)
+SELECT id, site_id FROM posts WHERE state = 'published' AND post_date <= CURRENT_TIMESTAMP
+ON CONFLICT DO NOTHING;
@@ -0,0 +1,53 @@
+ALTER TABLE posts ADD COLUMN IF NOT EXISTS search_document TSVECTOR;
+
+CREATE OR REPLACE FUNCTION posts_search_document() RETURNS TRIGGER AS $$
+BEGIN
+	NEW.search_document :=
+		setweight(to_tsvector('english', NEW.title), 'A') ||
+		setweight(to_tsvector('english', COALESCE((
+			SELECT string_agg(t.name, ' ')
+			FROM tags t
+			INNER JOIN post_tag pt ON pt.tag_id = t.id
+			WHERE pt.post_id = NEW.id
+		), '')), 'B') ||
+		setweight(to_tsvector('english', COALESCE(NEW.summary, '')), 'B') ||
+		setweight(to_tsvector('english', NEW.body), 'C');
+	RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE OR REPLACE TRIGGER posts_search_document_update
+BEFORE INSERT OR UPDATE OF title, summary, body ON posts
+FOR EACH ROW EXECUTE FUNCTION posts_search_document();
+
+-- Touching the title recalculates the document when a post's tags change.
+CREATE OR REPLACE FUNCTION post_tag_search_document() RETURNS TRIGGER AS $$
+BEGIN
+	IF TG_OP = 'DELETE' THEN
+		UPDATE posts SET title = title WHERE id = OLD.post_id;
+	ELSE
+		UPDATE posts SET title = title WHERE id = NEW.post_id;
+	END IF;
+	RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE OR REPLACE TRIGGER post_tag_search_document_update
+AFTER INSERT OR DELETE ON post_tag
+FOR EACH ROW EXECUTE FUNCTION post_tag_search_document();
+
+CREATE OR REPLACE FUNCTION tags_search_document() RETURNS TRIGGER AS $$
+BEGIN
+	UPDATE posts SET title = title
+	WHERE id IN (SELECT post_id FROM post_tag WHERE tag_id = NEW.id);
+	RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE OR REPLACE TRIGGER tags_search_document_update
+AFTER UPDATE OF name ON tags
+FOR EACH ROW EXECUTE FUNCTION tags_search_document();
+
+UPDATE posts SET title = title;
+
+CREATE INDEX IF NOT EXISTS posts_search_document_idx ON posts USING GIN (search_document);
@@ -0,0 +1,15 @@
+CREATE TABLE IF NOT EXISTS series (
+	   id INT PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+	   site_id INT NOT NULL REFERENCES sites(id) ON DELETE CASCADE,
+	   name TEXT NOT NULL,
+	   url_slug TEXT NOT NULL,
+	   UNIQUE(site_id, url_slug)
+);
+
+CREATE TABLE IF NOT EXISTS series_posts (
+	   post_id INT NOT NULL PRIMARY KEY REFERENCES posts(id) ON DELETE CASCADE,
+	   series_id INT NOT NULL REFERENCES series(id) ON DELETE CASCADE,
+	   position INT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS series_posts_series_id ON series_posts(series_id, position);
@@ -0,0 +1,7 @@
+CREATE TABLE IF NOT EXISTS reference_library (
+	   id INT PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+	   site_id INT NOT NULL REFERENCES sites(id) ON DELETE CASCADE,
+	   name TEXT NOT NULL,
+	   reference JSONB NOT NULL,
+	   UNIQUE(site_id, name)
+);
@@ -0,0 +1,19 @@
+-- The key comment form tokens are signed with. There's only ever one row.
+CREATE TABLE IF NOT EXISTS comment_form_secret (
+	   id INT PRIMARY KEY DEFAULT 1 CHECK (id = 1),
+	   secret TEXT NOT NULL
+);
+
+INSERT INTO comment_form_secret(secret)
+VALUES (replace(gen_random_uuid()::text || gen_random_uuid()::text, '-', ''))
+ON CONFLICT DO NOTHING;
+
+-- Recent comment submissions, for rate limiting by address and email.
+CREATE TABLE IF NOT EXISTS comment_attempts (
+	   id BIGINT PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+	   attempted_date TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	   ip_address TEXT NOT NULL,
+	   author_email TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS comment_attempts_date ON comment_attempts(attempted_date);
@@ -0,0 +1,11 @@
+-- Comment form tokens that have been used, so each form can only be sent
+-- once. They're only needed for as long as a token lasts.
+CREATE TABLE IF NOT EXISTS comment_token_uses (
+	   nonce TEXT PRIMARY KEY,
+	   used_date TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS comment_token_uses_date ON comment_token_uses(used_date);
+
+-- Requests without an address are only limited by email.
+ALTER TABLE comment_attempts ALTER COLUMN ip_address DROP NOT NULL;
@@ -0,0 +1,376 @@
+use std::collections::HashMap;
+
+use anyhow::anyhow;
+use askama::Template;
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use shared::{database, utils};
+use sqlx::{PgPool, query};
+use uuid::Uuid;
+
+/// How long a form can be left open before it has to be reloaded.
+const TOKEN_LIFETIME_SECONDS: i64 = 24 * 60 * 60;
+/// Nobody writes a comment faster than this, but bots do.
+const MINIMUM_FILL_SECONDS: i64 = 3;
+/// Submissions allowed in an hour from one address, and for one email.
+const SUBMISSIONS_PER_IP: i64 = 5;
+const SUBMISSIONS_PER_EMAIL: i64 = 3;
+
+// The sizes of the columns in the comments table.
+const MAX_NAME_LENGTH: usize = 200;
+const MAX_EMAIL_LENGTH: usize = 400;
+const MAX_COMMENT_LENGTH: usize = 20000;
+
+#[derive(Template)]
+#[template(path = "generated/comment_form.html")]
+struct CommentForm {
+    token: String,
+    post_id: i32,
+    comment_cgi_url: String,
+    static_base_url: String,
+}
+
+#[derive(Template)]
+#[template(path = "generated/comment_posted.html")]
+struct CommentPosted {
+    static_base_url: String,
+}
+
+#[derive(Template)]
+#[template(path = "generated/comment_rejected.html")]
+struct CommentRejected {
+    message: &'static str,
+    form_url: Option<String>,
+    static_base_url: String,
+}
+
+#[derive(serde::Deserialize)]
+struct NewComment {
+    post_id: i32,
+    token: String,
+    name: String,
+    email: String,
+    comment: String,
+    /// Hidden from people, so anything in it was filled in by a bot.
+    #[serde(default)]
+    website: String,
+}
+
+/// Why a comment wasn't accepted.
+#[derive(Debug, PartialEq)]
+enum Rejection {
+    Unreadable,
+    BadToken,
+    Expired,
+    TooFast,
+    Honeypot,
+    Incomplete,
+    TooMany,
+    AlreadySent,
+}
+
+impl Rejection {
+    fn status(&self) -> u16 {
+        match self {
+            Rejection::TooMany => 429,
+            _ => 400,
+        }
+    }
+
+    fn message(&self) -> &'static str {
+        match self {
+            Rejection::Unreadable | Rejection::BadToken => {
+                "The comment form couldn't be read. Please reload it and try again."
+            }
+            Rejection::Expired => {
+                "The comment form was open for too long. Please reload it and send your comment again."
+            }
+            Rejection::TooFast | Rejection::Honeypot => {
+                "Your comment looks like it was sent automatically, so it wasn't saved."
+            }
+            Rejection::Incomplete => {
+                "A name, an email address and a comment are all needed, and the comment can be at most 20,000 characters."
+            }
+            Rejection::TooMany => {
+                "Too many comments have been sent recently. Please wait an hour and try again."
+            }
+            Rejection::AlreadySent => {
+                "This comment form has already been sent. Please reload it to write another comment."
+            }
+        }
+    }
+}
+
+fn signature(secret: &str, post_id: i32, issued: i64, nonce: &str) -> Hmac<Sha256> {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC can take a key of any size");
+    mac.update(format!("{}.{}.{}", post_id, issued, nonce).as_bytes());
+    mac
+}
+
+/// A token for the form for one post, as `post.issued.nonce.signature`. The
+/// nonce is recorded when a comment is accepted so the form can't be sent
+/// again.
+fn sign_token(secret: &str, post_id: i32, issued: i64, nonce: &str) -> String {
+    let signature = signature(secret, post_id, issued, nonce)
+        .finalize()
+        .into_bytes();
+    format!(
+        "{}.{}.{}.{}",
+        post_id,
+        issued,
+        nonce,
+        hex::encode(signature)
+    )
+}
+
+/// Checks the token was made here for this post, isn't too old and that the
+/// form wasn't sent back too quickly, returning its nonce.
+fn check_token<'a>(
+    secret: &str,
+    token: &'a str,
+    post_id: i32,
+    now: i64,
+) -> Result<&'a str, Rejection> {
+    let mut parts = token.splitn(4, '.');
+    let (Some(token_post), Some(issued), Some(nonce), Some(signed)) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return Err(Rejection::BadToken);
+    };
+    let issued: i64 = issued.parse().map_err(|_| Rejection::BadToken)?;
+    let signed = hex::decode(signed).map_err(|_| Rejection::BadToken)?;
+    if token_post != post_id.to_string() || nonce.is_empty() {
+        return Err(Rejection::BadToken);
+    }
+    signature(secret, post_id, issued, nonce)
+        .verify_slice(&signed)
+        .map_err(|_| Rejection::BadToken)?;
+
+    match now - issued {
+        age if age < 0 => Err(Rejection::BadToken),
+        age if age > TOKEN_LIFETIME_SECONDS => Err(Rejection::Expired),
+        age if age < MINIMUM_FILL_SECONDS => Err(Rejection::TooFast),
+        _ => Ok(nonce),
+    }
+}
+
+async fn token_secret(conn: &PgPool) -> anyhow::Result<String> {
+    let row = query!("SELECT secret FROM comment_form_secret")
+        .fetch_one(conn)
+        .await?;
+    Ok(row.secret)
+}
+
+async fn static_base_url(conn: &PgPool) -> anyhow::Result<String> {
+    let row = query!("SELECT value FROM blog_settings WHERE setting_name='static_base_url'")
+        .fetch_one(conn)
+        .await?;
+    Ok(row.value)
+}
+
+pub async fn comment_form(query: HashMap<String, String>) -> anyhow::Result<cgi::Response> {
+    let post_id_str = query.get("post_id").ok_or(anyhow!("No post id"))?;
+    let post_id: i32 = post_id_str.parse()?;
+    let conn = database::connect_db().await?;
+    let settings_data = query!("SELECT setting_name, value FROM blog_settings")
+        .fetch_all(&conn)
+        .await?;
+    let settings: HashMap<String, String> =
+        HashMap::from_iter(settings_data.into_iter().map(|r| (r.setting_name, r.value)));
+
+    query!("SELECT id FROM posts WHERE id=$1", post_id)
+        .fetch_one(&conn)
+        .await?;
+
+    let secret = token_secret(&conn).await?;
+    utils::render_html(CommentForm {
+        token: sign_token(
+            &secret,
+            post_id,
+            Utc::now().timestamp(),
+            &Uuid::new_v4().simple().to_string(),
+        ),
+        post_id,
+        comment_cgi_url: settings.get("comment_cgi_url").unwrap().to_owned(),
+        static_base_url: settings.get("static_base_url").unwrap().to_owned(),
+    })
+}
+
+/// Records the token's nonce as used, returning false if it already was.
+/// Nonces are kept only until their tokens would have expired anyway.
+async fn use_nonce(conn: &PgPool, nonce: &str) -> anyhow::Result<bool> {
+    query!(
+        "DELETE FROM comment_token_uses WHERE used_date < CURRENT_TIMESTAMP - make_interval(secs => $1)",
+        TOKEN_LIFETIME_SECONDS as f64
+    )
+    .execute(conn)
+    .await?;
+    let inserted = query!(
+        "INSERT INTO comment_token_uses(nonce) VALUES($1) ON CONFLICT DO NOTHING",
+        nonce
+    )
+    .execute(conn)
+    .await?;
+    Ok(inserted.rows_affected() == 1)
+}
+
+/// Records this submission and checks neither its address nor its email has
+/// sent too many in the last hour. Older attempts are cleared out as we go.
+/// Attempts without an address are stored without one, so they're only
+/// limited by email rather than all sharing one bucket.
+async fn check_rate_limit(
+    conn: &PgPool,
+    ip_address: Option<&str>,
+    email: &str,
+) -> anyhow::Result<bool> {
+    query!(
+        "DELETE FROM comment_attempts WHERE attempted_date < CURRENT_TIMESTAMP - INTERVAL '1 hour'"
+    )
+    .execute(conn)
+    .await?;
+    query!(
+        "INSERT INTO comment_attempts(ip_address, author_email) VALUES($1, $2)",
+        ip_address,
+        email
+    )
+    .execute(conn)
+    .await?;
+    let counts = query!(
+        r#"
+SELECT COUNT(*) FILTER (WHERE ip_address=$1) AS "by_ip!", COUNT(*) FILTER (WHERE author_email=$2) AS "by_email!"
+FROM comment_attempts
+"#,
+        ip_address,
+        email
+    )
+    .fetch_one(conn)
+    .await?;
+    Ok(counts.by_ip <= SUBMISSIONS_PER_IP && counts.by_email <= SUBMISSIONS_PER_EMAIL)
+}
+
+async fn accept_comment(
+    conn: &PgPool,
+    request: &cgi::Request,
+) -> anyhow::Result<Result<(), (Rejection, Option<i32>)>> {
+    let body: NewComment = match utils::post_body(request) {
+        Ok(body) => body,
+        Err(_) => return Ok(Err((Rejection::Unreadable, None))),
+    };
+    let reject = |rejection| Ok(Err((rejection, Some(body.post_id))));
+
+    let secret = token_secret(conn).await?;
+    let nonce = match check_token(&secret, &body.token, body.post_id, Utc::now().timestamp()) {
+        Ok(nonce) => nonce,
+        Err(rejection) => return reject(rejection),
+    };
+    if !body.website.is_empty() {
+        return reject(Rejection::Honeypot);
+    }
+
+    let name = body.name.trim();
+    let email = body.email.trim().to_lowercase();
+    let comment = body.comment.trim();
+    if name.is_empty()
+        || !email.contains('@')
+        || comment.is_empty()
+        || name.chars().count() > MAX_NAME_LENGTH
+        || email.chars().count() > MAX_EMAIL_LENGTH
+        || comment.chars().count() > MAX_COMMENT_LENGTH
+    {
+        return reject(Rejection::Incomplete);
+    }
+
+    let ip_address = request
+        .headers()
+        .get("x-cgi-remote-addr")
+        .and_then(|a| a.to_str().ok())
+        .filter(|a| !a.is_empty());
+    if !check_rate_limit(conn, ip_address, &email).await? {
+        return reject(Rejection::TooMany);
+    }
+    // Only claimed once everything else is fine, so someone can go back and
+    // fix a rejected comment without reloading the form.
+    if !use_nonce(conn, nonce).await? {
+        return reject(Rejection::AlreadySent);
+    }
+
+    query!(
+        "
+INSERT INTO comments (post_id, created_date, author_name, author_email, post_body)
+VALUES($1, CURRENT_TIMESTAMP, $2, $3, $4)
+",
+        body.post_id,
+        name,
+        email,
+        comment
+    )
+    .execute(conn)
+    .await?;
+    Ok(Ok(()))
+}
+
+pub async fn post_comment(request: &cgi::Request) -> anyhow::Result<cgi::Response> {
+    let conn = database::connect_db().await?;
+    let outcome = accept_comment(&conn, request).await?;
+    let static_base_url = static_base_url(&conn).await?;
+
+    match outcome {
+        Ok(()) => utils::render_html(CommentPosted { static_base_url }),
+        Err((rejection, post_id)) => {
+            let comment_cgi_url =
+                query!("SELECT value FROM blog_settings WHERE setting_name='comment_cgi_url'")
+                    .fetch_one(&conn)
+                    .await?
+                    .value;
+            utils::render_html_status(
+                rejection.status(),
+                CommentRejected {
+                    message: rejection.message(),
+                    form_url: post_id.map(|id| {
+                        format!("{}?action=comment_form&post_id={}", comment_cgi_url, id)
+                    }),
+                    static_base_url,
+                },
+            )
+        }
+    }
+}
+
+#[test]
+fn test_comment_tokens() {
+    let secret = "secret";
+    let token = sign_token(secret, 12, 1000, "abc");
+
+    assert_eq!(check_token(secret, &token, 12, 1010), Ok("abc"));
+    assert_eq!(
+        check_token(secret, &token, 12, 1001),
+        Err(Rejection::TooFast)
+    );
+    assert_eq!(
+        check_token(secret, &token, 12, 1000 + TOKEN_LIFETIME_SECONDS + 1),
+        Err(Rejection::Expired)
+    );
+    assert_eq!(
+        check_token(secret, &token, 13, 1010),
+        Err(Rejection::BadToken)
+    );
+    assert_eq!(
+        check_token("other", &token, 12, 1010),
+        Err(Rejection::BadToken)
+    );
+    assert_eq!(
+        check_token(secret, &token.replacen("1000", "1005", 1), 12, 1010),
+        Err(Rejection::BadToken)
+    );
+    assert_eq!(
+        check_token(secret, &token.replacen("abc", "abd", 1), 12, 1010),
+        Err(Rejection::BadToken)
+    );
+    assert_eq!(
+        check_token(secret, &sign_token(secret, 12, 1000, ""), 12, 1010),
+        Err(Rejection::BadToken)
+    );
+    assert_eq!(check_token(secret, "", 12, 1010), Err(Rejection::BadToken));
+}
@@ -0,0 +1,149 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use shared::{
+    database,
+    generator::{get_common, types::Generator},
+    utils::{self, blog_post_url},
+};
+use sqlx::query;
+use tera::{Context, escape_html};
+
+use crate::{Http400, Http404};
+
+const RESULTS_PER_PAGE: i64 = 10;
+
+// ts_headline marks matches with these, and they're swapped for <mark> once
+// the snippet has been escaped. The body has images, links and HTML tags
+// stripped first so snippets read as plain text.
+const MATCH_START: &str = "\u{E000}";
+const MATCH_END: &str = "\u{E001}";
+
+#[derive(Serialize)]
+struct SearchResult {
+    title: String,
+    url: String,
+    post_date: DateTime<Utc>,
+    summary: Option<String>,
+    snippet: String,
+}
+
+#[derive(Serialize)]
+struct SearchPage<'a> {
+    title: String,
+    common: &'a shared::types::CommonData,
+    query: &'a str,
+    results: Vec<SearchResult>,
+    total: i64,
+    page: i64,
+    total_pages: i64,
+    search_url: String,
+}
+
+pub async fn search(query_string: HashMap<String, String>) -> anyhow::Result<cgi::Response> {
+    // Each site's search form says which site it is, so without one there's
+    // nothing sensible to search.
+    let Some(site_id) = query_string.get("site").and_then(|s| s.parse::<i32>().ok()) else {
+        return utils::render_html_status(400, Http400 {});
+    };
+    let search_text = query_string.get("q").map(|q| q.trim()).unwrap_or_default();
+    let page: i64 = query_string
+        .get("page")
+        .and_then(|p| p.parse().ok())
+        .filter(|p| *p > 0)
+        .unwrap_or(1);
+
+    let conn = database::connect_db().await?;
+    if query!("SELECT id FROM sites WHERE id=$1", site_id)
+        .fetch_optional(&conn)
+        .await?
+        .is_none()
+    {
+        return utils::render_html_status(404, Http404 {});
+    }
+    let common = get_common(&conn, site_id).await?;
+
+    let rows = if search_text.is_empty() {
+        Vec::new()
+    } else {
+        query!(
+            r#"
+SELECT
+	p.title,
+	p.url_slug,
+	p.post_date,
+	p.summary,
+	ts_headline('english', regexp_replace(regexp_replace(regexp_replace(p.body,
+		'!\[[^\]]*\]\([^)]*\)|<[^>]+>', ' ', 'g'),
+		'\[([^\]]*)\]\([^)]*\)', '\1', 'g'),
+		'[*_#`>]+', '', 'g'), q, $5) AS "snippet!",
+	COUNT(*) OVER() AS "total!"
+FROM posts p, websearch_to_tsquery('english', $2) q
+WHERE p.site_id = $1
+AND p.state = 'published'
+AND p.post_date <= CURRENT_TIMESTAMP
+AND p.search_document @@ q
+ORDER BY ts_rank(p.search_document, q) DESC, p.post_date DESC
+LIMIT $3 OFFSET $4
+"#,
+            site_id,
+            search_text,
+            RESULTS_PER_PAGE,
+            (page - 1) * RESULTS_PER_PAGE,
+            format!(
+                "StartSel={}, StopSel={}, MaxFragments=2, MaxWords=30, MinWords=10",
+                MATCH_START, MATCH_END
+            )
+        )
+        .fetch_all(&conn)
+        .await?
+    };
+
+    let total = rows.first().map(|r| r.total).unwrap_or(0);
+    let results = rows
+        .into_iter()
+        .map(|r| {
+            Ok(SearchResult {
+                url: blog_post_url(
+                    r.url_slug,
+                    r.post_date,
+                    common.timezone,
+                    common.base_url.clone(),
+                )?,
+                title: r.title,
+                post_date: r.post_date,
+                summary: r.summary,
+                snippet: escape_html(&r.snippet)
+                    .replace(MATCH_START, "<mark>")
+                    .replace(MATCH_END, "</mark>"),
+            })
+        })
+        .collect::<anyhow::Result<Vec<SearchResult>>>()?;
+
+    let generator = Generator::new("", &conn, &common, site_id).await?;
+    let search_page = SearchPage {
+        title: if search_text.is_empty() {
+            "Search".into()
+        } else {
+            format!("Search results for {}", search_text)
+        },
+        common: &common,
+        query: search_text,
+        results,
+        total,
+        page,
+        total_pages: (total + RESULTS_PER_PAGE - 1) / RESULTS_PER_PAGE,
+        search_url: format!(
+            "{}?action=search&amp;site={}&amp;q={}",
+            common.comment_cgi_url,
+            site_id,
+            url::form_urlencoded::byte_serialize(search_text.as_bytes()).collect::<String>()
+        ),
+    };
+
+    let html = generator
+        .tera
+        .render("search.html", &Context::from_serialize(search_page)?)?;
+    Ok(cgi::html_response(200, html))
+}
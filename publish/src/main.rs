@@ -0,0 +1,168 @@
+use std::{env, process::ExitCode};
+
+use anyhow::{anyhow, bail};
+use atproto::send_posts_for_site;
+use shared::{
+    database::connect_db,
+    generator::{
+        activitypub::publish_posts, get_common, regenerate_site, rollback_site,
+        validation::validate_site,
+    },
+};
+use sqlx::{PgPool, query};
+
+mod media;
+mod scheduled;
+
+const USAGE: &str = "Usage: publish [--site <id>] [--force] [--scheduled | --rollback]
+
+Regenerates the blog and sends new posts to the fediverse and Bluesky.
+Without --site every site is published. --force rewrites every file
+instead of only the ones whose inputs changed. --scheduled only publishes
+sites with scheduled posts whose time has come or newly uploaded images
+to resize, and is meant to be run from cron every few minutes. --rollback
+puts the previous generation of the site back live instead of publishing.
+
+Reads BLOG_CONNECTION_STRING and BLOG_OUTPUT_PATH from the environment.";
+
+struct Options {
+    site_id: Option<i32>,
+    force: bool,
+    scheduled: bool,
+    rollback: bool,
+    help: bool,
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let options = match parse_args(env::args().skip(1)) {
+        Ok(o) => o,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            return ExitCode::from(2);
+        }
+    };
+
+    if options.help {
+        println!("{}", USAGE);
+        return ExitCode::SUCCESS;
+    }
+
+    match run(options).await {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::FAILURE,
+        Err(e) => {
+            eprintln!("Error: {:#}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> anyhow::Result<Options> {
+    let mut options = Options {
+        site_id: None,
+        force: false,
+        scheduled: false,
+        rollback: false,
+        help: false,
+    };
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--site" => {
+                let id = args.next().ok_or(anyhow!("--site needs a site id"))?;
+                options.site_id = Some(id.parse().map_err(|_| anyhow!("Invalid site id {}", id))?);
+            }
+            "--force" => options.force = true,
+            "--scheduled" => options.scheduled = true,
+            "--rollback" => options.rollback = true,
+            "--help" | "-h" => options.help = true,
+            _ => bail!("Unknown argument {}", arg),
+        }
+    }
+    if options.scheduled && options.rollback {
+        bail!("--scheduled and --rollback can't be used together");
+    }
+    Ok(options)
+}
+
+/// Publishes each requested site, carrying on past failures so one broken
+/// site doesn't hold up the rest. Returns whether every site succeeded.
+async fn run(options: Options) -> anyhow::Result<bool> {
+    let output_path_base =
+        env::var("BLOG_OUTPUT_PATH").map_err(|_| anyhow!("BLOG_OUTPUT_PATH must be set"))?;
+    if env::var("BLOG_CONNECTION_STRING").is_err() {
+        bail!("BLOG_CONNECTION_STRING must be set");
+    }
+    let connection = connect_db().await?;
+
+    let site_ids = match options.site_id {
+        Some(id) => {
+            let site = query!("SELECT id FROM sites WHERE id=$1", id)
+                .fetch_optional(&connection)
+                .await?;
+            vec![site.ok_or(anyhow!("No site with id {}", id))?.id]
+        }
+        None => query!("SELECT id FROM sites ORDER BY id")
+            .fetch_all(&connection)
+            .await?
+            .into_iter()
+            .map(|r| r.id)
+            .collect(),
+    };
+
+    let mut succeeded = true;
+    for site_id in site_ids {
+        if let Err(e) = publish_site(&connection, site_id, &output_path_base, &options).await {
+            eprintln!("Site {}: failed: {:#}", site_id, e);
+            succeeded = false;
+        }
+    }
+    Ok(succeeded)
+}
+
+async fn publish_site(
+    connection: &PgPool,
+    site_id: i32,
+    output_path_base: &str,
+    options: &Options,
+) -> anyhow::Result<()> {
+    if options.rollback {
+        let generation = rollback_site(connection, site_id, output_path_base).await?;
+        println!("Site {}: rolled back to generation {}", site_id, generation);
+        return Ok(());
+    }
+
+    let resized = media::generate_variants(connection, site_id).await?;
+    if resized > 0 {
+        println!("Site {}: {} images resized", site_id, resized);
+    }
+
+    let due = scheduled::due_posts(connection, site_id).await?;
+    if options.scheduled {
+        if due.is_empty() && resized == 0 {
+            return Ok(());
+        }
+        println!("Site {}: {} scheduled posts due", site_id, due.len());
+    }
+
+    // Problems don't stop publishing, broken content renders as best it can.
+    let common = get_common(connection, site_id).await?;
+    for diagnostic in validate_site(connection, &common).await? {
+        eprintln!("Site {}: warning: {}", site_id, diagnostic);
+    }
+
+    let summary = regenerate_site(connection, site_id, output_path_base, options.force).await?;
+    println!(
+        "Site {}: {} files written, {} unchanged, {} removed",
+        site_id, summary.written, summary.skipped, summary.removed
+    );
+
+    let queued = publish_posts(connection, site_id, true).await?;
+    println!("Site {}: {} posts queued for ActivityPub", site_id, queued);
+
+    let sent = send_posts_for_site(connection, site_id).await?;
+    println!("Site {}: {} posts sent to Bluesky", site_id, sent);
+
+    scheduled::mark_published(connection, site_id, &due).await?;
+    Ok(())
+}
@@ -0,0 +1,162 @@
+use std::{
+    fs::{self, File},
+    io::BufWriter,
+};
+
+use anyhow::anyhow;
+use image::{DynamicImage, ImageReader, codecs::avif::AvifEncoder, imageops::FilterType};
+use shared::{
+    settings::get_settings_struct,
+    types::{ImageMetadata, ImageVariant},
+};
+use sqlx::{PgPool, query, types::Json};
+
+/// rav1e's fastest setting. Slower ones shrink files a little further but
+/// take many times as long for every width.
+const AVIF_SPEED: u8 = 10;
+const AVIF_QUALITY: u8 = 70;
+/// Lossy, as lossless WebP copies of photos come out larger than the JPEGs
+/// they were made from.
+const WEBP_QUALITY: f32 = 75.0;
+
+/// Makes the resized copies of images uploaded since the last run, and of
+/// older ones made before there were WebP copies. This is done here rather
+/// than during the upload as encoding AVIF takes seconds a width, far longer
+/// than a CGI request should. An image that can't be read is reported and
+/// tried again next time.
+pub async fn generate_variants(connection: &PgPool, site_id: i32) -> anyhow::Result<usize> {
+    let pending = query!(
+        r#"
+SELECT id, metadata AS "metadata!: Json<ImageMetadata>"
+FROM media
+WHERE site_id=$1
+AND metadata IS NOT NULL
+AND metadata->>'content_type' <> 'image/gif'
+AND NOT COALESCE(metadata->'variants' @> '[{"content_type": "image/webp"}]', false)
+ORDER BY id
+"#,
+        site_id
+    )
+    .fetch_all(connection)
+    .await?;
+    if pending.is_empty() {
+        return Ok(0);
+    }
+
+    let settings = get_settings_struct(connection, site_id).await?;
+    let mut media_path = settings.media_path.to_owned();
+    if !media_path.ends_with("/") {
+        media_path = format!("{}/", media_path);
+    }
+
+    let mut generated = 0;
+    for media in pending {
+        let mut metadata = media.metadata.0;
+        let variants = ImageReader::open(format!("{}{}", media_path, metadata.fullsize_name))
+            .map_err(anyhow::Error::from)
+            .and_then(|reader| Ok(reader.with_guessed_format()?.decode()?))
+            .and_then(|image| {
+                save_variants(
+                    &image,
+                    media.id,
+                    &metadata,
+                    &settings.image_widths,
+                    &media_path,
+                )
+            });
+        match variants {
+            Ok(variants) => metadata.variants = variants,
+            Err(e) => {
+                eprintln!(
+                    "Site {}: warning: could not resize media {}: {:#}",
+                    site_id, media.id, e
+                );
+                continue;
+            }
+        }
+
+        query!(
+            "UPDATE media SET metadata=$1 WHERE id=$2 AND site_id=$3",
+            Json(metadata) as _,
+            media.id,
+            site_id
+        )
+        .execute(connection)
+        .await?;
+        generated += 1;
+    }
+    Ok(generated)
+}
+
+/// Saves a copy of the image at each configured width narrower than the
+/// original, in its own format as well as WebP and AVIF. The modern formats
+/// also get a full width copy since the original only exists in its upload
+/// format.
+fn save_variants(
+    image: &DynamicImage,
+    id: i32,
+    metadata: &ImageMetadata,
+    widths: &[u32],
+    media_path: &str,
+) -> anyhow::Result<Vec<ImageVariant>> {
+    let ext = metadata
+        .fullsize_name
+        .rsplit_once('.')
+        .map(|(_, ext)| ext)
+        .unwrap_or("jpg");
+    let mut variants = Vec::new();
+    let mut widths: Vec<u32> = widths
+        .iter()
+        .copied()
+        .filter(|w| *w < image.width())
+        .collect();
+    widths.sort();
+    widths.dedup();
+
+    for width in widths.iter().copied().chain([image.width()]) {
+        let resized = if width == image.width() {
+            image.clone()
+        } else {
+            image.resize(width, u32::MAX, FilterType::Lanczos3)
+        };
+
+        if width != image.width() {
+            let file_name = format!("{}_{}.{}", id, width, ext);
+            resized.save(format!("{}{}", media_path, file_name))?;
+            variants.push(ImageVariant {
+                width: resized.width(),
+                height: resized.height(),
+                content_type: metadata.content_type.clone(),
+                file_name,
+            });
+        }
+
+        let file_name = format!("{}_{}.webp", id, width);
+        let rgba = DynamicImage::ImageRgba8(resized.to_rgba8());
+        let webp = webp::Encoder::from_image(&rgba)
+            .map_err(|e| anyhow!("Could not encode WebP: {}", e))?
+            .encode(WEBP_QUALITY);
+        fs::write(format!("{}{}", media_path, file_name), &*webp)?;
+        variants.push(ImageVariant {
+            width: resized.width(),
+            height: resized.height(),
+            content_type: "image/webp".into(),
+            file_name,
+        });
+
+        let file_name = format!("{}_{}.avif", id, width);
+        let file = BufWriter::new(File::create(format!("{}{}", media_path, file_name))?);
+        rgba.write_with_encoder(AvifEncoder::new_with_speed_quality(
+            file,
+            AVIF_SPEED,
+            AVIF_QUALITY,
+        ))?;
+        variants.push(ImageVariant {
+            width: resized.width(),
+            height: resized.height(),
+            content_type: "image/avif".into(),
+            file_name,
+        });
+    }
+    Ok(variants)
+}
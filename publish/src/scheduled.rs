@@ -0,0 +1,43 @@
+use sqlx::{PgPool, query};
+
+/// Published posts whose date has passed but haven't been put live by the
+/// scheduler yet.
+pub async fn due_posts(connection: &PgPool, site_id: i32) -> anyhow::Result<Vec<i32>> {
+    let due = query!(
+        r#"
+SELECT p.id
+FROM posts p
+WHERE p.site_id = $1
+AND p.state = 'published'
+AND p.post_date <= CURRENT_TIMESTAMP
+AND NOT EXISTS (SELECT 1 FROM post_publications pp WHERE pp.post_id = p.id)
+ORDER BY p.post_date
+"#,
+        site_id
+    )
+    .fetch_all(connection)
+    .await?;
+
+    Ok(due.into_iter().map(|r| r.id).collect())
+}
+
+/// Records that the posts have been regenerated and announced, so the next
+/// run doesn't pick them up again.
+pub async fn mark_published(
+    connection: &PgPool,
+    site_id: i32,
+    post_ids: &[i32],
+) -> anyhow::Result<()> {
+    query!(
+        r#"
+INSERT INTO post_publications(post_id, site_id)
+SELECT id, $1 FROM UNNEST($2::int[]) AS id
+ON CONFLICT DO NOTHING
+"#,
+        site_id,
+        post_ids
+    )
+    .execute(connection)
+    .await?;
+    Ok(())
+}
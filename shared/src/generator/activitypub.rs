@@ -1,3 +1,92 @@
+use sqlx::{PgPool, query, types::Json};
+use tera::escape_html;
+
+use crate::{
+    activities::{self, Activity},
+    settings::get_settings_struct,
+    utils::blog_post_url,
+};
+
+use super::series::series_context;
+
 pub fn regenerate_activitypub() -> anyhow::Result<()> {
     Ok(())
 }
+
+/// Queues a `Create` activity for every published post that hasn't been
+/// sent yet. Scheduled posts wait until their date has passed. With `push`
+/// set each one is addressed to all followers. Returns the number of posts
+/// queued.
+pub async fn publish_posts(connection: &PgPool, site_id: i32, push: bool) -> anyhow::Result<usize> {
+    let follower_rows =
+        query!("SELECT actor FROM activitypub_known_actors aka WHERE EXISTS (SELECT 1 FROM activitypub_followers af WHERE af.actor_id = aka.id AND af.site_id=$1)", site_id)
+            .fetch_all(connection)
+            .await?;
+    let followers: Vec<String> = follower_rows
+        .into_iter()
+        .map(|r| r.actor.unwrap())
+        .collect();
+
+    let to_post = query!("SELECT id, title, summary, url_slug, post_date FROM posts p WHERE p.site_id = $1 AND NOT EXISTS (SELECT 1 FROM activitypub_outbox o WHERE o.source_post = p.id) AND p.state = 'published' AND p.post_date <= CURRENT_TIMESTAMP", site_id)
+        .fetch_all(connection)
+        .await?;
+
+    let settings = get_settings_struct(connection, site_id).await?;
+    let queued = to_post.len();
+
+    for post in to_post {
+        let post_url = blog_post_url(
+            post.url_slug,
+            post.post_date,
+            settings.timezone,
+            settings.base_url.clone(),
+        )?;
+
+        let summary = post.summary.unwrap_or("New post!".into());
+        let mut content = format!(
+            r#"<p>{}</p><p><a href="{}">{}</a></p>"#,
+            summary, post_url, post.title
+        );
+        if let Some(series) =
+            series_context(connection, post.id, settings.timezone, &settings.base_url).await?
+        {
+            content.push_str(&format!(
+                r#"<p>Part {} of {} in <a href="{}">{}</a></p>"#,
+                series.part,
+                series.total,
+                series.url,
+                escape_html(&series.name)
+            ));
+        }
+        let note = Activity::note(
+            content,
+            post_url.clone(),
+            post.post_date,
+            vec![activities::PUBLIC_TIMELINE.into()],
+            vec![],
+        );
+        let create = Activity::create(
+            settings.activitypub_actor_uri(),
+            note,
+            vec![activities::PUBLIC_TIMELINE.into()],
+            vec![],
+        );
+
+        let inserted = query!(
+            "INSERT INTO activitypub_outbox(activity_id, activity, source_post, site_id) VALUES($1, $2, $3, $4) RETURNING id",
+            post_url,
+            Json(create) as _,
+            post.id,
+            site_id
+        )
+        .fetch_one(connection)
+        .await?;
+        if push {
+            for f in &followers {
+                query!("INSERT INTO activitypub_outbox_target(activitypub_outbox_id, target) VALUES ($1, $2)", inserted.id, f).execute(connection).await?;
+            }
+        }
+    }
+
+    Ok(queued)
+}
@@ -0,0 +1,411 @@
+use tera::escape_html;
+
+use super::shortcodes::parse_arguments;
+
+/// What separates the cells of a fenced code block that holds data to be
+/// shown as a table, or `None` for any other block.
+pub fn data_delimiter(info: &str) -> Option<char> {
+    match info.split_whitespace().next() {
+        Some("csv") => Some(','),
+        Some("tsv") => Some('\t'),
+        _ => None,
+    }
+}
+
+/// A problem with a data table, on a line counted from the opening fence so
+/// the first row of data is line 1.
+type DataProblem = (usize, String);
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Align {
+    Left,
+    Centre,
+    Right,
+}
+
+impl Align {
+    fn class(&self) -> &'static str {
+        match self {
+            Align::Left => "align-left",
+            Align::Centre => "align-centre",
+            Align::Right => "align-right",
+        }
+    }
+}
+
+/// How a table is shown, from the rest of the info string, like
+/// ` ```csv header caption="Rainfall" align=lrr decimals=1 separators `.
+#[derive(Debug, Default)]
+struct TableOptions {
+    /// Whether the first row names the columns, or `None` to guess.
+    header: Option<bool>,
+    align: Vec<Align>,
+    caption: Option<String>,
+    decimals: Option<usize>,
+    separators: bool,
+    row_headers: bool,
+}
+
+impl TableOptions {
+    fn parse(info: &str, problems: &mut Vec<DataProblem>) -> TableOptions {
+        let (flags, named) = parse_arguments(info);
+        let mut options = TableOptions::default();
+        for flag in flags.iter().skip(1) {
+            match flag.as_str() {
+                "header" => options.header = Some(true),
+                "no-header" => options.header = Some(false),
+                "separators" => options.separators = true,
+                "row-headers" => options.row_headers = true,
+                _ => problems.push((0, format!("Unknown table option `{}`", flag))),
+            }
+        }
+
+        let mut named: Vec<(String, String)> = named.into_iter().collect();
+        named.sort();
+        for (name, value) in named {
+            match name.as_str() {
+                "caption" => options.caption = Some(value),
+                "align" => {
+                    for c in value.chars() {
+                        match c {
+                            'l' => options.align.push(Align::Left),
+                            'c' => options.align.push(Align::Centre),
+                            'r' => options.align.push(Align::Right),
+                            _ => problems
+                                .push((0, format!("`{}` isn't an alignment, use l, c or r", c))),
+                        }
+                    }
+                }
+                "decimals" => match value.parse() {
+                    Ok(decimals) => options.decimals = Some(decimals),
+                    Err(_) => problems.push((0, "`decimals` must be a whole number".into())),
+                },
+                _ => problems.push((0, format!("Unknown table option `{}`", name))),
+            }
+        }
+        options
+    }
+}
+
+/// Splits `data` into rows of cells, each with the line it starts on. Cells
+/// in double quotes can hold the delimiter, line breaks and `""` for a quote.
+fn parse_rows(
+    data: &str,
+    delimiter: char,
+    problems: &mut Vec<DataProblem>,
+) -> Vec<(usize, Vec<String>)> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut cell = String::new();
+    let mut line = 1;
+    let mut row_line = 1;
+    let mut quoted_from: Option<usize> = None;
+
+    let mut end_row = |row: &mut Vec<String>, cell: &mut String, row_line: usize| {
+        row.push(std::mem::take(cell));
+        let row = std::mem::take(row);
+        if row.len() > 1 || !row[0].trim().is_empty() {
+            rows.push((row_line, row));
+        }
+    };
+
+    let mut chars = data.chars().peekable();
+    while let Some(c) = chars.next() {
+        if quoted_from.is_some() {
+            match c {
+                '"' if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    cell.push('"');
+                }
+                '"' => {
+                    quoted_from = None;
+                    if chars
+                        .peek()
+                        .is_some_and(|&next| next != delimiter && next != '\n' && next != '\r')
+                    {
+                        problems.push((line, "There's text after a closing quote".into()));
+                    }
+                }
+                '\n' => {
+                    line += 1;
+                    cell.push(c);
+                }
+                _ => cell.push(c),
+            }
+        } else if c == '"' && cell.trim().is_empty() {
+            cell.clear();
+            quoted_from = Some(line);
+        } else if c == delimiter {
+            row.push(std::mem::take(&mut cell));
+        } else if c == '\n' {
+            end_row(&mut row, &mut cell, row_line);
+            line += 1;
+            row_line = line;
+        } else if c != '\r' {
+            cell.push(c);
+        }
+    }
+    if let Some(quote_line) = quoted_from {
+        problems.push((quote_line, "This quote is never closed".into()));
+    }
+    end_row(&mut row, &mut cell, row_line);
+    rows
+}
+
+fn parse_number(cell: &str) -> Option<f64> {
+    let cell = cell.trim();
+    if cell.is_empty()
+        || !cell
+            .chars()
+            .all(|c| c.is_ascii_digit() || "+-.,eE".contains(c))
+        || !cell.chars().any(|c| c.is_ascii_digit())
+    {
+        return None;
+    }
+    cell.replace(',', "").parse().ok()
+}
+
+/// Columns are numeric when every cell in the body that isn't empty is a
+/// number.
+fn numeric_columns(body: &[Vec<String>], columns: usize) -> Vec<bool> {
+    (0..columns)
+        .map(|i| {
+            let cells: Vec<&str> = body
+                .iter()
+                .filter_map(|row| row.get(i))
+                .map(|c| c.trim())
+                .filter(|c| !c.is_empty())
+                .collect();
+            !cells.is_empty() && cells.iter().all(|c| parse_number(c).is_some())
+        })
+        .collect()
+}
+
+/// The first row names the columns if it has text at the top of a column of
+/// numbers. Tables with no numbers in them are assumed to have a header.
+fn detect_header(rows: &[Vec<String>], columns: usize) -> bool {
+    if rows.len() < 2 {
+        return false;
+    }
+    let numeric = numeric_columns(&rows[1..], columns);
+    if !numeric.contains(&true) {
+        return true;
+    }
+    numeric
+        .iter()
+        .enumerate()
+        .any(|(i, numeric)| *numeric && rows[0].get(i).is_none_or(|c| parse_number(c).is_none()))
+}
+
+fn format_number(cell: &str, options: &TableOptions) -> String {
+    let cell = cell.trim();
+    if options.decimals.is_none() && !options.separators {
+        return cell.to_string();
+    }
+    let Some(value) = parse_number(cell) else {
+        return cell.to_string();
+    };
+    let number = match options.decimals {
+        Some(decimals) => format!("{:.*}", decimals, value),
+        None => cell.replace(',', ""),
+    };
+    if !options.separators {
+        return number;
+    }
+
+    let (sign, unsigned) = match number.strip_prefix('-') {
+        Some(rest) => ("-", rest),
+        None => ("", number.trim_start_matches('+')),
+    };
+    let (whole, fraction) = match unsigned.find(['.', 'e', 'E']) {
+        Some(i) => unsigned.split_at(i),
+        None => (unsigned, ""),
+    };
+    let mut grouped = String::new();
+    for (i, digit) in whole.chars().enumerate() {
+        if i > 0 && (whole.len() - i) % 3 == 0 {
+            grouped.push(',');
+        }
+        grouped.push(digit);
+    }
+    format!("{}{}{}", sign, grouped, fraction)
+}
+
+struct DataTable {
+    options: TableOptions,
+    header: Option<Vec<String>>,
+    body: Vec<Vec<String>>,
+    numeric: Vec<bool>,
+}
+
+fn read_table(info: &str, data: &str) -> (DataTable, Vec<DataProblem>) {
+    let mut problems = Vec::new();
+    let options = TableOptions::parse(info, &mut problems);
+    let delimiter = data_delimiter(info).unwrap_or(',');
+    let rows = parse_rows(data, delimiter, &mut problems);
+
+    let expected = rows.first().map(|(_, r)| r.len()).unwrap_or_default();
+    let cells = |n: usize| format!("{} {}", n, if n == 1 { "cell" } else { "cells" });
+    for (line, row) in &rows {
+        if row.len() != expected {
+            problems.push((
+                *line,
+                format!(
+                    "This row has {} but the first row has {}",
+                    cells(row.len()),
+                    cells(expected)
+                ),
+            ));
+        }
+    }
+    if rows.is_empty() {
+        problems.push((0, "This table has no data".into()));
+    }
+
+    let columns = rows.iter().map(|(_, r)| r.len()).max().unwrap_or_default();
+    let mut rows: Vec<Vec<String>> = rows
+        .into_iter()
+        .map(|(_, mut row)| {
+            row.resize(columns, String::new());
+            row
+        })
+        .collect();
+    let has_header = options
+        .header
+        .unwrap_or_else(|| detect_header(&rows, columns));
+    let header = (has_header && !rows.is_empty()).then(|| rows.remove(0));
+    let numeric = numeric_columns(&rows, columns);
+    problems.sort_by_key(|(line, _)| *line);
+    (
+        DataTable {
+            options,
+            header,
+            body: rows,
+            numeric,
+        },
+        problems,
+    )
+}
+
+/// The problems with a `csv` or `tsv` block's data and options.
+pub fn data_problems(info: &str, data: &str) -> Vec<DataProblem> {
+    read_table(info, data).1
+}
+
+/// Renders a `csv` or `tsv` block as a table. Numeric columns are aligned
+/// right unless the info string says otherwise. Data that doesn't parse is
+/// shown as well as it can be, and previews list what's wrong with it.
+pub fn data_table(info: &str, data: &str, preview: bool) -> String {
+    let (table, problems) = read_table(info, data);
+    let options = &table.options;
+    let align = |column: usize| {
+        options
+            .align
+            .get(column)
+            .copied()
+            .unwrap_or(match table.numeric[column] {
+                true => Align::Right,
+                false => Align::Left,
+            })
+    };
+
+    let mut html = String::from(r#"<div class="data-table">"#);
+    if preview && !problems.is_empty() {
+        html.push_str(r#"<div class="data-error">"#);
+        for (line, problem) in &problems {
+            match line {
+                0 => html.push_str(&format!("<p>{}</p>", escape_html(problem))),
+                _ => html.push_str(&format!("<p>Line {}: {}</p>", line, escape_html(problem))),
+            }
+        }
+        html.push_str("</div>");
+    }
+    if table.header.is_none() && table.body.is_empty() {
+        html.push_str("</div>");
+        return html;
+    }
+
+    html.push_str("<table>\n");
+    if let Some(caption) = &options.caption {
+        html.push_str(&format!("<caption>{}</caption>\n", escape_html(caption)));
+    }
+    if let Some(header) = &table.header {
+        html.push_str("<thead><tr>");
+        for (column, cell) in header.iter().enumerate() {
+            html.push_str(&format!(
+                r#"<th scope="col" class="{}">{}</th>"#,
+                align(column).class(),
+                escape_html(cell.trim())
+            ));
+        }
+        html.push_str("</tr></thead>\n");
+    }
+    html.push_str("<tbody>\n");
+    for row in &table.body {
+        html.push_str("<tr>");
+        for (column, cell) in row.iter().enumerate() {
+            let (text, number_class) = match table.numeric[column] {
+                true => (format_number(cell, options), " number"),
+                false => (cell.trim().to_string(), ""),
+            };
+            let (open, close) = match column == 0 && options.row_headers {
+                true => (r#"th scope="row""#, "th"),
+                false => ("td", "td"),
+            };
+            html.push_str(&format!(
+                r#"<{} class="{}{}">{}</{}>"#,
+                open,
+                align(column).class(),
+                number_class,
+                escape_html(&text),
+                close
+            ));
+        }
+        html.push_str("</tr>\n");
+    }
+    html.push_str("</tbody>\n</table></div>");
+    html
+}
+
+#[test]
+fn reads_data_into_tables() {
+    let data = "Town,\"Rain, mm\",Days\nWellington,1249.5,\"1\"\"\"\nAuckland,1212\n\n";
+    let (table, problems) = read_table("csv", data);
+    assert_eq!(
+        table.header,
+        Some(vec!["Town".into(), "Rain, mm".into(), "Days".into()])
+    );
+    assert_eq!(table.body[1], vec!["Auckland", "1212", ""]);
+    assert_eq!(table.numeric, vec![false, true, false]);
+    assert_eq!(
+        problems,
+        vec![(
+            3,
+            "This row has 2 cells but the first row has 3 cells".into()
+        )]
+    );
+
+    // All numbers, so there's nothing to name the columns.
+    let (table, _) = read_table("tsv", "1\t2\n3\t4");
+    assert_eq!(table.header, None);
+
+    let html = data_table(
+        "csv caption=\"Rain\" align=cr decimals=1 separators row-headers",
+        "Town,Rain\nWellington,1249.46",
+        false,
+    );
+    assert!(html.contains("<caption>Rain</caption>"));
+    assert!(html.contains(r#"<th scope="col" class="align-centre">Town</th>"#));
+    assert!(html.contains(r#"<th scope="row" class="align-centre">Wellington</th>"#));
+    assert!(html.contains(r#"<td class="align-right number">1,249.5</td>"#));
+
+    let (_, problems) = read_table("csv align=x wide", "a,\"b\nc");
+    assert_eq!(
+        problems,
+        vec![
+            (0, "Unknown table option `wide`".into()),
+            (0, "`x` isn't an alignment, use l, c or r".into()),
+            (1, "This quote is never closed".into())
+        ]
+    );
+}
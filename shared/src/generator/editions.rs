@@ -0,0 +1,120 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::query;
+use tera::Context;
+
+use crate::types::{CommonData, HydratedPost};
+
+use super::types::Generator;
+
+#[derive(Serialize)]
+struct Edition<'a> {
+    number: i32,
+    date: DateTime<Utc>,
+    posts: Vec<&'a HydratedPost>,
+}
+
+#[derive(Serialize)]
+struct EditionPage<'a> {
+    title: &'a str,
+    common: &'a CommonData,
+    edition: &'a Edition<'a>,
+}
+
+#[derive(Serialize)]
+struct EditionsArchivePage<'a> {
+    title: &'a str,
+    common: &'a CommonData,
+    editions: &'a [Edition<'a>],
+}
+
+/// Where an edition's index page lives, relative to the site root.
+pub fn edition_output_dir(number: i32) -> String {
+    format!("editions/{}/", number)
+}
+
+/// Writes an index page for every edition with published posts, plus an
+/// archive of all editions at `editions/`. Does nothing unless the site has
+/// editions enabled.
+pub async fn generate_edition_pages(
+    posts: &[HydratedPost],
+    generator: &Generator<'_>,
+) -> anyhow::Result<()> {
+    if !generator.common.editions_enabled {
+        return Ok(());
+    }
+
+    let rows = query!(
+        r#"
+SELECT e.number, e.date, array_agg(ep.post_id) AS "post_ids!"
+FROM editions e
+INNER JOIN edition_posts ep
+ON ep.edition_id = e.id
+WHERE e.site_id = $1
+AND e.date <= CURRENT_TIMESTAMP
+GROUP BY e.id
+ORDER BY e.number DESC
+"#,
+        generator.site_id
+    )
+    .fetch_all(generator.pool)
+    .await?;
+
+    let editions: Vec<Edition> = rows
+        .into_iter()
+        .map(|row| {
+            let mut edition_posts: Vec<&HydratedPost> = posts
+                .iter()
+                .filter(|p| row.post_ids.contains(&p.id))
+                .collect();
+            edition_posts.sort_by_key(|p| p.post_date);
+            Edition {
+                number: row.number,
+                date: row.date,
+                posts: edition_posts,
+            }
+        })
+        .filter(|e| !e.posts.is_empty())
+        .collect();
+
+    for edition in &editions {
+        let title = format!("Edition {}", edition.number);
+        let page = EditionPage {
+            title: &title,
+            common: generator.common,
+            edition,
+        };
+        let deps = generator
+            .page_dependencies("edition.html")
+            .value(&(edition.number, edition.date))
+            .posts(edition.posts.iter().copied(), generator.common);
+        generator
+            .render_output(
+                &format!("{}index.html", edition_output_dir(edition.number)),
+                &deps,
+                "edition.html",
+                &Context::from_serialize(page)?,
+            )
+            .await?;
+    }
+
+    let page = EditionsArchivePage {
+        title: "Editions",
+        common: generator.common,
+        editions: &editions,
+    };
+    let deps = editions
+        .iter()
+        .fold(generator.page_dependencies("editions.html"), |deps, e| {
+            deps.value(&(e.number, e.date))
+                .posts(e.posts.iter().copied(), generator.common)
+        });
+    generator
+        .render_output(
+            "editions/index.html",
+            &deps,
+            "editions.html",
+            &Context::from_serialize(page)?,
+        )
+        .await
+}
@@ -1,64 +1,183 @@
-use crate::types::{CommonData, HydratedPost};
-use anyhow::anyhow;
+use crate::types::{CommonData, FeedLink, HydratedPost};
 use chrono::{DateTime, Utc};
+use itertools::Itertools;
 use serde::Serialize;
+use sqlx::query;
 use tera::Context;
-use tokio::{fs::File, io::AsyncWriteExt};
 
 use super::types::Generator;
 
 #[derive(Serialize)]
 struct Feed<'a> {
     common: &'a CommonData,
-    posts: &'a [HydratedPost],
+    posts: &'a [&'a HydratedPost],
     date: DateTime<Utc>,
+    title: &'a str,
+    link: &'a str,
+    self_url: &'a str,
+}
+
+/// The RSS, Atom and JSON feeds written into `output_dir`, relative to the site root.
+pub fn feed_links(common: &CommonData, output_dir: &str, title: &str) -> Vec<FeedLink> {
+    vec![
+        FeedLink {
+            title: format!("{} (RSS)", title),
+            url: format!("{}{}feed.rss", common.base_url, output_dir),
+            mime_type: "application/rss+xml",
+        },
+        FeedLink {
+            title: format!("{} (Atom)", title),
+            url: format!("{}{}feed.atom", common.base_url, output_dir),
+            mime_type: "application/atom+xml",
+        },
+        FeedLink {
+            title: format!("{} (JSON Feed)", title),
+            url: format!("{}{}feed.json", common.base_url, output_dir),
+            mime_type: "application/feed+json",
+        },
+    ]
+}
+
+pub fn tag_feed_title(common: &CommonData, tag: &str) -> String {
+    format!("{}: {}", common.blog_name, tag)
+}
+
+pub fn tag_output_dir(tag: &str) -> String {
+    format!("tags/{}/", tag.to_lowercase())
 }
 
 pub async fn generate_rss_feed(
     posts: &[HydratedPost],
     generator: &Generator<'_>,
 ) -> anyhow::Result<()> {
-    let max = ::std::cmp::min(posts.len(), 10);
-    let posts_in_feed = posts
-        .get(0..max)
-        .ok_or(anyhow!("Failed to get posts for feed"))?;
+    let posts: Vec<&HydratedPost> = posts.iter().collect();
+    write_feed(
+        &posts,
+        generator,
+        "",
+        &generator.common.blog_name,
+        "rss.xml",
+        "feed.rss",
+    )
+    .await
+}
 
-    let feed = Feed {
-        common: generator.common,
-        posts: posts_in_feed,
-        date: Utc::now(),
-    };
-    let mut file = File::create(format!("{}/feed.rss", generator.output_path)).await?;
+pub async fn generate_atom_feed(
+    posts: &[HydratedPost],
+    generator: &Generator<'_>,
+) -> anyhow::Result<()> {
+    let posts: Vec<&HydratedPost> = posts.iter().collect();
+    write_feed(
+        &posts,
+        generator,
+        "",
+        &generator.common.blog_name,
+        "atom.xml",
+        "feed.atom",
+    )
+    .await
+}
 
-    let rendered = generator
-        .tera
-        .render("rss.xml", &Context::from_serialize(feed)?)?;
-    file.write_all(rendered.as_bytes()).await?;
+pub async fn generate_json_feed(
+    posts: &[HydratedPost],
+    generator: &Generator<'_>,
+) -> anyhow::Result<()> {
+    let posts: Vec<&HydratedPost> = posts.iter().collect();
+    write_feed(
+        &posts,
+        generator,
+        "",
+        &generator.common.blog_name,
+        "feed.json",
+        "feed.json",
+    )
+    .await
+}
 
+/// Writes RSS, Atom and JSON feeds for each tag next to its index.
+pub async fn generate_tag_feeds(
+    posts: &[HydratedPost],
+    generator: &Generator<'_>,
+) -> anyhow::Result<()> {
+    let all_tags = query!("SELECT name FROM tags WHERE site_id=$1", generator.site_id)
+        .fetch_all(generator.pool)
+        .await?;
+    for tag_row in all_tags {
+        let tag = tag_row.name;
+        let tag_posts: Vec<&HydratedPost> = posts
+            .iter()
+            .filter(|p| p.tags.as_ref().is_some_and(|t| t.contains(&tag)))
+            .sorted_by(|a, b| Ord::cmp(&b.post_date, &a.post_date))
+            .collect();
+        if tag_posts.is_empty() {
+            continue;
+        }
+
+        let output_dir = tag_output_dir(&tag);
+        let title = tag_feed_title(generator.common, &tag);
+        write_feed(
+            &tag_posts,
+            generator,
+            &output_dir,
+            &title,
+            "rss.xml",
+            "feed.rss",
+        )
+        .await?;
+        write_feed(
+            &tag_posts,
+            generator,
+            &output_dir,
+            &title,
+            "atom.xml",
+            "feed.atom",
+        )
+        .await?;
+        write_feed(
+            &tag_posts,
+            generator,
+            &output_dir,
+            &title,
+            "feed.json",
+            "feed.json",
+        )
+        .await?;
+    }
     Ok(())
 }
 
-pub async fn generate_atom_feed(
-    posts: &[HydratedPost],
+async fn write_feed(
+    posts: &[&HydratedPost],
     generator: &Generator<'_>,
+    output_dir: &str,
+    title: &str,
+    template: &str,
+    filename: &str,
 ) -> anyhow::Result<()> {
     let max = ::std::cmp::min(posts.len(), 10);
-    let posts_in_feed = posts
-        .get(0..max)
-        .ok_or(anyhow!("Failed to get posts for feed"))?;
+    let posts_in_feed = &posts[0..max];
+    let link = format!("{}{}", generator.common.base_url, output_dir);
+    let self_url = format!("{}{}", link, filename);
 
     let feed = Feed {
         common: generator.common,
         posts: posts_in_feed,
         date: Utc::now(),
+        title,
+        link: &link,
+        self_url: &self_url,
     };
+    let deps = generator
+        .dependencies(&[template])
+        .value(title)
+        .posts(posts_in_feed.iter().copied(), generator.common);
 
-    let mut file = File::create(format!("{}/feed.atom", generator.output_path)).await?;
-
-    let rendered = generator
-        .tera
-        .render("atom.xml", &Context::from_serialize(feed)?)?;
-    file.write_all(rendered.as_bytes()).await?;
-
-    Ok(())
+    generator
+        .render_output(
+            &format!("{}{}", output_dir, filename),
+            &deps,
+            template,
+            &Context::from_serialize(feed)?,
+        )
+        .await
 }
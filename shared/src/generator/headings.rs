@@ -0,0 +1,158 @@
+use std::collections::HashSet;
+
+use pulldown_cmark::{Event, HeadingLevel, Parser, Tag, TagEnd};
+use serde::Serialize;
+
+/// A heading in a post or page, with the id it's rendered with.
+#[derive(Debug, PartialEq)]
+pub struct Heading {
+    pub level: HeadingLevel,
+    pub title: String,
+    pub id: String,
+}
+
+/// One entry in a table of contents, with the headings under it.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct TocEntry {
+    pub title: String,
+    pub id: String,
+    pub children: Vec<TocEntry>,
+}
+
+/// Lower case letters and numbers, with anything else between them turned
+/// into a single `-`.
+pub fn slugify(text: &str) -> String {
+    let mut slug = String::with_capacity(text.len());
+    for c in text.chars() {
+        if c.is_alphanumeric() {
+            slug.extend(c.to_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    let slug = slug.trim_end_matches('-');
+    if slug.is_empty() {
+        "section".into()
+    } else {
+        slug.into()
+    }
+}
+
+/// Every heading in a document, in order. Ids come from the heading text,
+/// with `-2`, `-3` and so on added when the same text is used again. Ids
+/// given in the markdown are kept.
+pub fn find_headings(parser: Parser) -> Vec<Heading> {
+    let mut headings = Vec::new();
+    let mut current: Option<(HeadingLevel, Option<String>, String)> = None;
+    for event in parser {
+        match event {
+            Event::Start(Tag::Heading { level, id, .. }) => {
+                current = Some((level, id.map(|i| i.to_string()), String::new()));
+            }
+            Event::Text(text) | Event::Code(text) => {
+                if let Some((_, _, title)) = current.as_mut() {
+                    title.push_str(&text);
+                }
+            }
+            Event::End(TagEnd::Heading(_)) => {
+                if let Some((level, id, title)) = current.take() {
+                    headings.push((level, id, title));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let mut used: HashSet<String> = headings
+        .iter()
+        .filter_map(|(_, id, _)| id.clone())
+        .collect();
+    headings
+        .into_iter()
+        .map(|(level, id, title)| {
+            let id = id.unwrap_or_else(|| {
+                let slug = slugify(&title);
+                let mut id = slug.clone();
+                let mut n = 1;
+                while used.contains(&id) {
+                    n += 1;
+                    id = format!("{}-{}", slug, n);
+                }
+                used.insert(id.clone());
+                id
+            });
+            Heading {
+                level,
+                title: title.trim().to_string(),
+                id,
+            }
+        })
+        .collect()
+}
+
+/// Nests headings under the closest heading before them with a higher
+/// level.
+pub fn table_of_contents(headings: &[Heading]) -> Vec<TocEntry> {
+    fn nest(headings: &[Heading], index: &mut usize, level: HeadingLevel) -> Vec<TocEntry> {
+        let mut entries = Vec::new();
+        while let Some(heading) = headings.get(*index) {
+            if heading.level < level {
+                break;
+            }
+            *index += 1;
+            let children = match deeper(heading.level) {
+                Some(level) => nest(headings, index, level),
+                None => Vec::new(),
+            };
+            entries.push(TocEntry {
+                title: heading.title.clone(),
+                id: heading.id.clone(),
+                children,
+            });
+        }
+        entries
+    }
+
+    let top = headings
+        .iter()
+        .map(|h| h.level)
+        .min()
+        .unwrap_or(HeadingLevel::H1);
+    nest(headings, &mut 0, top)
+}
+
+fn deeper(level: HeadingLevel) -> Option<HeadingLevel> {
+    match level {
+        HeadingLevel::H1 => Some(HeadingLevel::H2),
+        HeadingLevel::H2 => Some(HeadingLevel::H3),
+        HeadingLevel::H3 => Some(HeadingLevel::H4),
+        HeadingLevel::H4 => Some(HeadingLevel::H5),
+        HeadingLevel::H5 => Some(HeadingLevel::H6),
+        HeadingLevel::H6 => None,
+    }
+}
+
+#[test]
+fn unique_ids_and_nesting() {
+    let source = "## Intro\n\n### Set up `cargo`\n\n## Intro\n\n### Détails!\n\n# Intro\n\n## ?\n";
+    let headings = find_headings(Parser::new(source));
+    let ids: Vec<&str> = headings.iter().map(|h| h.id.as_str()).collect();
+    assert_eq!(
+        ids,
+        vec![
+            "intro",
+            "set-up-cargo",
+            "intro-2",
+            "détails",
+            "intro-3",
+            "section"
+        ]
+    );
+
+    let toc = table_of_contents(&headings);
+    assert_eq!(toc.len(), 3);
+    assert_eq!(toc[0].children[0].title, "Set up cargo");
+    assert_eq!(toc[1].children[0].id, "détails");
+    assert_eq!(toc[2].id, "intro-3");
+    assert_eq!(toc[2].children[0].id, "section");
+}
@@ -0,0 +1,268 @@
+use std::{fmt::Write, ops::RangeInclusive, sync::LazyLock};
+
+use syntect::{
+    highlighting::{Theme, ThemeSet},
+    html::{ClassStyle, css_for_theme_with_class_style, line_tokens_to_classed_spans},
+    parsing::{ParseState, Scope, ScopeStack, SyntaxReference, SyntaxSet},
+    util::LinesWithEndings,
+};
+use tera::escape_html;
+
+use crate::settings::DEFAULT_CODE_THEME;
+
+static SYNTAXES: LazyLock<SyntaxSet> = LazyLock::new(SyntaxSet::load_defaults_newlines);
+static THEMES: LazyLock<ThemeSet> = LazyLock::new(ThemeSet::load_defaults);
+
+/// Prefix on every highlighting class, so theme CSS can't clash with the
+/// site's own.
+const CLASS_PREFIX: &str = "hl-";
+const CLASS_STYLE: ClassStyle = ClassStyle::SpacedPrefixed {
+    prefix: CLASS_PREFIX,
+};
+
+/// Every theme a site can pick for its code blocks.
+pub fn theme_names() -> Vec<String> {
+    THEMES.themes.keys().cloned().collect()
+}
+
+/// What a fenced code block's info string asks for, e.g. `rust{3-5}` or
+/// `python {1,4} numbered`.
+#[derive(Debug, PartialEq, Default)]
+pub struct CodeBlockInfo {
+    pub language: String,
+    pub highlighted: Vec<RangeInclusive<usize>>,
+    pub line_numbers: Option<bool>,
+}
+
+impl CodeBlockInfo {
+    pub fn parse(info: &str) -> CodeBlockInfo {
+        let info = info.trim();
+        let language_end = info
+            .find(|c: char| c == '{' || c.is_whitespace())
+            .unwrap_or(info.len());
+        let (language, mut rest) = info.split_at(language_end);
+
+        let mut highlighted = Vec::new();
+        if let Some(ranges) = rest.trim_start().strip_prefix('{')
+            && let Some((ranges, after)) = ranges.split_once('}')
+        {
+            highlighted = ranges.split(',').filter_map(parse_range).collect();
+            rest = after;
+        }
+
+        let mut line_numbers = None;
+        for flag in rest.split_whitespace() {
+            match flag {
+                "numbered" => line_numbers = Some(true),
+                "unnumbered" => line_numbers = Some(false),
+                _ => {}
+            }
+        }
+
+        CodeBlockInfo {
+            language: language.to_string(),
+            highlighted,
+            line_numbers,
+        }
+    }
+
+    fn is_highlighted(&self, line: usize) -> bool {
+        self.highlighted.iter().any(|r| r.contains(&line))
+    }
+}
+
+fn parse_range(range: &str) -> Option<RangeInclusive<usize>> {
+    let range = range.trim();
+    match range.split_once('-') {
+        Some((start, end)) => Some(start.trim().parse().ok()?..=end.trim().parse().ok()?),
+        None => {
+            let line = range.parse().ok()?;
+            Some(line..=line)
+        }
+    }
+}
+
+fn find_syntax(language: &str) -> &'static SyntaxReference {
+    SYNTAXES
+        .find_syntax_by_token(language)
+        .unwrap_or_else(|| SYNTAXES.find_syntax_plain_text())
+}
+
+/// The classes syntect gives `scope`, matching its own span output.
+fn scope_classes(scope: &Scope) -> String {
+    scope
+        .build_string()
+        .split('.')
+        .map(|atom| format!("{}{}", CLASS_PREFIX, atom))
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+/// Highlights `code` into a `<pre>` block. Each line is wrapped in its own
+/// `<span class="line">` with every open scope closed and reopened around
+/// it, so lines can be numbered and highlighted with CSS alone.
+pub fn highlight_code(code: &str, info: &CodeBlockInfo, line_numbers: bool) -> String {
+    let language = escape_html(&info.language);
+    let line_numbers = info.line_numbers.unwrap_or(line_numbers);
+    let mut html = format!(
+        r#"<pre class="fenced-code language-{}{}"><code class="language-{}">"#,
+        language,
+        if line_numbers { " numbered" } else { "" },
+        language
+    );
+
+    let mut parse_state = ParseState::new(find_syntax(&info.language));
+    let mut stack = ScopeStack::new();
+    for (index, line) in LinesWithEndings::from(code).enumerate() {
+        let (text, newline) = match line.strip_suffix('\n') {
+            Some(text) => (text, "\n"),
+            None => (line, ""),
+        };
+        let class = if info.is_highlighted(index + 1) {
+            "line highlighted"
+        } else {
+            "line"
+        };
+        write_line(&mut html, class, &mut stack, text, |stack| {
+            parse_state
+                .parse_line(line, &SYNTAXES)
+                .ok()
+                .and_then(|ops| line_tokens_to_classed_spans(line, &ops, CLASS_STYLE, stack).ok())
+                // The newline goes after the line's span instead.
+                .map(|(spans, _)| spans.replacen('\n', "", 1))
+        });
+        html.push_str(newline);
+    }
+
+    html.push_str("</code></pre>");
+    html
+}
+
+/// Writes one line in its own `<span class="{class}">`, reopening the scopes
+/// `stack` has open from the lines before and closing whatever is open at
+/// the end. `highlight` turns the line into spans, moving `stack` along.
+fn write_line(
+    html: &mut String,
+    class: &str,
+    stack: &mut ScopeStack,
+    text: &str,
+    highlight: impl FnOnce(&mut ScopeStack) -> Option<String>,
+) {
+    let _ = write!(html, r#"<span class="{}">"#, class);
+    for scope in stack.as_slice() {
+        let _ = write!(html, r#"<span class="{}">"#, scope_classes(scope));
+    }
+    let opened = stack.len();
+    match highlight(stack) {
+        Some(spans) => {
+            html.push_str(&spans);
+            html.push_str(&"</span>".repeat(stack.len()));
+        }
+        // If the grammar chokes carry on unhighlighted rather than losing
+        // the code, closing the scopes this line reopened.
+        None => {
+            html.push_str(&escape_html(text));
+            html.push_str(&"</span>".repeat(opened));
+            *stack = ScopeStack::new();
+        }
+    }
+    html.push_str("</span>");
+}
+
+fn theme(name: &str) -> &'static Theme {
+    THEMES
+        .themes
+        .get(name)
+        .unwrap_or_else(|| &THEMES.themes[DEFAULT_CODE_THEME])
+}
+
+/// CSS for the site's code theme, with the dark theme if one is set applied
+/// when the reader prefers a dark colour scheme.
+pub fn theme_css(light: &str, dark: Option<&str>) -> anyhow::Result<String> {
+    let mut css = css_for_theme(theme(light))?;
+    if let Some(dark) = dark {
+        css.push_str("\n@media (prefers-color-scheme: dark) {\n");
+        css.push_str(&css_for_theme(theme(dark))?);
+        css.push_str("}\n");
+    }
+    Ok(css)
+}
+
+fn css_for_theme(theme: &Theme) -> anyhow::Result<String> {
+    let mut css = css_for_theme_with_class_style(theme, CLASS_STYLE)?;
+    if let Some(background) = theme.settings.background {
+        let _ = writeln!(
+            css,
+            "pre.fenced-code {{ background-color: #{:02x}{:02x}{:02x}; }}",
+            background.r, background.g, background.b
+        );
+    }
+    if let Some(highlight) = theme.settings.line_highlight {
+        let _ = writeln!(
+            css,
+            "pre.fenced-code .line.highlighted {{ background-color: #{:02x}{:02x}{:02x}{:02x}; }}",
+            highlight.r, highlight.g, highlight.b, highlight.a
+        );
+    }
+    Ok(css)
+}
+
+#[test]
+fn parses_info_strings() {
+    assert_eq!(
+        CodeBlockInfo::parse("rust{3-5}"),
+        CodeBlockInfo {
+            language: "rust".into(),
+            highlighted: vec![3..=5],
+            line_numbers: None,
+        }
+    );
+    assert_eq!(
+        CodeBlockInfo::parse("python {1, 4-6} numbered"),
+        CodeBlockInfo {
+            language: "python".into(),
+            highlighted: vec![1..=1, 4..=6],
+            line_numbers: Some(true),
+        }
+    );
+    assert_eq!(
+        CodeBlockInfo::parse("sh unnumbered"),
+        CodeBlockInfo {
+            language: "sh".into(),
+            highlighted: vec![],
+            line_numbers: Some(false),
+        }
+    );
+}
+
+#[test]
+fn highlights_lines_independently() {
+    let code = "/* a\n   b */\nfn main() {}\n";
+    let html = highlight_code(code, &CodeBlockInfo::parse("rust{2}"), true);
+    assert!(html.starts_with(r#"<pre class="fenced-code language-rust numbered">"#));
+    let lines: Vec<&str> = html.split('\n').collect();
+    assert_eq!(lines.len(), 4);
+    assert!(
+        lines[1].starts_with(r#"<span class="line highlighted"><span class="hl-source hl-rust">"#)
+    );
+    assert!(lines[1].contains("hl-comment"));
+    // Every line closes what it opens.
+    for line in &lines[..3] {
+        assert_eq!(
+            line.matches("<span").count(),
+            line.matches("</span>").count()
+        );
+    }
+}
+
+#[test]
+fn closes_scopes_when_highlighting_fails() {
+    let mut stack: ScopeStack = "source.rust comment.block.rust".parse().unwrap();
+    let mut html = String::new();
+    write_line(&mut html, "line", &mut stack, "a < b", |_| None);
+    assert_eq!(
+        html,
+        r#"<span class="line"><span class="hl-source hl-rust"><span class="hl-comment hl-block hl-rust">a &lt; b</span></span></span>"#
+    );
+    assert!(stack.is_empty());
+}
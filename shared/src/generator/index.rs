@@ -3,14 +3,13 @@ use serde::Serialize;
 use sqlx::query;
 use std::vec::IntoIter;
 use tera::Context;
-use tokio::{
-    fs::{File, create_dir_all},
-    io::AsyncWriteExt,
-};
 
-use crate::types::{CommonData, HydratedPost};
+use crate::types::{CommonData, FeedLink, HydratedPost};
 
-use super::types::Generator;
+use super::{
+    feeds::{feed_links, tag_feed_title, tag_output_dir},
+    types::Generator,
+};
 
 #[derive(Serialize)]
 struct IndexPage<'a> {
@@ -19,13 +18,15 @@ struct IndexPage<'a> {
     posts: Vec<&'a HydratedPost>,
     page: i32,
     total_pages: i32,
+    feeds: &'a [FeedLink],
 }
 
 pub async fn generate_index_pages(
     posts: IntoIter<&HydratedPost>,
     generator: &Generator<'_>,
 ) -> anyhow::Result<()> {
-    generate_pages(posts, generator, generator.output_path, "index.html").await
+    let feeds = feed_links(generator.common, "", &generator.common.blog_name);
+    generate_pages(posts, generator, "", "index.html", &feeds).await
 }
 
 pub fn index_content(
@@ -34,6 +35,7 @@ pub fn index_content(
     page_number: i32,
     total_pages: i32,
     template: &str,
+    feeds: &[FeedLink],
 ) -> anyhow::Result<String> {
     let page = IndexPage {
         title: &generator.common.blog_name,
@@ -41,6 +43,7 @@ pub fn index_content(
         common: generator.common,
         page: page_number,
         total_pages,
+        feeds,
     };
 
     let result = generator
@@ -52,21 +55,36 @@ pub fn index_content(
 async fn generate_pages<'a>(
     posts: IntoIter<&HydratedPost>,
     generator: &Generator<'a>,
-    output_path: &'a str,
+    output_dir: &'a str,
     template: &'a str,
+    feeds: &[FeedLink],
 ) -> anyhow::Result<()> {
     let total_pages = (posts.len() as f64 / 10.0).ceil() as i32;
     for (pos, chunk) in posts.chunks(10).into_iter().enumerate() {
         let path = if pos == 0 {
-            String::from("index.html")
+            format!("{}index.html", output_dir)
         } else {
-            format!("index{}.html", pos + 1)
+            format!("{}index{}.html", output_dir, pos + 1)
         };
-        let posts = chunk.collect();
+        let posts: Vec<&HydratedPost> = chunk.collect();
+        let deps = generator
+            .page_dependencies(template)
+            .value(&(pos, total_pages))
+            .value(feeds)
+            .posts(posts.iter().copied(), generator.common);
+        if generator.is_current(&path, &deps).await? {
+            continue;
+        }
 
-        let rendered = index_content(posts, generator, pos as i32 + 1, total_pages, template)?;
-        let mut file = File::create(format!("{}/{}", output_path, path)).await?;
-        file.write_all(rendered.as_bytes()).await?;
+        let rendered = index_content(
+            posts,
+            generator,
+            pos as i32 + 1,
+            total_pages,
+            template,
+            feeds,
+        )?;
+        generator.write_output(&path, &deps, &rendered).await?;
     }
     Ok(())
 }
@@ -86,13 +104,18 @@ pub async fn generate_tag_indexes(
             .filter(|p| p.tags.clone().map(|t| t.contains(&tag)).unwrap_or(false))
             .sorted_by(|a, b| Ord::cmp(&b.post_date, &a.post_date));
 
-        let tag_output_path = format!("{}/tags/{}/", generator.output_path, tag.to_lowercase());
-        create_dir_all(&tag_output_path).await?;
+        let output_dir = tag_output_dir(&tag);
+        let feeds = feed_links(
+            generator.common,
+            &output_dir,
+            &tag_feed_title(generator.common, &tag),
+        );
         generate_pages(
             tag_posts.into_iter(),
             generator,
-            &tag_output_path,
+            &output_dir,
             "subindex.html",
+            &feeds,
         )
         .await?;
     }
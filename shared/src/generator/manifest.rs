@@ -0,0 +1,192 @@
+use std::collections::{HashMap, HashSet};
+
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use sqlx::{PgConnection, PgPool, query};
+
+use super::{
+    templates::{embedded_media, markdown_options},
+    wiki_links::resolved_links,
+};
+use crate::{
+    referencing::cited_references,
+    types::{CommonData, HydratedPost},
+};
+
+/// Records what each generated file was rendered from, so a regeneration
+/// only has to re-render the files whose inputs changed.
+pub struct Manifest {
+    site_id: i32,
+    force: bool,
+    previous: HashMap<String, String>,
+    current: HashMap<String, String>,
+    seen: HashSet<String>,
+    pub written: usize,
+    pub skipped: usize,
+}
+
+impl Manifest {
+    /// Loads the manifest from the last run. A forced rebuild considers every
+    /// file stale.
+    pub async fn load(pool: &PgPool, site_id: i32, force: bool) -> anyhow::Result<Self> {
+        let previous = query!(
+            "SELECT path, input_hash FROM generated_files WHERE site_id=$1",
+            site_id
+        )
+        .fetch_all(pool)
+        .await?
+        .into_iter()
+        .map(|r| (r.path, r.input_hash))
+        .collect();
+
+        Ok(Manifest {
+            site_id,
+            force,
+            previous,
+            current: HashMap::new(),
+            seen: HashSet::new(),
+            written: 0,
+            skipped: 0,
+        })
+    }
+
+    pub fn is_current(&mut self, path: &str, hash: &str) -> bool {
+        self.seen.insert(path.to_string());
+        let current = !self.force && self.previous.get(path).is_some_and(|h| h == hash);
+        if current {
+            self.skipped += 1;
+        }
+        current
+    }
+
+    pub fn record(&mut self, path: &str, hash: String) {
+        self.seen.insert(path.to_string());
+        self.current.insert(path.to_string(), hash);
+        self.written += 1;
+    }
+
+    /// Paths written or confirmed up to date during this run.
+    pub fn seen(&self) -> impl Iterator<Item = &String> {
+        self.seen.iter()
+    }
+
+    /// Files the last run generated that this run didn't.
+    pub fn orphans(&self) -> impl Iterator<Item = &String> {
+        self.previous.keys().filter(|p| !self.seen.contains(*p))
+    }
+
+    /// Forgets every generated file, so the next run rebuilds the whole site.
+    pub async fn clear(connection: &mut PgConnection, site_id: i32) -> anyhow::Result<()> {
+        query!("DELETE FROM generated_files WHERE site_id=$1", site_id)
+            .execute(connection)
+            .await?;
+        Ok(())
+    }
+
+    /// Saves the hashes of the files written during this run and forgets any
+    /// file that wasn't produced this time. The caller commits, so the
+    /// manifest only changes if the generation it describes goes live.
+    pub async fn save(&self, connection: &mut PgConnection) -> anyhow::Result<()> {
+        for (path, hash) in &self.current {
+            query!(
+                "
+INSERT INTO generated_files(site_id, path, input_hash, generated_at)
+VALUES ($1, $2, $3, CURRENT_TIMESTAMP)
+ON CONFLICT (site_id, path) DO UPDATE SET input_hash=EXCLUDED.input_hash, generated_at=EXCLUDED.generated_at
+",
+                self.site_id,
+                path,
+                hash
+            )
+            .execute(&mut *connection)
+            .await?;
+        }
+
+        let seen: Vec<String> = self.seen.iter().cloned().collect();
+        query!(
+            "DELETE FROM generated_files WHERE site_id=$1 AND NOT (path = ANY($2))",
+            self.site_id,
+            &seen
+        )
+        .execute(&mut *connection)
+        .await?;
+        Ok(())
+    }
+}
+
+/// The inputs a generated file was rendered from, reduced to a hash.
+pub struct Dependencies {
+    hasher: Sha256,
+}
+
+impl Dependencies {
+    pub fn new() -> Self {
+        Dependencies {
+            hasher: Sha256::new(),
+        }
+    }
+
+    pub fn template(mut self, name: &str, hash: Option<&String>) -> Self {
+        self.hasher.update(name.as_bytes());
+        self.hasher
+            .update(hash.map(|h| h.as_bytes()).unwrap_or_default());
+        self
+    }
+
+    /// Adds the whole post row, so retagging or a new comment is picked up
+    /// even though neither touches `updated_date`, and what its body is
+    /// rendered with.
+    pub fn post(self, post: &HydratedPost, common: &CommonData) -> Self {
+        self.value(post).markdown(&post.body, common)
+    }
+
+    /// Adds where the `[[slug]]` links in `body` go, the media it embeds and
+    /// the library entries it cites. Anything showing the body is rewritten
+    /// when one of those changes, without every upload or import rewriting
+    /// the whole site.
+    pub fn markdown(self, body: &str, common: &CommonData) -> Self {
+        self.value(&resolved_links(
+            body,
+            markdown_options(&common.markdown_extensions),
+            &common.link_targets,
+        ))
+        .value(
+            &embedded_media(body)
+                .map(|id| (id, common.media.get(&id)))
+                .collect::<Vec<_>>(),
+        )
+        .value(&cited_references(body, &common.reference_library))
+    }
+
+    pub fn posts<'a, I>(self, posts: I, common: &CommonData) -> Self
+    where
+        I: IntoIterator<Item = &'a HydratedPost>,
+    {
+        posts
+            .into_iter()
+            .fold(self, |deps, post| deps.post(post, common))
+    }
+
+    pub fn value<T: Serialize + ?Sized>(mut self, value: &T) -> Self {
+        // Serialising plain data to JSON can't fail, and if it ever did the
+        // file would just be rendered again.
+        self.hasher
+            .update(serde_json::to_vec(value).unwrap_or_default());
+        self.hasher.update(b"\0");
+        self
+    }
+
+    pub fn finish(&self) -> String {
+        format!("{:x}", self.hasher.clone().finalize())
+    }
+}
+
+impl Default for Dependencies {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub fn hash_str(content: &str) -> String {
+    format!("{:x}", Sha256::digest(content.as_bytes()))
+}
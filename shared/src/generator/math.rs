@@ -0,0 +1,223 @@
+use std::{collections::HashMap, sync::LazyLock};
+
+use latex2mathml::{DisplayStyle, latex_to_mathml};
+use pulldown_cmark::{CodeBlockKind, Event, Parser, Tag, TagEnd};
+use regex::{Captures, Regex};
+use tera::escape_html;
+
+static LABEL_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\\label\{([^}]*)\}").expect("Could not create label regex"));
+static REF_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"\\(?:eq)?ref\{([^}]*)\}").expect("Could not create equation reference regex")
+});
+
+/// Whether a fenced code block's info string makes it a display equation.
+pub fn is_math_block(info: &str) -> bool {
+    info.split_whitespace().next() == Some("math")
+}
+
+/// A display equation is numbered if it has a `\label` or its info string
+/// says `math numbered`.
+fn is_numbered(info: &str, latex: &str) -> bool {
+    LABEL_RE.is_match(latex) || info.split_whitespace().any(|f| f == "numbered")
+}
+
+fn equation_id(label: &str) -> String {
+    let label: String = label
+        .trim()
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { '-' })
+        .collect();
+    format!("eq-{}", label)
+}
+
+/// What's wrong with `latex`, if it can't be turned into MathML.
+pub fn latex_error(latex: &str, display: DisplayStyle) -> Option<String> {
+    latex_to_mathml(LABEL_RE.replace_all(latex, "").trim(), display)
+        .err()
+        .map(|e| e.to_string())
+}
+
+/// The numbered equations in a post, found before rendering so `\ref`s can
+/// point forwards as well as back.
+#[derive(Debug, Default)]
+pub struct Equations {
+    labels: HashMap<String, usize>,
+    count: usize,
+    preview: bool,
+}
+
+impl Equations {
+    pub fn new(parser: Parser, preview: bool) -> Equations {
+        let mut labels = HashMap::new();
+        let mut number = 0;
+        let mut block: Option<(String, String)> = None;
+        for event in parser {
+            match event {
+                Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(info)))
+                    if is_math_block(&info) =>
+                {
+                    block = Some((info.to_string(), String::new()));
+                }
+                Event::Text(text) => {
+                    if let Some((_, latex)) = block.as_mut() {
+                        latex.push_str(&text);
+                    }
+                }
+                Event::End(TagEnd::CodeBlock) => {
+                    if let Some((info, latex)) = block.take()
+                        && is_numbered(&info, &latex)
+                    {
+                        number += 1;
+                        if let Some(label) = LABEL_RE.captures(&latex) {
+                            labels.entry(label[1].trim().to_string()).or_insert(number);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        Equations {
+            labels,
+            count: 0,
+            preview,
+        }
+    }
+
+    /// Renders an inline `$$...$$` expression.
+    pub fn inline(&self, latex: &str) -> String {
+        match latex_to_mathml(latex, DisplayStyle::Inline) {
+            Ok(mathml) => mathml,
+            Err(e) if self.preview => format!(
+                r#"<span class="math-error">{}: <code>{}</code></span>"#,
+                escape_html(&e.to_string()),
+                escape_html(latex)
+            ),
+            Err(_) => format!(r#"<code class="math">{}</code>"#, escape_html(latex)),
+        }
+    }
+
+    /// Renders a fenced `math` block as a display equation, numbering it if
+    /// asked to. Equations are numbered in the order they're rendered, which
+    /// matches the order [`Equations::new`] found them in.
+    pub fn display(&mut self, info: &str, latex: &str) -> String {
+        let number = if is_numbered(info, latex) {
+            self.count += 1;
+            Some(self.count)
+        } else {
+            None
+        };
+        let label = LABEL_RE.captures(latex).map(|c| c[1].to_string());
+        let stripped = LABEL_RE.replace_all(latex, "");
+
+        let mut html = String::from(r#"<div class="equation""#);
+        if let Some(label) = &label {
+            html.push_str(&format!(r#" id="{}""#, equation_id(label)));
+        }
+        html.push('>');
+        match latex_to_mathml(stripped.trim(), DisplayStyle::Block) {
+            Ok(mathml) => html.push_str(&mathml),
+            Err(e) if self.preview => html.push_str(&format!(
+                r#"<div class="math-error"><p>{}</p><pre>{}</pre></div>"#,
+                escape_html(&e.to_string()),
+                escape_html(latex)
+            )),
+            Err(_) => html.push_str(&format!(
+                r#"<pre class="math"><code>{}</code></pre>"#,
+                escape_html(latex)
+            )),
+        }
+        if let Some(number) = number {
+            html.push_str(&format!(
+                r#"<span class="equation-number">({})</span>"#,
+                number
+            ));
+        }
+        html.push_str("</div>");
+        html
+    }
+
+    /// Replaces `\ref{label}` and `\eqref{label}` in `text` with links to
+    /// the equation, or returns `None` if there are none. References to
+    /// labels that don't exist read "(??)", as they would in LaTeX.
+    pub fn references(&self, text: &str) -> Option<String> {
+        if !REF_RE.is_match(text) {
+            return None;
+        }
+        let mut html = String::new();
+        let mut last = 0;
+        for captures in REF_RE.captures_iter(text) {
+            let whole = captures.get(0).expect("Captures always have a match");
+            html.push_str(&escape_html(&text[last..whole.start()]));
+            html.push_str(&self.reference(&captures));
+            last = whole.end();
+        }
+        html.push_str(&escape_html(&text[last..]));
+        Some(html)
+    }
+
+    /// The labels `text` refers to that no equation has.
+    pub fn unknown_labels<'a>(&self, text: &'a str) -> Vec<&'a str> {
+        REF_RE
+            .captures_iter(text)
+            .filter_map(|c| c.get(1))
+            .map(|m| m.as_str().trim())
+            .filter(|label| !self.labels.contains_key(*label))
+            .collect()
+    }
+
+    fn reference(&self, captures: &Captures) -> String {
+        let label = captures[1].trim();
+        match self.labels.get(label) {
+            Some(number) => format!(
+                r##"<a class="equation-ref" href="#{}">({})</a>"##,
+                equation_id(label),
+                number
+            ),
+            None if self.preview => format!(
+                r#"<span class="math-error">No equation labelled <code>{}</code></span>"#,
+                escape_html(label)
+            ),
+            None => "(??)".into(),
+        }
+    }
+}
+
+#[test]
+fn numbers_and_references_equations() {
+    let content = "See \\eqref{euler} and \\ref{nope}.\n\n```math\ne^{i\\pi} + 1 = 0 \\label{euler}\n```\n\n```math\nx = 1\n```\n\n```math numbered\ny = 2\n```\n";
+    let mut equations = Equations::new(Parser::new(content), false);
+    assert_eq!(
+        equations
+            .references("See \\eqref{euler} and \\ref{nope}.")
+            .unwrap(),
+        r##"See <a class="equation-ref" href="#eq-euler">(1)</a> and (??)."##
+    );
+    assert_eq!(equations.references("No references"), None);
+    assert_eq!(
+        equations.unknown_labels("\\ref{euler} \\eqref{ nope }"),
+        vec!["nope"]
+    );
+
+    let first = equations.display("math", "e^{i\\pi} + 1 = 0 \\label{euler}");
+    assert!(first.starts_with(r#"<div class="equation" id="eq-euler"><math"#));
+    assert!(first.ends_with(r#"<span class="equation-number">(1)</span></div>"#));
+    assert!(
+        !equations
+            .display("math", "x = 1")
+            .contains("equation-number")
+    );
+    assert!(equations.display("math numbered", "y = 2").contains("(2)"));
+
+    // Errors show the expression, and only say what's wrong in previews.
+    assert_eq!(
+        equations.inline("\\frac{1"),
+        r#"<code class="math">\frac{1</code>"#
+    );
+    let preview = Equations::new(Parser::new(content), true);
+    assert!(
+        preview
+            .inline("\\frac{1")
+            .starts_with(r#"<span class="math-error">"#)
+    );
+}
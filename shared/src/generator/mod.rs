@@ -1,23 +1,59 @@
 use crate::database::connect_db;
-use crate::types::{CommonData, HydratedPost, ImageMetadata, Link, Media, PageLink};
+use crate::referencing::{CitationStyle, Reference};
+use crate::settings::DEFAULT_CODE_THEME;
+use crate::types::{
+    CommonData, HydratedPost, ImageMetadata, Link, LinkTarget, MarkdownExtension, Media, PageLink,
+    TagFeeds,
+};
 use anyhow::anyhow;
 use cgi::{html_response, text_response};
 use chrono::{Datelike, Utc};
-use posts::generate_post_html;
-use sqlx::PgPool;
+use editions::generate_edition_pages;
+use feeds::{
+    feed_links, generate_atom_feed, generate_json_feed, generate_rss_feed, generate_tag_feeds,
+    tag_feed_title, tag_output_dir,
+};
+use index::{generate_index_pages, generate_tag_indexes};
+use manifest::Manifest;
+use month_index::generate_month_index_pages;
+use navigation::PostNavigation;
+use pages::generate_pages;
+use posts::{generate_post_html, generate_post_page};
+use series::{generate_series_pages, series_context};
+use sitemap::{generate_robots, generate_sitemap};
+use sqlx::{PgConnection, PgPool};
 use sqlx::{query, query_as, types::Json};
+use staging::Staging;
+use static_content::generate_static;
 use std::collections::HashMap;
-use templates::load_templates;
+use templates::{blog_post_url, markdown_options};
+use tokio::fs::create_dir_all;
 use types::Generator;
+use wiki_links::mentions;
+use year_index::generate_year_index_pages;
 pub mod activitypub;
+pub mod data_tables;
+pub mod editions;
 pub mod feeds;
+pub mod headings;
+pub mod highlight;
 pub mod index;
+pub mod manifest;
+pub mod math;
 pub mod month_index;
+pub mod navigation;
 pub mod pages;
 pub mod posts;
+pub mod series;
+pub mod shortcodes;
+pub mod sidenotes;
+pub mod sitemap;
+pub mod staging;
 pub mod static_content;
 pub mod templates;
 pub mod types;
+pub mod validation;
+pub mod wiki_links;
 pub mod year_index;
 
 pub async fn external_preview(id: i32) -> anyhow::Result<cgi::Response> {
@@ -28,6 +64,7 @@ pub async fn external_preview(id: i32) -> anyhow::Result<cgi::Response> {
 SELECT
     posts.id as id,
     post_date,
+    updated_date,
     url_slug,
     title,
     body,
@@ -36,8 +73,9 @@ SELECT
     summary,
 	site_id,
     users.display_name AS author_name,
-    (SELECT COUNT(*) FROM comments WHERE comments.post_id = posts.id) AS comment_count,
-    (SELECT array_agg(t.name) FROM tags t INNER JOIN post_tag pt ON pt.tag_id = t.id WHERE pt.post_id = posts.id) AS tags
+    (SELECT COUNT(*) FROM comments WHERE comments.post_id = posts.id AND comments.status = 'approved') AS comment_count,
+    (SELECT array_agg(t.name) FROM tags t INNER JOIN post_tag pt ON pt.tag_id = t.id WHERE pt.post_id = posts.id) AS tags,
+    (SELECT MIN(e.number) FROM editions e INNER JOIN edition_posts ep ON ep.edition_id = e.id WHERE ep.post_id = posts.id AND e.date <= CURRENT_TIMESTAMP) AS edition
 FROM posts
 INNER JOIN users
 ON users.id = posts.author_id
@@ -52,16 +90,13 @@ AND posts.id=$1
     match maybe_post {
         Some(post) => {
             let common = get_common(&connection, post.site_id).await?;
-            let tera = load_templates(&connection, post.site_id, &common).await?;
-
-            let generator = Generator {
-                output_path: "",
-                pool: &connection,
-                common: &common,
-                tera,
-                site_id: post.site_id,
-            };
-            let html = generate_post_html(&generator, &post).await?;
+            let posts = get_published_posts(&connection, post.site_id).await?;
+            let navigation = PostNavigation::new(&post, &posts, &common)?;
+            let generator = Generator::new("", &connection, &common, post.site_id).await?;
+            let series =
+                series_context(&connection, post.id, common.timezone, &common.base_url).await?;
+            let html =
+                generate_post_html(&generator, &post, &navigation, series.as_ref(), &[]).await?;
 
             Ok(html_response(200, html))
         }
@@ -69,6 +104,201 @@ AND posts.id=$1
     }
 }
 
+/// What a regeneration run did.
+#[derive(Debug, Default)]
+pub struct RegenerationSummary {
+    pub written: usize,
+    pub skipped: usize,
+    pub removed: usize,
+}
+
+/// Regenerates the site into a new generation and makes it live at
+/// `{output_path_base}/{site_id}`. Unless `force` is set only files whose
+/// inputs changed since the last run are written. If anything fails the live
+/// site is left as it was.
+pub async fn regenerate_site(
+    connection: &PgPool,
+    site_id: i32,
+    output_path_base: &str,
+    force: bool,
+) -> anyhow::Result<RegenerationSummary> {
+    let mut transaction = connection.begin().await?;
+    lock_site(&mut transaction, site_id).await?;
+
+    let posts = get_published_posts(connection, site_id).await?;
+    if posts.is_empty() {
+        return Ok(RegenerationSummary::default());
+    }
+    let common = get_common(connection, site_id).await?;
+    let manifest = Manifest::load(connection, site_id, force).await?;
+
+    let staging = Staging::prepare(output_path_base, site_id)?;
+    let summary = match build_generation(
+        connection,
+        &mut transaction,
+        &staging,
+        &posts,
+        &common,
+        manifest,
+    )
+    .await
+    {
+        Ok(summary) => summary,
+        Err(e) => {
+            staging.discard()?;
+            return Err(e);
+        }
+    };
+
+    // The manifest is only committed once the new generation is live, and
+    // the site goes back to the old generation if it can't be.
+    if let Err(e) = staging.activate() {
+        staging.discard()?;
+        return Err(e);
+    }
+    if let Err(e) = transaction.commit().await {
+        staging.revert()?;
+        return Err(e.into());
+    }
+    staging.finish()?;
+    Ok(summary)
+}
+
+/// Renders the site into `staging`, clears out files that are no longer
+/// generated and saves the manifest in `transaction` without committing it.
+async fn build_generation(
+    connection: &PgPool,
+    transaction: &mut PgConnection,
+    staging: &Staging,
+    posts: &[HydratedPost],
+    common: &CommonData,
+    manifest: Manifest,
+) -> anyhow::Result<RegenerationSummary> {
+    let manifest = generate_site(connection, staging, posts, common, manifest).await?;
+    let summary = RegenerationSummary {
+        written: manifest.written,
+        skipped: manifest.skipped,
+        removed: staging.remove_orphans(&manifest)?,
+    };
+    manifest.save(transaction).await?;
+    Ok(summary)
+}
+
+/// Waits for any other regeneration or rollback of the site to finish, then
+/// holds the site until `transaction` ends. Without this two runs could link
+/// from and prune the same generations and leave a manifest that doesn't
+/// match the live files.
+async fn lock_site(transaction: &mut PgConnection, site_id: i32) -> anyhow::Result<()> {
+    query!(
+        "SELECT pg_advisory_xact_lock(hashtext('regenerate_site'), $1)::text AS locked",
+        site_id
+    )
+    .fetch_one(transaction)
+    .await?;
+    Ok(())
+}
+
+/// Rolls the site back to the previous generation. The manifest describes
+/// the generation being abandoned, so the next regeneration is a full one.
+pub async fn rollback_site(
+    connection: &PgPool,
+    site_id: i32,
+    output_path_base: &str,
+) -> anyhow::Result<String> {
+    let mut transaction = connection.begin().await?;
+    lock_site(&mut transaction, site_id).await?;
+    // Forgetting the manifest only costs a full rebuild, so it's done first
+    // and outside the lock's transaction.
+    Manifest::clear(&mut *connection.acquire().await?, site_id).await?;
+    let generation = staging::rollback(output_path_base, site_id)?;
+    transaction.commit().await?;
+    Ok(generation)
+}
+
+async fn generate_site(
+    connection: &PgPool,
+    staging: &Staging,
+    posts: &[HydratedPost],
+    common: &CommonData,
+    manifest: Manifest,
+) -> anyhow::Result<Manifest> {
+    let output_path = staging.path()?;
+    create_dir_all(format!("{}/static", output_path)).await?;
+    let generator = Generator::new(output_path, connection, common, common.site_id)
+        .await?
+        .with_manifest(manifest);
+
+    let mentions = mentions(posts, common, markdown_options(&common.markdown_extensions))?;
+    for post in posts {
+        generate_post_page(&generator, post, posts, &mentions).await?;
+    }
+
+    generate_index_pages(
+        posts.iter().collect::<Vec<&HydratedPost>>().into_iter(),
+        &generator,
+    )
+    .await?;
+
+    generate_month_index_pages(posts, &generator).await?;
+    generate_year_index_pages(posts, &generator).await?;
+    generate_rss_feed(posts, &generator).await?;
+    generate_atom_feed(posts, &generator).await?;
+    generate_json_feed(posts, &generator).await?;
+    generate_tag_indexes(posts, &generator).await?;
+    generate_tag_feeds(posts, &generator).await?;
+    generate_edition_pages(posts, &generator).await?;
+    generate_series_pages(posts, &generator).await?;
+    generate_pages(&generator).await?;
+    generate_sitemap(posts, &generator).await?;
+    generate_robots(&generator).await?;
+    generate_static(&generator).await?;
+
+    let manifest = generator
+        .into_manifest()
+        .ok_or(anyhow!("Generator lost its manifest"))?;
+    staging.validate(&manifest)?;
+    Ok(manifest)
+}
+
+/// Published posts whose date has passed, newest first.
+pub async fn get_published_posts(
+    connection: &PgPool,
+    site_id: i32,
+) -> anyhow::Result<Vec<HydratedPost>> {
+    let posts = query_as!(
+        HydratedPost,
+        r#"
+SELECT
+    posts.id as id,
+    post_date,
+    updated_date,
+    url_slug,
+    title,
+    body,
+    song,
+    mood,
+    summary,
+    users.display_name AS author_name,
+    (SELECT COUNT(*) FROM comments WHERE comments.post_id = posts.id AND comments.status = 'approved') AS comment_count,
+    (SELECT array_agg(t.name) FROM tags t INNER JOIN post_tag pt ON pt.tag_id = t.id WHERE pt.post_id = posts.id) AS tags,
+    (SELECT MIN(e.number) FROM editions e INNER JOIN edition_posts ep ON ep.edition_id = e.id WHERE ep.post_id = posts.id AND e.date <= CURRENT_TIMESTAMP) AS edition,
+	site_id
+FROM posts
+INNER JOIN users
+ON users.id = posts.author_id
+WHERE state = 'published'
+AND posts.site_id = $1
+AND posts.post_date <= CURRENT_TIMESTAMP
+ORDER BY post_date DESC
+"#,
+        site_id
+    )
+    .fetch_all(connection)
+    .await?;
+
+    Ok(posts)
+}
+
 pub async fn get_common(connection: &PgPool, site_id: i32) -> anyhow::Result<CommonData> {
     let raw_settings = query!(
         "SELECT setting_name, value FROM blog_settings WHERE site_id=$1",
@@ -96,6 +326,10 @@ pub async fn get_common(connection: &PgPool, site_id: i32) -> anyhow::Result<Com
     .fetch_all(connection)
     .await?;
 
+    let site = query!("SELECT editions_enabled FROM sites WHERE id=$1", site_id)
+        .fetch_one(connection)
+        .await?;
+
     let earliest_post = query!(
         "SELECT post_date FROM posts WHERE site_id=$1 ORDER BY post_date ASC LIMIT 1",
         site_id
@@ -112,6 +346,51 @@ pub async fn get_common(connection: &PgPool, site_id: i32) -> anyhow::Result<Com
             .fetch_all(connection)
             .await?;
 
+    let base_url = settings
+        .get("base_url")
+        .ok_or(anyhow!("No blog URL set"))?
+        .to_owned();
+    let timezone = settings
+        .get("timezone")
+        .and_then(|x| x.parse().ok())
+        .unwrap_or(chrono_tz::UTC);
+
+    // Posts are added oldest first, so a reused slug links to the newest
+    // post. Posts win over pages with the same slug.
+    let mut link_targets: HashMap<String, LinkTarget> = page_links
+        .iter()
+        .map(|p| {
+            (
+                p.url_slug.clone(),
+                LinkTarget {
+                    title: p.title.clone(),
+                    url: format!("{}{}.html", base_url, p.url_slug),
+                },
+            )
+        })
+        .collect();
+    let linkable_posts = query!(
+        "SELECT url_slug, title, post_date FROM posts WHERE site_id=$1 AND state='published' AND post_date <= CURRENT_TIMESTAMP ORDER BY post_date ASC",
+        site_id
+    )
+    .fetch_all(connection)
+    .await?;
+    for post in linkable_posts {
+        let url = blog_post_url(
+            post.url_slug.clone(),
+            post.post_date,
+            timezone,
+            base_url.clone(),
+        )?;
+        link_targets.insert(
+            post.url_slug,
+            LinkTarget {
+                title: post.title,
+                url,
+            },
+        );
+    }
+
     let media = HashMap::from_iter(media_rows.into_iter().map(|m| -> (i32, Media) {
         (
             m.id,
@@ -123,11 +402,33 @@ pub async fn get_common(connection: &PgPool, site_id: i32) -> anyhow::Result<Com
         )
     }));
 
-    Ok(CommonData {
-        base_url: settings
-            .get("base_url")
-            .ok_or(anyhow!("No blog URL set"))?
-            .to_owned(),
+    let reference_library = query!(
+        r#"SELECT name, reference AS "reference: Json<Reference>" FROM reference_library WHERE site_id=$1"#,
+        site_id
+    )
+    .fetch_all(connection)
+    .await?
+    .into_iter()
+    .map(|r| (r.name, r.reference.0))
+    .collect();
+
+    let tags = query!(
+        "
+SELECT name FROM tags
+WHERE site_id=$1 AND EXISTS (
+    SELECT 1 FROM post_tag INNER JOIN posts ON posts.id = post_tag.post_id
+    WHERE post_tag.tag_id = tags.id AND posts.state='published' AND posts.post_date <= CURRENT_TIMESTAMP
+)
+ORDER BY name
+",
+        site_id
+    )
+    .fetch_all(connection)
+    .await?;
+
+    let mut common = CommonData {
+        site_id,
+        base_url,
         blog_name: settings
             .get("blog_name")
             .ok_or(anyhow!("No blog name set"))?
@@ -145,13 +446,48 @@ pub async fn get_common(connection: &PgPool, site_id: i32) -> anyhow::Result<Com
             .ok_or(anyhow!("No media url set"))?
             .to_owned(),
         archive_years: years,
-        timezone: settings
-            .get("timezone")
-            .and_then(|x| x.parse().ok())
-            .unwrap_or(chrono_tz::UTC)
-            .to_owned(),
+        timezone,
         links,
         page_links,
+        tag_feeds: vec![],
         media,
-    })
+        editions_enabled: site.editions_enabled,
+        code_theme: settings
+            .get("code_theme")
+            .filter(|t| !t.is_empty())
+            .cloned()
+            .unwrap_or(DEFAULT_CODE_THEME.into()),
+        code_theme_dark: settings
+            .get("code_theme_dark")
+            .filter(|t| !t.is_empty())
+            .cloned(),
+        code_line_numbers: settings
+            .get("code_line_numbers")
+            .is_some_and(|v| v == "true"),
+        heading_anchors: settings.get("heading_anchors").is_some_and(|v| v == "true"),
+        markdown_extensions: settings
+            .get("markdown_extensions")
+            .map(|e| MarkdownExtension::parse_list(e))
+            .unwrap_or_default(),
+        sidenotes: settings.get("sidenotes").is_some_and(|v| v == "true"),
+        citation_style: settings
+            .get("citation_style")
+            .map(|s| CitationStyle::parse(s))
+            .unwrap_or_default(),
+        preview: false,
+        link_targets,
+        reference_library,
+    };
+    common.tag_feeds = tags
+        .into_iter()
+        .map(|t| TagFeeds {
+            feeds: feed_links(
+                &common,
+                &tag_output_dir(&t.name),
+                &tag_feed_title(&common, &t.name),
+            ),
+            tag: t.name,
+        })
+        .collect();
+    Ok(common)
 }
@@ -3,10 +3,6 @@ use chrono::{Datelike, Month, NaiveDate, Utc};
 use num_traits::FromPrimitive;
 use serde::Serialize;
 use tera::Context;
-use tokio::{
-    fs::{create_dir_all, File},
-    io::AsyncWriteExt,
-};
 
 use crate::types::{CommonData, HydratedPost};
 
@@ -33,15 +29,7 @@ pub async fn generate_month_index_pages(
         let month_name = Month::from_u32(current_date.month())
             .ok_or(anyhow!("Bad month number"))?
             .name();
-        let index_dir = format!(
-            "{}/{}/{}",
-            generator.output_path,
-            current_date.year(),
-            month_name
-        );
-        create_dir_all(&index_dir).await?;
-
-        let mut file = File::create(format!("{}/{}", index_dir, "index.html")).await?;
+        let index_path = format!("{}/{}/index.html", current_date.year(), month_name);
         let mut month_posts: Vec<&HydratedPost> = posts
             .iter()
             .filter(|p| {
@@ -49,7 +37,7 @@ pub async fn generate_month_index_pages(
                 post_date.year() == current_date.year() && post_date.month() == current_date.month()
             })
             .collect();
-        month_posts.sort_by(|a, b| a.post_date.cmp(&b.post_date));
+        month_posts.sort_by_key(|a| a.post_date);
 
         let title = format!("{} {}", month_name, current_date.year());
         let page = MonthIndexPage {
@@ -58,11 +46,19 @@ pub async fn generate_month_index_pages(
             common: generator.common,
             date: &current_date,
         };
+        let deps = generator
+            .page_dependencies("month_index.html")
+            .value(&current_date)
+            .posts(page.posts.iter().copied(), generator.common);
 
-        let rendered = generator
-            .tera
-            .render("month_index.html", &Context::from_serialize(page)?)?;
-        file.write_all(rendered.as_bytes()).await?;
+        generator
+            .render_output(
+                &index_path,
+                &deps,
+                "month_index.html",
+                &Context::from_serialize(page)?,
+            )
+            .await?;
 
         current_date = current_date
             .checked_add_months(chrono::Months::new(1))
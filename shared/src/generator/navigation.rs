@@ -0,0 +1,197 @@
+use std::cmp::Reverse;
+
+use chrono::{DateTime, Utc};
+use itertools::Itertools;
+use serde::Serialize;
+
+use crate::{
+    types::{CommonData, HydratedPost},
+    utils::blog_post_url,
+};
+
+/// How many related posts a post page lists.
+const RELATED_POSTS: usize = 5;
+
+/// Just enough of a neighbouring post to link to it. Kept small so a new
+/// comment on a neighbour doesn't force every page linking to it to be
+/// rendered again.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct PostLink {
+    pub id: i32,
+    pub title: String,
+    pub url: String,
+    pub post_date: DateTime<Utc>,
+    pub summary: Option<String>,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct TagNavigation {
+    pub tag: String,
+    pub previous: Option<PostLink>,
+    pub next: Option<PostLink>,
+}
+
+/// The posts around a post: the ones either side of it by date, either
+/// side of it within each of its tags, and the ones sharing the most tags.
+#[derive(Serialize, Debug, Default, PartialEq)]
+pub struct PostNavigation {
+    pub previous: Option<PostLink>,
+    pub next: Option<PostLink>,
+    pub tags: Vec<TagNavigation>,
+    pub related: Vec<PostLink>,
+}
+
+impl PostNavigation {
+    /// Works out the navigation for `post` among `posts`. The post doesn't
+    /// have to be one of `posts`, so previews get navigation too.
+    pub fn new(
+        post: &HydratedPost,
+        posts: &[HydratedPost],
+        common: &CommonData,
+    ) -> anyhow::Result<PostNavigation> {
+        let others: Vec<&HydratedPost> = posts
+            .iter()
+            .filter(|p| p.id != post.id)
+            .sorted_by_key(|p| (p.post_date, p.id))
+            .collect();
+        let post_tags = post.tags.clone().unwrap_or_default();
+
+        let (previous, next) = neighbours(post, others.iter().copied());
+
+        let tags = post_tags
+            .iter()
+            .map(|tag| {
+                let (previous, next) =
+                    neighbours(post, others.iter().copied().filter(|p| has_tag(p, tag)));
+                Ok(TagNavigation {
+                    tag: tag.clone(),
+                    previous: link(previous, common)?,
+                    next: link(next, common)?,
+                })
+            })
+            .collect::<anyhow::Result<_>>()?;
+
+        let related = others
+            .iter()
+            .map(|p| (shared_tags(&post_tags, p), *p))
+            .filter(|(shared, _)| *shared > 0)
+            .sorted_by_key(|(shared, p)| (Reverse(*shared), Reverse(p.post_date)))
+            .take(RELATED_POSTS)
+            .map(|(_, p)| PostLink::new(p, common))
+            .collect::<anyhow::Result<_>>()?;
+
+        Ok(PostNavigation {
+            previous: link(previous, common)?,
+            next: link(next, common)?,
+            tags,
+            related,
+        })
+    }
+}
+
+impl PostLink {
+    fn new(post: &HydratedPost, common: &CommonData) -> anyhow::Result<PostLink> {
+        Ok(PostLink {
+            id: post.id,
+            title: post.title.clone(),
+            url: blog_post_url(
+                post.url_slug.clone(),
+                post.post_date,
+                common.timezone,
+                common.base_url.clone(),
+            )?,
+            post_date: post.post_date,
+            summary: post.summary.clone(),
+        })
+    }
+}
+
+fn link(post: Option<&HydratedPost>, common: &CommonData) -> anyhow::Result<Option<PostLink>> {
+    post.map(|p| PostLink::new(p, common)).transpose()
+}
+
+/// The last post before `post` and the first after it, from posts sorted
+/// oldest first.
+fn neighbours<'a>(
+    post: &HydratedPost,
+    sorted: impl Iterator<Item = &'a HydratedPost>,
+) -> (Option<&'a HydratedPost>, Option<&'a HydratedPost>) {
+    let mut previous = None;
+    for p in sorted {
+        if (p.post_date, p.id) < (post.post_date, post.id) {
+            previous = Some(p);
+        } else {
+            return (previous, Some(p));
+        }
+    }
+    (previous, None)
+}
+
+fn has_tag(post: &HydratedPost, tag: &str) -> bool {
+    post.tags.iter().flatten().any(|t| t == tag)
+}
+
+fn shared_tags(tags: &[String], post: &HydratedPost) -> usize {
+    tags.iter().filter(|t| has_tag(post, t)).count()
+}
+
+#[cfg(test)]
+fn test_post(id: i32, day: u32, tags: &[&str]) -> HydratedPost {
+    use chrono::TimeZone;
+
+    let date = Utc.with_ymd_and_hms(2024, 1, day, 12, 0, 0).unwrap();
+    HydratedPost {
+        id,
+        post_date: date,
+        updated_date: date,
+        url_slug: format!("post-{}", id),
+        title: format!("Post {}", id),
+        body: String::new(),
+        author_name: None,
+        comment_count: None,
+        song: None,
+        mood: None,
+        summary: None,
+        tags: Some(tags.iter().map(|t| t.to_string()).collect()),
+        site_id: 1,
+        edition: None,
+    }
+}
+
+#[test]
+fn navigation_by_date_tag_and_overlap() {
+    let common = CommonData {
+        site_id: 1,
+        base_url: "https://example.com/".into(),
+        ..Default::default()
+    };
+    // Newest first, the way the generator loads them.
+    let posts = vec![
+        test_post(5, 5, &["rust", "life"]),
+        test_post(4, 4, &["life"]),
+        test_post(3, 3, &["rust"]),
+        test_post(2, 2, &["rust", "life"]),
+        test_post(1, 1, &[]),
+    ];
+
+    let navigation = PostNavigation::new(&posts[2], &posts, &common).unwrap();
+    assert_eq!(navigation.previous.unwrap().id, 2);
+    assert_eq!(navigation.next.unwrap().id, 4);
+    assert_eq!(navigation.tags.len(), 1);
+    assert_eq!(navigation.tags[0].previous.as_ref().unwrap().id, 2);
+    assert_eq!(navigation.tags[0].next.as_ref().unwrap().id, 5);
+    assert_eq!(
+        navigation.tags[0].next.as_ref().unwrap().url,
+        "https://example.com/2024/January/post-5.html"
+    );
+    let related: Vec<i32> = navigation.related.iter().map(|p| p.id).collect();
+    assert_eq!(related, vec![5, 2]);
+
+    let navigation = PostNavigation::new(&posts[3], &posts, &common).unwrap();
+    let related: Vec<i32> = navigation.related.iter().map(|p| p.id).collect();
+    assert_eq!(related, vec![5, 4, 3]);
+
+    let navigation = PostNavigation::new(&posts[0], &posts, &common).unwrap();
+    assert_eq!(navigation.next, None);
+    assert!(navigation.tags.iter().all(|t| t.next.is_none()));
+}
@@ -1,11 +1,14 @@
 use crate::types::CommonData;
 
-use super::types::Generator;
+use super::{
+    headings::{TocEntry, table_of_contents},
+    templates::headings,
+    types::Generator,
+};
 use chrono::{DateTime, Utc};
 use serde::Serialize;
 use sqlx::query;
 use tera::Context;
-use tokio::{fs::File, io::AsyncWriteExt};
 
 #[derive(Serialize)]
 struct Page<'a> {
@@ -13,30 +16,51 @@ struct Page<'a> {
     title: String,
     body: String,
     last_updated: DateTime<Utc>,
+    toc: Vec<TocEntry>,
+    /// How many headings `toc` has at every level.
+    toc_size: usize,
 }
 
 pub async fn generate_pages(generator: &Generator<'_>) -> anyhow::Result<()> {
     let pages = query!(
-        "SELECT title, url_slug, body, date_updated FROM pages WHERE site_id=$1",
+        "SELECT id, title, url_slug, body, date_updated FROM pages WHERE site_id=$1",
         generator.site_id
     )
     .fetch_all(generator.pool)
     .await?;
 
     for page in pages {
+        let headings = headings(
+            &page.body,
+            &generator.common.markdown_extensions,
+            &generator.common.reference_library,
+        )?;
         let page_context = Page {
             common: generator.common,
+            toc: table_of_contents(&headings),
+            toc_size: headings.len(),
             title: page.title,
             body: page.body,
             last_updated: page.date_updated,
         };
-        let mut file =
-            File::create(format!("{}/{}.html", generator.output_path, page.url_slug)).await?;
+        let deps = generator
+            .page_dependencies("page.html")
+            .value(&page.id)
+            .value(&(
+                &page_context.title,
+                &page_context.body,
+                &page_context.last_updated,
+            ))
+            .markdown(&page_context.body, generator.common);
 
-        let rendered = generator
-            .tera
-            .render("page.html", &Context::from_serialize(page_context)?)?;
-        file.write_all(rendered.as_bytes()).await?;
+        generator
+            .render_output(
+                &format!("{}.html", page.url_slug),
+                &deps,
+                "page.html",
+                &Context::from_serialize(page_context)?,
+            )
+            .await?;
     }
     Ok(())
 }
@@ -50,8 +74,15 @@ pub async fn generate_single_page(id: i32, generator: &Generator<'_>) -> anyhow:
     .fetch_one(generator.pool)
     .await?;
 
+    let headings = headings(
+        &page.body,
+        &generator.common.markdown_extensions,
+        &generator.common.reference_library,
+    )?;
     let page_context = Page {
         common: generator.common,
+        toc: table_of_contents(&headings),
+        toc_size: headings.len(),
         title: page.title,
         body: page.body,
         last_updated: page.date_updated,
@@ -1,20 +1,25 @@
+use std::collections::HashMap;
+
 use anyhow::{Result, anyhow};
 use chrono::{Datelike, Month};
 use num_traits::FromPrimitive;
 use serde::Serialize;
 use sqlx::{query, query_as, types::Json};
 use tera::Context;
-use tokio::{
-    fs::{File, create_dir_all},
-    io::AsyncWriteExt,
-};
 
 use crate::{
     activities::Activity,
-    types::{CommonData, HydratedComment, HydratedPost},
+    types::{CommonData, HydratedComment, HydratedPost, LinkTarget},
 };
 
-use super::types::Generator;
+use super::{
+    headings::{TocEntry, table_of_contents},
+    manifest::Dependencies,
+    navigation::PostNavigation,
+    series::{SeriesContext, series_context},
+    templates::headings,
+    types::Generator,
+};
 
 #[derive(Serialize)]
 struct PostPage<'a> {
@@ -22,9 +27,21 @@ struct PostPage<'a> {
     post: &'a HydratedPost,
     common: &'a CommonData,
     comments: Vec<HydratedComment>,
+    navigation: &'a PostNavigation,
+    series: Option<&'a SeriesContext>,
+    toc: Vec<TocEntry>,
+    /// How many headings `toc` has at every level.
+    toc_size: usize,
+    mentioned_in: &'a [LinkTarget],
 }
 
-pub async fn generate_post_html(generator: &Generator<'_>, post: &HydratedPost) -> Result<String> {
+pub async fn generate_post_html(
+    generator: &Generator<'_>,
+    post: &HydratedPost,
+    navigation: &PostNavigation,
+    series: Option<&SeriesContext>,
+    mentioned_in: &[LinkTarget],
+) -> Result<String> {
     let comments = if post.id > 0 {
         query_as!(HydratedComment, "SELECT author_name, post_body, created_date FROM comments WHERE post_id=$1 AND status = 'approved' ORDER BY created_date ASC", post.id)
                                    .fetch_all(generator.pool).await?
@@ -32,11 +49,21 @@ pub async fn generate_post_html(generator: &Generator<'_>, post: &HydratedPost)
         vec![]
     };
 
+    let headings = headings(
+        &post.body,
+        &generator.common.markdown_extensions,
+        &generator.common.reference_library,
+    )?;
     let post_page = PostPage {
         title: &post.title,
         post,
         common: generator.common,
         comments,
+        navigation,
+        series,
+        toc: table_of_contents(&headings),
+        toc_size: headings.len(),
+        mentioned_in,
     };
 
     Ok(generator
@@ -44,22 +71,44 @@ pub async fn generate_post_html(generator: &Generator<'_>, post: &HydratedPost)
         .render("post.html", &Context::from_serialize(&post_page)?)?)
 }
 
-pub async fn generate_post_page(generator: &Generator<'_>, post: &HydratedPost) -> Result<()> {
-    let rendered = generate_post_html(generator, post).await?;
+/// Writes the post's page and its ActivityPub note. `posts` is every
+/// published post, for the previous, next and related links, and `mentions`
+/// is the posts linking to each URL.
+pub async fn generate_post_page(
+    generator: &Generator<'_>,
+    post: &HydratedPost,
+    posts: &[HydratedPost],
+    mentions: &HashMap<String, Vec<LinkTarget>>,
+) -> Result<()> {
     let post_date = post.post_date.with_timezone(&generator.common.timezone);
     let month_name = Month::from_u32(post_date.month())
         .ok_or(anyhow!("Bad month number"))?
         .name();
-    let dir = format!(
-        "{}/{}/{}",
-        generator.output_path,
-        post_date.year(),
-        month_name
-    );
+    let dir = format!("{}/{}", post_date.year(), month_name);
     let post_path = format!("{}/{}.html", &dir, post.url_slug);
-    create_dir_all(&dir).await?;
-    let mut file = File::create(post_path).await?;
-    file.write_all(rendered.as_bytes()).await?;
+    let navigation = PostNavigation::new(post, posts, generator.common)?;
+    let series = series_context(
+        generator.pool,
+        post.id,
+        generator.common.timezone,
+        &generator.common.base_url,
+    )
+    .await?;
+    let mentioned_in = mentions
+        .get(&format!("{}{}", generator.common.base_url, post_path))
+        .map(Vec::as_slice)
+        .unwrap_or_default();
+    let deps = generator
+        .page_dependencies("post.html")
+        .post(post, generator.common)
+        .value(&navigation)
+        .value(&series)
+        .value(mentioned_in);
+    if !generator.is_current(&post_path, &deps).await? {
+        let rendered =
+            generate_post_html(generator, post, &navigation, series.as_ref(), mentioned_in).await?;
+        generator.write_output(&post_path, &deps, &rendered).await?;
+    }
 
     let activitypub = query!(
         r#"SELECT activity AS "activity: Json<Activity>" FROM activitypub_outbox WHERE source_post=$1"#,
@@ -68,15 +117,15 @@ pub async fn generate_post_page(generator: &Generator<'_>, post: &HydratedPost)
     .fetch_optional(generator.pool)
     .await?;
 
-    if let Some(row) = activitypub {
-        if let Activity::Create(create) = row.activity.as_ref() {
-            if let Activity::Note(note) = create.object() {
-                let json_path = format!("{}/{}.json", &dir, post.url_slug);
-                let mut json_file = File::create(json_path).await?;
-                json_file
-                    .write_all(serde_json::to_string(note)?.as_bytes())
-                    .await?;
-            }
+    if let Some(row) = activitypub
+        && let Activity::Create(create) = row.activity.as_ref()
+        && let Activity::Note(note) = create.object()
+    {
+        let json_path = format!("{}/{}.json", &dir, post.url_slug);
+        let json = serde_json::to_string(note)?;
+        let deps = Dependencies::new().value(&json);
+        if !generator.is_current(&json_path, &deps).await? {
+            generator.write_output(&json_path, &deps, &json).await?;
         }
     }
 
@@ -0,0 +1,200 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{PgPool, query};
+use tera::Context;
+
+use crate::{
+    types::{CommonData, HydratedPost},
+    utils::blog_post_url,
+};
+
+use super::types::Generator;
+
+#[derive(Serialize, Debug)]
+pub struct SeriesPart {
+    pub id: i32,
+    pub part: usize,
+    pub title: String,
+    pub url: String,
+    pub post_date: DateTime<Utc>,
+    pub current: bool,
+}
+
+/// Where a post sits in its series, for "Part 3 of 5" and links to the
+/// other parts. Parts are numbered among the published posts only.
+#[derive(Serialize, Debug)]
+pub struct SeriesContext {
+    pub name: String,
+    pub url_slug: String,
+    pub url: String,
+    pub part: usize,
+    pub total: usize,
+    pub parts: Vec<SeriesPart>,
+}
+
+#[derive(Serialize)]
+struct SeriesPage<'a> {
+    title: &'a str,
+    common: &'a CommonData,
+    series: &'a SeriesContext,
+    posts: Vec<&'a HydratedPost>,
+}
+
+#[derive(Serialize)]
+struct SeriesIndexPage<'a> {
+    title: &'a str,
+    common: &'a CommonData,
+    series: &'a [SeriesContext],
+}
+
+/// Where a series' index page lives, relative to the site root.
+pub fn series_output_dir(url_slug: &str) -> String {
+    format!("series/{}/", url_slug)
+}
+
+/// The series `post_id` belongs to. The post itself is included even if it
+/// isn't published yet, so previews show where it will sit.
+pub async fn series_context(
+    connection: &PgPool,
+    post_id: i32,
+    timezone: chrono_tz::Tz,
+    base_url: &str,
+) -> anyhow::Result<Option<SeriesContext>> {
+    let rows = query!(
+        r#"
+SELECT
+    s.name,
+    s.url_slug AS series_slug,
+    p.id,
+    p.title,
+    p.url_slug,
+    p.post_date
+FROM series s
+INNER JOIN series_posts sp
+ON sp.series_id = s.id
+INNER JOIN posts p
+ON p.id = sp.post_id
+WHERE s.id = (SELECT series_id FROM series_posts WHERE post_id = $1)
+AND (p.id = $1 OR (p.state = 'published' AND p.post_date <= CURRENT_TIMESTAMP))
+ORDER BY sp.position, p.post_date
+"#,
+        post_id
+    )
+    .fetch_all(connection)
+    .await?;
+
+    let Some(first) = rows.first() else {
+        return Ok(None);
+    };
+    let name = first.name.clone();
+    let url_slug = first.series_slug.clone();
+
+    let mut parts = Vec::with_capacity(rows.len());
+    for (index, row) in rows.into_iter().enumerate() {
+        parts.push(SeriesPart {
+            id: row.id,
+            part: index + 1,
+            url: blog_post_url(row.url_slug, row.post_date, timezone, base_url.to_string())?,
+            title: row.title,
+            post_date: row.post_date,
+            current: row.id == post_id,
+        });
+    }
+    let part = parts.iter().position(|p| p.current).unwrap_or_default() + 1;
+
+    Ok(Some(SeriesContext {
+        url: format!("{}{}", base_url, series_output_dir(&url_slug)),
+        name,
+        url_slug,
+        part,
+        total: parts.len(),
+        parts,
+    }))
+}
+
+/// Writes an index page for every series with published posts, plus a list
+/// of all of them at `series/`.
+pub async fn generate_series_pages(
+    posts: &[HydratedPost],
+    generator: &Generator<'_>,
+) -> anyhow::Result<()> {
+    let common = generator.common;
+    let first_posts = query!(
+        r#"
+SELECT DISTINCT ON (sp.series_id) sp.post_id
+FROM series_posts sp
+INNER JOIN series s
+ON s.id = sp.series_id
+INNER JOIN posts p
+ON p.id = sp.post_id
+WHERE s.site_id = $1
+AND p.state = 'published'
+AND p.post_date <= CURRENT_TIMESTAMP
+ORDER BY sp.series_id, sp.position, p.post_date
+"#,
+        generator.site_id
+    )
+    .fetch_all(generator.pool)
+    .await?;
+
+    let mut all_series = Vec::with_capacity(first_posts.len());
+    for row in first_posts {
+        if let Some(series) = series_context(
+            generator.pool,
+            row.post_id,
+            common.timezone,
+            &common.base_url,
+        )
+        .await?
+        {
+            all_series.push(series);
+        }
+    }
+    all_series.sort_by(|a, b| a.name.cmp(&b.name));
+
+    for series in &all_series {
+        let series_posts: Vec<&HydratedPost> = series
+            .parts
+            .iter()
+            .filter_map(|part| posts.iter().find(|p| p.id == part.id))
+            .collect();
+        let page = SeriesPage {
+            title: &series.name,
+            common,
+            series,
+            posts: series_posts,
+        };
+        let deps = generator
+            .page_dependencies("series.html")
+            .value(series)
+            .posts(page.posts.iter().copied(), generator.common);
+        generator
+            .render_output(
+                &format!("{}index.html", series_output_dir(&series.url_slug)),
+                &deps,
+                "series.html",
+                &Context::from_serialize(page)?,
+            )
+            .await?;
+    }
+
+    if all_series.is_empty() {
+        return Ok(());
+    }
+    let page = SeriesIndexPage {
+        title: "Series",
+        common,
+        series: &all_series,
+    };
+    let deps = generator
+        .page_dependencies("series_index.html")
+        .value(&all_series);
+    generator
+        .render_output(
+            "series/index.html",
+            &deps,
+            "series_index.html",
+            &Context::from_serialize(page)?,
+        )
+        .await
+}
@@ -0,0 +1,255 @@
+use std::{collections::HashMap, ops::Range, sync::LazyLock};
+
+use pulldown_cmark::{Event, Options, Parser, Tag};
+use regex::Regex;
+use tera::{Context, Tera, escape_html};
+
+static SHORTCODE_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"\{\{<\s*(/)?\s*([A-Za-z][\w-]*)(.*?)>\}\}")
+        .expect("Could not create shortcode regex")
+});
+static ARGUMENT_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"(?:([A-Za-z_]\w*)=)?(?:"([^"]*)"|(\S+))"#)
+        .expect("Could not create shortcode argument regex")
+});
+static MARKER_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"<!--/?shortcode:\d+-->\n?").expect("Could not create shortcode marker regex")
+});
+
+/// Where a shortcode's partial lives among the templates.
+pub fn shortcode_template(name: &str) -> String {
+    format!("shortcodes/{}.html", name)
+}
+
+/// A `{{< name args >}}` shortcode, or a `{{< name >}}...{{< /name >}}` pair
+/// wrapped around some markdown.
+#[derive(Debug, PartialEq)]
+pub struct Shortcode {
+    pub name: String,
+    pub args: Vec<String>,
+    pub named: HashMap<String, String>,
+    /// Where the opening tag is in the source.
+    pub range: Range<usize>,
+    /// Where the closing tag is, for a pair.
+    pub closing: Option<Range<usize>>,
+}
+
+/// A closing tag with nothing to close.
+#[derive(Debug, PartialEq)]
+pub struct UnmatchedClose {
+    pub name: String,
+    pub range: Range<usize>,
+}
+
+/// Splits `arguments` into positional ones and `name=value` ones. Values
+/// with spaces go in double quotes.
+pub(crate) fn parse_arguments(arguments: &str) -> (Vec<String>, HashMap<String, String>) {
+    let mut args = Vec::new();
+    let mut named = HashMap::new();
+    for c in ARGUMENT_RE.captures_iter(arguments) {
+        let value = c
+            .get(2)
+            .or(c.get(3))
+            .map(|v| v.as_str().to_string())
+            .unwrap_or_default();
+        match c.get(1) {
+            Some(name) => {
+                named.insert(name.as_str().to_string(), value);
+            }
+            None => args.push(value),
+        }
+    }
+    (args, named)
+}
+
+/// The parts of `source` that are code, where shortcodes are left alone.
+fn code_ranges(source: &str, options: Options) -> Vec<Range<usize>> {
+    Parser::new_ext(source, options)
+        .into_offset_iter()
+        .filter(|(event, _)| matches!(event, Event::Code(_) | Event::Start(Tag::CodeBlock(_))))
+        .map(|(_, range)| range)
+        .collect()
+}
+
+/// Finds the shortcodes in `source` outside of code. An opening tag is
+/// paired with the next closing tag of the same name, otherwise it stands
+/// alone.
+pub fn find_shortcodes(source: &str, options: Options) -> (Vec<Shortcode>, Vec<UnmatchedClose>) {
+    let code = code_ranges(source, options);
+    let mut shortcodes: Vec<Shortcode> = Vec::new();
+    let mut unmatched = Vec::new();
+    for c in SHORTCODE_RE.captures_iter(source) {
+        let whole = c.get(0).expect("Captures always have a match");
+        if code.iter().any(|r| r.contains(&whole.start())) {
+            continue;
+        }
+        let name = c[2].to_string();
+        if c.get(1).is_some() {
+            match shortcodes
+                .iter_mut()
+                .rev()
+                .find(|s| s.name == name && s.closing.is_none())
+            {
+                Some(open) => open.closing = Some(whole.range()),
+                None => unmatched.push(UnmatchedClose {
+                    name,
+                    range: whole.range(),
+                }),
+            }
+        } else {
+            let (args, named) = parse_arguments(&c[3]);
+            shortcodes.push(Shortcode {
+                name,
+                args,
+                named,
+                range: whole.range(),
+                closing: None,
+            });
+        }
+    }
+    (shortcodes, unmatched)
+}
+
+/// Shortcodes swapped out of a post for HTML comment markers, which markdown
+/// passes through untouched, ready to be rendered into the finished HTML.
+pub struct Shortcodes {
+    shortcodes: Vec<(Shortcode, String, Option<String>)>,
+}
+
+impl Shortcodes {
+    /// Replaces every shortcode in `source` with a marker, returning the
+    /// marked up source.
+    pub fn extract(source: &str, options: Options) -> (String, Shortcodes) {
+        let (found, _) = find_shortcodes(source, options);
+        let mut replacements: Vec<(Range<usize>, String)> = Vec::new();
+        let mut shortcodes = Vec::with_capacity(found.len());
+        for (index, shortcode) in found.into_iter().enumerate() {
+            let open_source = source[shortcode.range.clone()].to_string();
+            replacements.push((
+                shortcode.range.clone(),
+                format!("<!--shortcode:{}-->", index),
+            ));
+            let close_source = shortcode.closing.clone().map(|closing| {
+                replacements.push((closing.clone(), format!("<!--/shortcode:{}-->", index)));
+                source[closing].to_string()
+            });
+            shortcodes.push((shortcode, open_source, close_source));
+        }
+        replacements.sort_by_key(|(range, _)| range.start);
+
+        let mut marked = String::with_capacity(source.len());
+        let mut last = 0;
+        for (range, marker) in replacements {
+            marked.push_str(&source[last..range.start]);
+            marked.push_str(&marker);
+            last = range.end;
+        }
+        marked.push_str(&source[last..]);
+        (marked, Shortcodes { shortcodes })
+    }
+
+    /// Renders each shortcode's partial in place of its markers. Inner
+    /// shortcodes come later in the list, so going backwards renders them
+    /// before the ones wrapped around them. Shortcodes that can't be rendered
+    /// are shown as written, or with the reason in previews.
+    pub fn render(&self, mut html: String, partials: &Tera, preview: bool) -> String {
+        for (index, (shortcode, open_source, close_source)) in
+            self.shortcodes.iter().enumerate().rev()
+        {
+            let open = format!("<!--shortcode:{}-->", index);
+            let Some(start) = html.find(&open) else {
+                continue;
+            };
+            let close = format!("<!--/shortcode:{}-->", index);
+            let (body, end) = match html[start..].find(&close) {
+                Some(close_start) => (
+                    Some(html[start + open.len()..start + close_start].to_string()),
+                    start + close_start + close.len(),
+                ),
+                None => (None, start + open.len()),
+            };
+
+            let rendered =
+                render_partial(shortcode, body.as_deref(), partials).unwrap_or_else(|e| {
+                    let written = format!(
+                        "{}{}{}",
+                        escape_html(open_source),
+                        body.as_deref().unwrap_or_default(),
+                        close_source.as_deref().map(escape_html).unwrap_or_default()
+                    );
+                    if preview {
+                        format!(
+                            r#"<span class="shortcode-error">{}</span>{}"#,
+                            escape_html(&e),
+                            written
+                        )
+                    } else {
+                        written
+                    }
+                });
+            html.replace_range(start..end, &rendered);
+        }
+        // A pair split by the cut loses its closing marker.
+        MARKER_RE.replace_all(&html, "").to_string()
+    }
+}
+
+fn render_partial(
+    shortcode: &Shortcode,
+    body: Option<&str>,
+    partials: &Tera,
+) -> Result<String, String> {
+    let template = shortcode_template(&shortcode.name);
+    if !partials.get_template_names().any(|t| t == template) {
+        return Err(format!("Unknown shortcode `{}`", shortcode.name));
+    }
+    let mut context = Context::new();
+    for (name, value) in &shortcode.named {
+        context.insert(name, value);
+    }
+    context.insert("args", &shortcode.args);
+    if let Some(body) = body {
+        context.insert("body", body.trim());
+    }
+    partials
+        .render(&template, &context)
+        .map_err(|e| format!("Could not render shortcode `{}`: {}", shortcode.name, e))
+}
+
+#[test]
+fn finds_shortcodes_outside_code() {
+    let source = "{{< youtube abc123 start=\"1m 30s\" >}}\n\n`{{< toot x >}}`\n\n{{< note kind=warn >}}\nHi\n{{< /note >}}\n{{< /oops >}}";
+    let (shortcodes, unmatched) = find_shortcodes(source, Options::empty());
+    assert_eq!(shortcodes.len(), 2);
+    assert_eq!(shortcodes[0].name, "youtube");
+    assert_eq!(shortcodes[0].args, vec!["abc123"]);
+    assert_eq!(shortcodes[0].named["start"], "1m 30s");
+    assert_eq!(shortcodes[0].closing, None);
+    assert_eq!(shortcodes[1].name, "note");
+    assert_eq!(shortcodes[1].named["kind"], "warn");
+    assert!(shortcodes[1].closing.is_some());
+    assert_eq!(unmatched[0].name, "oops");
+}
+
+#[test]
+fn renders_partials_around_markdown() {
+    let mut partials = Tera::default();
+    partials
+        .add_raw_template(
+            "shortcodes/note.html",
+            r#"<aside class="{{ kind }}">{{ body|safe }}</aside>"#,
+        )
+        .unwrap();
+    let source = "{{< note kind=tip >}}\nSome *words*\n{{< /note >}}\n\n{{< nope >}}\n";
+    let (marked, shortcodes) = Shortcodes::extract(source, Options::empty());
+    let mut html = String::new();
+    pulldown_cmark::html::push_html(&mut html, Parser::new(&marked));
+
+    let rendered = shortcodes.render(html.clone(), &partials, false);
+    assert_eq!(
+        rendered,
+        "<aside class=\"tip\"><p>Some <em>words</em></p></aside>\n{{&lt; nope &gt;}}\n"
+    );
+    let preview = shortcodes.render(html, &partials, true);
+    assert!(preview.contains(r#"<span class="shortcode-error">Unknown shortcode `nope`</span>"#));
+}
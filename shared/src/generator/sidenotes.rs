@@ -0,0 +1,140 @@
+use std::collections::HashMap;
+
+use pulldown_cmark::{CowStr, Event, Tag, TagEnd};
+
+/// Footnotes starting with this are margin notes, which aren't numbered.
+const MARGIN_NOTE_MARKER: &str = "{-}";
+
+/// Moves each footnote to where it's referenced, as a sidenote in the
+/// margin. On narrow screens the note's number toggles it open inline, which
+/// works without JavaScript.
+pub fn sidenotes(events: Vec<Event>) -> Vec<Event> {
+    let mut notes: HashMap<CowStr, Vec<Event>> = HashMap::new();
+    let mut body = Vec::with_capacity(events.len());
+    let mut definition: Option<(CowStr, Vec<Event>)> = None;
+    for event in events {
+        match event {
+            Event::Start(Tag::FootnoteDefinition(label)) => definition = Some((label, Vec::new())),
+            Event::End(TagEnd::FootnoteDefinition) => {
+                if let Some((label, events)) = definition.take() {
+                    notes.insert(label, events);
+                }
+            }
+            event => match definition.as_mut() {
+                Some((_, events)) => events.push(event),
+                None => body.push(event),
+            },
+        }
+    }
+
+    let mut numbers: HashMap<CowStr, usize> = HashMap::new();
+    let mut references = 0;
+    body.into_iter()
+        .map(|event| match event {
+            Event::FootnoteReference(label) if notes.contains_key(&label) => {
+                references += 1;
+                let note = &notes[&label];
+                let number = (!is_margin_note(note)).then(|| {
+                    let next = numbers.len() + 1;
+                    *numbers.entry(label.clone()).or_insert(next)
+                });
+                Event::InlineHtml(note_html(note, number, references).into())
+            }
+            event => event,
+        })
+        .collect()
+}
+
+/// Leaves footnotes out altogether, for excerpts.
+pub fn strip_footnotes<'a>(
+    events: impl Iterator<Item = Event<'a>>,
+) -> impl Iterator<Item = Event<'a>> {
+    let mut in_definition = false;
+    events.filter(move |event| match event {
+        Event::Start(Tag::FootnoteDefinition(_)) => {
+            in_definition = true;
+            false
+        }
+        Event::End(TagEnd::FootnoteDefinition) => {
+            in_definition = false;
+            false
+        }
+        Event::FootnoteReference(_) => false,
+        _ => !in_definition,
+    })
+}
+
+fn is_margin_note(events: &[Event]) -> bool {
+    events
+        .iter()
+        .find_map(|event| match event {
+            Event::Text(text) => Some(text.trim_start().starts_with(MARGIN_NOTE_MARKER)),
+            _ => None,
+        })
+        .unwrap_or(false)
+}
+
+/// The note's paragraphs are run together with line breaks, as a sidenote
+/// sits inside the paragraph that refers to it. Margin notes have no number.
+fn note_html(events: &[Event], number: Option<usize>, reference: usize) -> String {
+    let mut first_text = true;
+    let mut paragraphs = 0;
+    let inline = events.iter().filter_map(|event| match event {
+        Event::Start(Tag::Paragraph) => {
+            paragraphs += 1;
+            (paragraphs > 1).then_some(Event::InlineHtml("<br />".into()))
+        }
+        Event::End(TagEnd::Paragraph) => None,
+        Event::Text(text) if first_text => {
+            first_text = false;
+            match text.trim_start().strip_prefix(MARGIN_NOTE_MARKER) {
+                Some(rest) => Some(Event::Text(rest.trim_start().to_string().into())),
+                None => Some(event.clone()),
+            }
+        }
+        event => Some(event.clone()),
+    });
+    let mut content = String::new();
+    pulldown_cmark::html::push_html(&mut content, inline);
+
+    let id = format!("sn-{}", reference);
+    match number {
+        None => format!(
+            r#"<label for="{id}" class="margin-toggle" aria-label="Margin note">&#8853;</label><input type="checkbox" id="{id}" class="margin-toggle"><span class="marginnote">{}</span>"#,
+            content.trim()
+        ),
+        Some(number) => format!(
+            r#"<label for="{id}" class="margin-toggle sidenote-number">{number}</label><input type="checkbox" id="{id}" class="margin-toggle"><span class="sidenote"><span class="sidenote-number">{number}</span> {}</span>"#,
+            content.trim()
+        ),
+    }
+}
+
+#[test]
+fn moves_footnotes_beside_their_references() {
+    use pulldown_cmark::{Options, Parser};
+
+    let source = "One[^a] two[^m] three[^a] four[^b].\n\n[^a]: A *note*.\n\n    More.\n\n[^m]: {-} In the margin.\n\n[^b]: Second.\n";
+    let mut html = String::new();
+    pulldown_cmark::html::push_html(
+        &mut html,
+        sidenotes(Parser::new_ext(source, Options::ENABLE_FOOTNOTES).collect()).into_iter(),
+    );
+    assert_eq!(
+        html,
+        concat!(
+            r#"<p>One<label for="sn-1" class="margin-toggle sidenote-number">1</label><input type="checkbox" id="sn-1" class="margin-toggle"><span class="sidenote"><span class="sidenote-number">1</span> A <em>note</em>.<br />More.</span>"#,
+            r#" two<label for="sn-2" class="margin-toggle" aria-label="Margin note">&#8853;</label><input type="checkbox" id="sn-2" class="margin-toggle"><span class="marginnote">In the margin.</span>"#,
+            r#" three<label for="sn-3" class="margin-toggle sidenote-number">1</label><input type="checkbox" id="sn-3" class="margin-toggle"><span class="sidenote"><span class="sidenote-number">1</span> A <em>note</em>.<br />More.</span>"#,
+            r#" four<label for="sn-4" class="margin-toggle sidenote-number">2</label><input type="checkbox" id="sn-4" class="margin-toggle"><span class="sidenote"><span class="sidenote-number">2</span> Second.</span>.</p>"#,
+            "\n"
+        )
+    );
+
+    let mut excerpt = String::new();
+    pulldown_cmark::html::push_html(
+        &mut excerpt,
+        strip_footnotes(Parser::new_ext(source, Options::ENABLE_FOOTNOTES)),
+    );
+    assert_eq!(excerpt, "<p>One two three four.</p>\n");
+}
@@ -0,0 +1,275 @@
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Datelike, Month, SecondsFormat, Utc};
+use num_traits::FromPrimitive;
+use serde::Serialize;
+use sqlx::query;
+use tera::Context;
+
+use crate::{types::HydratedPost, utils::blog_post_url};
+
+use super::{editions::edition_output_dir, series::series_output_dir, types::Generator};
+
+/// The most URLs a single sitemap file may list.
+const MAX_SITEMAP_URLS: usize = 50_000;
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+struct SitemapEntry {
+    loc: String,
+    lastmod: DateTime<Utc>,
+}
+
+/// Writes `sitemap.xml` listing every post, page, series, tag index, edition
+/// and archive. Large sites get a sitemap index pointing at numbered sitemap
+/// files instead.
+pub async fn generate_sitemap(
+    posts: &[HydratedPost],
+    generator: &Generator<'_>,
+) -> anyhow::Result<()> {
+    let common = generator.common;
+    let Some(newest) = posts.iter().map(|p| p.updated_date).max() else {
+        return Ok(());
+    };
+
+    let mut entries = vec![SitemapEntry {
+        loc: common.base_url.clone(),
+        lastmod: newest,
+    }];
+
+    for post in posts {
+        entries.push(SitemapEntry {
+            loc: blog_post_url(
+                post.url_slug.clone(),
+                post.post_date,
+                common.timezone,
+                common.base_url.clone(),
+            )?,
+            lastmod: post.updated_date,
+        });
+    }
+
+    let pages = query!(
+        "SELECT url_slug, date_updated FROM pages WHERE site_id=$1 ORDER BY url_slug",
+        generator.site_id
+    )
+    .fetch_all(generator.pool)
+    .await?;
+    for page in pages {
+        entries.push(SitemapEntry {
+            loc: format!("{}{}.html", common.base_url, page.url_slug),
+            lastmod: page.date_updated,
+        });
+    }
+
+    let series = query!(
+        r#"
+SELECT s.url_slug, MAX(p.updated_date) AS "lastmod!"
+FROM series s
+INNER JOIN series_posts sp
+ON sp.series_id = s.id
+INNER JOIN posts p
+ON p.id = sp.post_id
+WHERE s.site_id = $1
+AND p.state = 'published'
+AND p.post_date <= CURRENT_TIMESTAMP
+GROUP BY s.id
+ORDER BY s.url_slug
+"#,
+        generator.site_id
+    )
+    .fetch_all(generator.pool)
+    .await?;
+    if let Some(lastmod) = series.iter().map(|s| s.lastmod).max() {
+        entries.push(SitemapEntry {
+            loc: format!("{}series/", common.base_url),
+            lastmod,
+        });
+    }
+    for s in series {
+        entries.push(SitemapEntry {
+            loc: format!("{}{}", common.base_url, series_output_dir(&s.url_slug)),
+            lastmod: s.lastmod,
+        });
+    }
+
+    let mut tags: BTreeMap<String, DateTime<Utc>> = BTreeMap::new();
+    let mut years: BTreeMap<i32, DateTime<Utc>> = BTreeMap::new();
+    let mut months: BTreeMap<(i32, u32), DateTime<Utc>> = BTreeMap::new();
+    let mut editions: BTreeMap<i32, DateTime<Utc>> = BTreeMap::new();
+    for post in posts {
+        if let Some(edition) = post.edition.filter(|_| common.editions_enabled) {
+            let lastmod = editions.entry(edition).or_insert(post.updated_date);
+            *lastmod = (*lastmod).max(post.updated_date);
+        }
+        for tag in post.tags.iter().flatten() {
+            let lastmod = tags.entry(tag.to_lowercase()).or_insert(post.updated_date);
+            *lastmod = (*lastmod).max(post.updated_date);
+        }
+        let post_date = post.post_date.with_timezone(&common.timezone);
+        let lastmod = years.entry(post_date.year()).or_insert(post.updated_date);
+        *lastmod = (*lastmod).max(post.updated_date);
+        let lastmod = months
+            .entry((post_date.year(), post_date.month()))
+            .or_insert(post.updated_date);
+        *lastmod = (*lastmod).max(post.updated_date);
+    }
+
+    for (tag, lastmod) in tags {
+        entries.push(SitemapEntry {
+            loc: format!("{}tags/{}/", common.base_url, tag),
+            lastmod,
+        });
+    }
+    for (year, lastmod) in years {
+        entries.push(SitemapEntry {
+            loc: format!("{}{}/", common.base_url, year),
+            lastmod,
+        });
+    }
+    for ((year, month), lastmod) in months {
+        let month_name = Month::from_u32(month)
+            .ok_or(anyhow::anyhow!("Bad month number"))?
+            .name();
+        entries.push(SitemapEntry {
+            loc: format!("{}{}/{}/", common.base_url, year, month_name),
+            lastmod,
+        });
+    }
+
+    if let Some(lastmod) = editions.values().max() {
+        entries.push(SitemapEntry {
+            loc: format!("{}editions/", common.base_url),
+            lastmod: *lastmod,
+        });
+    }
+    for (edition, lastmod) in editions {
+        entries.push(SitemapEntry {
+            loc: format!("{}{}", common.base_url, edition_output_dir(edition)),
+            lastmod,
+        });
+    }
+
+    for (path, content) in build_sitemaps(&entries, &common.base_url, MAX_SITEMAP_URLS) {
+        let deps = generator.dependencies(&[]).value(&content);
+        if !generator.is_current(&path, &deps).await? {
+            generator.write_output(&path, &deps, &content).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Writes `robots.txt` from the site's robots template.
+pub async fn generate_robots(generator: &Generator<'_>) -> anyhow::Result<()> {
+    let mut context = Context::new();
+    context.insert("common", generator.common);
+    let deps = generator.dependencies(&["robots.txt"]);
+    generator
+        .render_output("robots.txt", &deps, "robots.txt", &context)
+        .await
+}
+
+/// Splits the entries into sitemap files of at most `max_urls` entries. When
+/// more than one is needed `sitemap.xml` becomes an index of the others.
+fn build_sitemaps(
+    entries: &[SitemapEntry],
+    base_url: &str,
+    max_urls: usize,
+) -> Vec<(String, String)> {
+    if entries.len() <= max_urls {
+        return vec![("sitemap.xml".into(), urlset(entries))];
+    }
+
+    let mut files = Vec::new();
+    let mut index = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<sitemapindex xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n",
+    );
+    for (n, chunk) in entries.chunks(max_urls).enumerate() {
+        let path = format!("sitemap-{}.xml", n + 1);
+        index.push_str(&format!(
+            "\t<sitemap>\n\t\t<loc>{}</loc>\n",
+            xml_escape(&format!("{}{}", base_url, path))
+        ));
+        if let Some(lastmod) = chunk.iter().map(|e| e.lastmod).max() {
+            index.push_str(&format!(
+                "\t\t<lastmod>{}</lastmod>\n",
+                lastmod.to_rfc3339_opts(SecondsFormat::Secs, true)
+            ));
+        }
+        index.push_str("\t</sitemap>\n");
+        files.push((path, urlset(chunk)));
+    }
+    index.push_str("</sitemapindex>\n");
+    files.push(("sitemap.xml".into(), index));
+    files
+}
+
+fn urlset(entries: &[SitemapEntry]) -> String {
+    let mut xml = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n",
+    );
+    for entry in entries {
+        xml.push_str(&format!(
+            "\t<url>\n\t\t<loc>{}</loc>\n\t\t<lastmod>{}</lastmod>\n\t</url>\n",
+            xml_escape(&entry.loc),
+            entry.lastmod.to_rfc3339_opts(SecondsFormat::Secs, true)
+        ));
+    }
+    xml.push_str("</urlset>\n");
+    xml
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+#[test]
+fn test_small_sitemap_is_single_file() {
+    let entries = vec![SitemapEntry {
+        loc: "https://example.com/a&b.html".into(),
+        lastmod: DateTime::from_timestamp(0, 0).unwrap(),
+    }];
+    let files = build_sitemaps(&entries, "https://example.com/", 10);
+
+    assert_eq!(files.len(), 1);
+    assert_eq!(files[0].0, "sitemap.xml");
+    assert!(
+        files[0]
+            .1
+            .contains("<loc>https://example.com/a&amp;b.html</loc>")
+    );
+    assert!(
+        files[0]
+            .1
+            .contains("<lastmod>1970-01-01T00:00:00Z</lastmod>")
+    );
+}
+
+#[test]
+fn test_large_sitemap_is_split() {
+    let entries: Vec<SitemapEntry> = (0..5)
+        .map(|n| SitemapEntry {
+            loc: format!("https://example.com/{}.html", n),
+            lastmod: DateTime::from_timestamp(n * 60, 0).unwrap(),
+        })
+        .collect();
+    let files = build_sitemaps(&entries, "https://example.com/", 2);
+
+    let paths: Vec<&str> = files.iter().map(|(p, _)| p.as_str()).collect();
+    assert_eq!(
+        paths,
+        vec![
+            "sitemap-1.xml",
+            "sitemap-2.xml",
+            "sitemap-3.xml",
+            "sitemap.xml"
+        ]
+    );
+    let index = &files[3].1;
+    assert!(index.contains("<sitemapindex"));
+    assert!(index.contains("<loc>https://example.com/sitemap-3.xml</loc>"));
+    assert!(index.contains("<lastmod>1970-01-01T00:04:00Z</lastmod>"));
+}
@@ -0,0 +1,220 @@
+use std::{
+    fs, io,
+    os::unix::fs::symlink,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, bail};
+use chrono::Utc;
+
+use super::manifest::Manifest;
+
+/// How many generations, including the live one, are kept for rollback.
+const KEEP_GENERATIONS: usize = 5;
+
+/// A generation of the site being built next to the live one.
+///
+/// `BLOG_OUTPUT_PATH/<site_id>` is a symlink to the live generation in
+/// `BLOG_OUTPUT_PATH/.generations/<site_id>/`. A new generation starts as a
+/// hard linked copy of the live one, so files the manifest says are current
+/// don't need rendering again, and goes live by swapping the symlink.
+pub struct Staging {
+    site_link: PathBuf,
+    generations: PathBuf,
+    path: PathBuf,
+    previous: Option<PathBuf>,
+}
+
+impl Staging {
+    pub fn prepare(output_path_base: &str, site_id: i32) -> anyhow::Result<Staging> {
+        let site_link = Path::new(output_path_base).join(site_id.to_string());
+        let generations = generations_dir(output_path_base, site_id);
+        fs::create_dir_all(&generations)?;
+
+        // Sites generated before staging existed have a real directory here.
+        // It becomes the first generation so it can still be rolled back to.
+        if site_link.is_dir() && !site_link.is_symlink() {
+            let adopted = generations.join(generation_name());
+            fs::rename(&site_link, &adopted)?;
+            point_link_at(&site_link, &generations, &adopted)?;
+        }
+
+        let path = generations.join(generation_name());
+        let previous = match fs::canonicalize(&site_link) {
+            Ok(live) => {
+                link_tree(&live, &path)?;
+                Some(live)
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                fs::create_dir_all(&path)?;
+                None
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        Ok(Staging {
+            site_link,
+            generations,
+            path,
+            previous,
+        })
+    }
+
+    pub fn path(&self) -> anyhow::Result<&str> {
+        self.path
+            .to_str()
+            .ok_or(anyhow!("Staging path is not valid UTF-8"))
+    }
+
+    /// Deletes files an earlier run generated that this one didn't, such as
+    /// the index of a deleted tag.
+    pub fn remove_orphans(&self, manifest: &Manifest) -> anyhow::Result<usize> {
+        let mut removed = 0;
+        for orphan in manifest.orphans() {
+            match fs::remove_file(self.path.join(orphan)) {
+                Ok(()) => removed += 1,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Checks everything the run produced made it to disk before it goes live.
+    pub fn validate(&self, manifest: &Manifest) -> anyhow::Result<()> {
+        if !self.path.join("index.html").is_file() {
+            bail!("Generated site has no index.html");
+        }
+        for path in manifest.seen() {
+            let metadata = fs::metadata(self.path.join(path))
+                .map_err(|e| anyhow!("Generated file {} is missing: {}", path, e))?;
+            if metadata.len() == 0 {
+                bail!("Generated file {} is empty", path);
+            }
+        }
+        Ok(())
+    }
+
+    /// Makes this generation the live site. Until `finish` is called it can
+    /// still be undone with `revert`.
+    pub fn activate(&self) -> anyhow::Result<()> {
+        point_link_at(&self.site_link, &self.generations, &self.path)
+    }
+
+    /// Puts the generation that was live before back, and throws this one
+    /// away.
+    pub fn revert(self) -> anyhow::Result<()> {
+        match &self.previous {
+            Some(previous) => point_link_at(&self.site_link, &self.generations, previous)?,
+            None => fs::remove_file(&self.site_link)?,
+        }
+        self.discard()
+    }
+
+    /// Prunes old generations once this one is live for good.
+    pub fn finish(self) -> anyhow::Result<()> {
+        prune_generations(&self.generations, &self.path)
+    }
+
+    /// Throws away a generation that failed.
+    pub fn discard(self) -> anyhow::Result<()> {
+        fs::remove_dir_all(&self.path)?;
+        Ok(())
+    }
+}
+
+/// Points the site back at the generation before the live one. Returns the
+/// name of the generation now live.
+pub fn rollback(output_path_base: &str, site_id: i32) -> anyhow::Result<String> {
+    let site_link = Path::new(output_path_base).join(site_id.to_string());
+    let generations = generations_dir(output_path_base, site_id);
+    let live = fs::canonicalize(&site_link)?;
+    let names = generation_names(&generations)?;
+
+    let live_name = live
+        .file_name()
+        .and_then(|n| n.to_str())
+        .ok_or(anyhow!("Site is not published from a generation"))?;
+    let position = names.iter().position(|n| n == live_name).ok_or(anyhow!(
+        "Live generation {} is not in {:?}",
+        live_name,
+        generations
+    ))?;
+    if position == 0 {
+        bail!("There is no older generation to roll back to");
+    }
+
+    let previous = &names[position - 1];
+    point_link_at(&site_link, &generations, &generations.join(previous))?;
+    Ok(previous.clone())
+}
+
+fn generations_dir(output_path_base: &str, site_id: i32) -> PathBuf {
+    Path::new(output_path_base)
+        .join(".generations")
+        .join(site_id.to_string())
+}
+
+/// Names sort in the order the generations were made.
+fn generation_name() -> String {
+    Utc::now().format("%Y%m%d%H%M%S%9f").to_string()
+}
+
+fn generation_names(generations: &Path) -> anyhow::Result<Vec<String>> {
+    let mut names = fs::read_dir(generations)?
+        .filter_map(|e| e.ok())
+        .filter(|e| e.path().is_dir())
+        .filter_map(|e| e.file_name().into_string().ok())
+        .collect::<Vec<String>>();
+    names.sort();
+    Ok(names)
+}
+
+/// Swaps `link` to point at `target` by renaming a new symlink over it, so
+/// readers see either the old generation or the new one and never neither.
+fn point_link_at(link: &Path, generations: &Path, target: &Path) -> anyhow::Result<()> {
+    let parent = link.parent().ok_or(anyhow!("Site path has no parent"))?;
+    let relative = target
+        .strip_prefix(parent)
+        .map_err(|_| anyhow!("Generation {:?} is outside {:?}", target, generations))?;
+    let new_link = parent.join(format!(
+        ".{}.new",
+        link.file_name()
+            .and_then(|n| n.to_str())
+            .ok_or(anyhow!("Bad site path"))?
+    ));
+    if new_link.is_symlink() {
+        fs::remove_file(&new_link)?;
+    }
+    symlink(relative, &new_link)?;
+    fs::rename(&new_link, link)?;
+    Ok(())
+}
+
+fn prune_generations(generations: &Path, live: &Path) -> anyhow::Result<()> {
+    let names = generation_names(generations)?;
+    let excess = names.len().saturating_sub(KEEP_GENERATIONS);
+    for name in names.into_iter().take(excess) {
+        let path = generations.join(name);
+        if path != live {
+            fs::remove_dir_all(path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Recreates the `from` tree at `to` with every file hard linked, falling
+/// back to copying where linking isn't possible.
+fn link_tree(from: &Path, to: &Path) -> io::Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            link_tree(&entry.path(), &target)?;
+        } else if fs::hard_link(entry.path(), &target).is_err() {
+            fs::copy(entry.path(), &target)?;
+        }
+    }
+    Ok(())
+}
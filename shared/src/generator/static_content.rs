@@ -1,18 +1,31 @@
 use tera::Context;
-use tokio::{fs::File, io::AsyncWriteExt};
 
-use super::types::Generator;
+use super::{highlight::theme_css, types::Generator};
 
-pub async fn generate_static<'a>(
-    generator: &Generator<'a>,
-    output_path: &'a str,
-) -> anyhow::Result<()> {
-    let rendered = generator.tera.render("blog.css", &Context::new())?;
-    let mut file = File::create(format!("{}/blog.css", output_path)).await?;
-    file.write_all(rendered.as_bytes()).await?;
+pub async fn generate_static(generator: &Generator<'_>) -> anyhow::Result<()> {
+    let deps = generator.dependencies(&["blog.css"]);
+    generator
+        .render_output("static/blog.css", &deps, "blog.css", &Context::new())
+        .await?;
+
+    let css = code_css(generator)?;
+    let deps = generator.dependencies(&[]).value(&css);
+    if !generator.is_current("static/code.css", &deps).await? {
+        generator
+            .write_output("static/code.css", &deps, &css)
+            .await?;
+    }
     Ok(())
 }
 
+/// Colours for highlighted code, from the site's chosen themes.
+fn code_css(generator: &Generator<'_>) -> anyhow::Result<String> {
+    theme_css(
+        &generator.common.code_theme,
+        generator.common.code_theme_dark.as_deref(),
+    )
+}
+
 pub struct StaticContent {
     pub content: String,
     pub content_type: String,
@@ -27,6 +40,10 @@ pub async fn get_static_content(
             content: generator.tera.render("blog.css", &Context::new())?,
             content_type: "text/css".into(),
         }),
+        "code.css" => Ok(StaticContent {
+            content: code_css(generator)?,
+            content_type: "text/css".into(),
+        }),
         _ => anyhow::bail!("Unknown static content!"),
     }
 }
@@ -1,18 +1,33 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, sync::LazyLock};
 
+use anyhow::anyhow;
 use chrono::{DateTime, Datelike, Month, NaiveDate, offset::Utc};
 use chrono_tz::Tz;
-use latex2mathml::{DisplayStyle, latex_to_mathml};
+use itertools::Itertools;
 use num_traits::FromPrimitive;
 use ordinal::Ordinal;
-use pulldown_cmark::{CodeBlockKind, Event, Options, Parser, Tag, TagEnd};
+use pulldown_cmark::{
+    BlockQuoteKind, CodeBlockKind, Event, LinkType, Options, Parser, Tag, TagEnd,
+};
+use regex::Regex;
+use serde::Serialize;
 use serde_json::{Value, from_value};
 use sqlx::PgPool;
-use tera::{Filter, Function, Tera};
+use tera::{Filter, Function, Tera, escape_html};
 
+use super::{
+    data_tables::{data_delimiter, data_table},
+    headings::{Heading, find_headings},
+    highlight::{CodeBlockInfo, highlight_code},
+    math::{Equations, is_math_block},
+    shortcodes::Shortcodes,
+    sidenotes::{sidenotes, strip_footnotes},
+};
 use crate::{
-    referencing::{references_to_markdown, remove_citations_and_references},
-    types::{CommonData, HydratedPost, Media},
+    referencing::{
+        CitationStyle, ReferenceLibrary, references_to_markdown, remove_citations_and_references,
+    },
+    types::{CommonData, HydratedPost, LinkTarget, MarkdownExtension, Media},
 };
 
 pub static BASE: &str = include_str!("../../../templates/generated/tera_base.html");
@@ -25,6 +40,20 @@ pub static YEAR_INDEX: &str = include_str!("../../../templates/generated/year_in
 pub static RSS: &str = include_str!("../../../templates/generated/feed.xml");
 pub static ATOM: &str = include_str!("../../../templates/generated/atom.xml");
 pub static CSS: &str = include_str!("../../../templates/generated/blog.css");
+pub static PAGE: &str = include_str!("../../../templates/generated/page.html");
+pub static JSON_FEED: &str = include_str!("../../../templates/generated/feed.json");
+pub static SEARCH: &str = include_str!("../../../templates/generated/search.html");
+pub static ROBOTS: &str = include_str!("../../../templates/generated/robots.txt");
+pub static EDITION: &str = include_str!("../../../templates/generated/edition.html");
+pub static EDITIONS: &str = include_str!("../../../templates/generated/editions.html");
+pub static SERIES: &str = include_str!("../../../templates/generated/series.html");
+pub static SERIES_INDEX: &str = include_str!("../../../templates/generated/series_index.html");
+pub static SHORTCODE_YOUTUBE: &str =
+    include_str!("../../../templates/generated/shortcodes/youtube.html");
+pub static SHORTCODE_TOOT: &str = include_str!("../../../templates/generated/shortcodes/toot.html");
+pub static SHORTCODE_AUDIO: &str =
+    include_str!("../../../templates/generated/shortcodes/audio.html");
+pub static SHORTCODE_NOTE: &str = include_str!("../../../templates/generated/shortcodes/note.html");
 
 pub struct TemplateInfo {
     pub custom_path: Option<String>,
@@ -42,7 +71,7 @@ impl TemplateInfo {
     }
 }
 
-static TEMPLATE_MAP: [(&str, &str, &str); 10] = [
+static TEMPLATE_MAP: [(&str, &str, &str); 22] = [
     ("base", "base.html", BASE),
     ("macros", "macros.html", MACROS),
     ("posts", "post.html", POST),
@@ -52,9 +81,34 @@ static TEMPLATE_MAP: [(&str, &str, &str); 10] = [
     ("year_index", "year_index.html", YEAR_INDEX),
     ("atom", "atom.xml", ATOM),
     ("rss", "rss.xml", RSS),
+    ("json_feed", "feed.json", JSON_FEED),
     ("css", "blog.css", CSS),
+    ("page", "page.html", PAGE),
+    ("robots", "robots.txt", ROBOTS),
+    ("search", "search.html", SEARCH),
+    ("edition", "edition.html", EDITION),
+    ("editions", "editions.html", EDITIONS),
+    ("series", "series.html", SERIES),
+    ("series_index", "series_index.html", SERIES_INDEX),
+    (
+        "shortcode_youtube",
+        "shortcodes/youtube.html",
+        SHORTCODE_YOUTUBE,
+    ),
+    ("shortcode_toot", "shortcodes/toot.html", SHORTCODE_TOOT),
+    ("shortcode_audio", "shortcodes/audio.html", SHORTCODE_AUDIO),
+    ("shortcode_note", "shortcodes/note.html", SHORTCODE_NOTE),
 ];
 
+/// The shortcodes posts can use, one for each `shortcodes/` partial.
+pub fn shortcode_names() -> impl Iterator<Item = &'static str> {
+    TEMPLATE_MAP.iter().filter_map(|(_, filename, _)| {
+        filename
+            .strip_prefix("shortcodes/")
+            .and_then(|f| f.strip_suffix(".html"))
+    })
+}
+
 pub fn default_templates() -> HashMap<String, TemplateInfo> {
     let mut templates = HashMap::new();
     for (name, _, template) in TEMPLATE_MAP {
@@ -66,32 +120,55 @@ pub fn default_templates() -> HashMap<String, TemplateInfo> {
     templates
 }
 
-pub async fn load_templates(
+/// The template source for each template file, with the site's customised
+/// templates replacing the defaults.
+pub async fn load_template_sources(
     database: &PgPool,
     site_id: i32,
-    common: &CommonData,
-) -> anyhow::Result<Tera> {
+) -> anyhow::Result<Vec<(&'static str, String)>> {
     let templates = sqlx::query!(
         "SELECT template_kind, content FROM templates WHERE site_id=$1",
         site_id
     )
     .fetch_all(database)
     .await?;
-    let lookup: HashMap<String, String> = templates
+    let mut lookup: HashMap<String, String> = templates
         .into_iter()
         .map(|r| (r.template_kind, r.content))
         .collect();
 
+    Ok(TEMPLATE_MAP
+        .iter()
+        .map(|(name, filename, default_template)| {
+            (
+                *filename,
+                lookup
+                    .remove(*name)
+                    .unwrap_or_else(|| default_template.to_string()),
+            )
+        })
+        .collect())
+}
+
+pub async fn load_templates(
+    database: &PgPool,
+    site_id: i32,
+    common: &CommonData,
+) -> anyhow::Result<Tera> {
+    build_templates(&load_template_sources(database, site_id).await?, common)
+}
+
+pub fn build_templates(sources: &[(&str, String)], common: &CommonData) -> anyhow::Result<Tera> {
     let mut tera = Tera::default();
+    // The markdown filter renders shortcodes with its own copy of their
+    // partials, as it can't reach the Tera it's registered with.
+    let mut shortcodes = Tera::default();
 
-    for (name, filename, default_template) in TEMPLATE_MAP {
-        tera.add_raw_template(
-            filename,
-            lookup
-                .get(name)
-                .map(|c| c.as_str())
-                .unwrap_or(default_template),
-        )?;
+    for (filename, content) in sources {
+        tera.add_raw_template(filename, content)?;
+        if filename.starts_with("shortcodes/") {
+            shortcodes.add_raw_template(filename, content)?;
+        }
     }
 
     let base_url = common.base_url.clone();
@@ -116,10 +193,29 @@ pub async fn load_templates(
         "format_human_datetime",
         move |v: &Value, _args: &HashMap<String, Value>| format_human_datetime(v, &tz),
     );
+    let markdown_settings = MarkdownSettings {
+        media_base_url: media_base_url.clone(),
+        media: media.clone(),
+        code_line_numbers: common.code_line_numbers,
+        preview: common.preview,
+        shortcodes,
+        heading_anchors: common.heading_anchors,
+        options: markdown_options(&common.markdown_extensions),
+        link_targets: common.link_targets.clone(),
+        sidenotes: common.sidenotes,
+        citation_style: common.citation_style,
+        reference_library: common.reference_library.clone(),
+    };
     tera.register_filter(
         "format_markdown",
         move |v: &Value, args: &HashMap<String, Value>| {
-            format_markdown(v, args, &media_base_url, &media)
+            format_markdown(v, args, &markdown_settings)
+        },
+    );
+    tera.register_filter(
+        "media_attachments",
+        move |v: &Value, _args: &HashMap<String, Value>| {
+            media_attachments(v, &media_base_url, &media)
         },
     );
 
@@ -478,29 +574,280 @@ pub fn format_weekday(date: &NaiveDate) -> ::askama::Result<String> {
     let day = Ordinal(date.day());
     Ok(format!("{} {}", weekday, day))
 }*/
-fn format_markdown(
+static MEDIA_IMAGE_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\]\(!!(\d+)").expect("Could not create media image regex"));
+
+#[derive(Serialize)]
+struct Attachment<'a> {
+    url: String,
+    mime_type: &'a str,
+}
+
+/// The ids of the media images `body` embeds, in order and without repeats.
+pub(crate) fn embedded_media(body: &str) -> impl Iterator<Item = i32> + '_ {
+    MEDIA_IMAGE_RE
+        .captures_iter(body)
+        .filter_map(|c| c[1].parse::<i32>().ok())
+        .unique()
+}
+
+/// The media images a post body embeds, in order and without repeats, as
+/// JSON Feed attachments.
+fn media_attachments(
     value: &Value,
-    args: &HashMap<String, Value>,
     media_base_url: &String,
     media: &HashMap<i32, Media>,
 ) -> tera::Result<Value> {
-    let before_cut = args
-        .get("before_cut")
-        .and_then(|v| from_value::<bool>(v.clone()).ok())
-        .unwrap_or(false);
-    let raw_content: String = from_value(value.clone()).map_err(tera::Error::from)?;
+    let body: String = from_value(value.clone()).map_err(tera::Error::from)?;
+    let attachments: Vec<Attachment> = embedded_media(&body)
+        .filter_map(|id| media.get(&id))
+        .map(|image| Attachment {
+            url: format!("{}{}", media_base_url, image.metadata.fullsize_name),
+            mime_type: &image.metadata.content_type,
+        })
+        .collect();
+    serde_json::to_value(attachments).map_err(tera::Error::from)
+}
+
+/// A `<picture>` offering every stored size of the image, modern formats
+/// first, falling back to the original upload.
+fn picture_html(
+    image: &Media,
+    media_base_url: &str,
+    alt: &str,
+    title: &str,
+    args: &HashMap<String, String>,
+) -> String {
+    let metadata = &image.metadata;
+    let sizes = args.get("sizes").map(|s| s.as_str()).unwrap_or("100vw");
+    let srcset = |content_type: &str| {
+        metadata
+            .variants
+            .iter()
+            .filter(|v| v.content_type == content_type)
+            .map(|v| format!("{}{} {}w", media_base_url, v.file_name, v.width))
+            .join(", ")
+    };
+
+    let mut html = String::from("<picture>");
+    for content_type in ["image/avif", "image/webp"] {
+        let set = srcset(content_type);
+        if !set.is_empty() {
+            html.push_str(&format!(
+                r#"<source type="{}" srcset="{}" sizes="{}">"#,
+                content_type,
+                set,
+                escape_html(sizes)
+            ));
+        }
+    }
+
+    let fullsize = format!("{}{}", media_base_url, metadata.fullsize_name);
+    html.push_str(&format!(r#"<img src="{}""#, fullsize));
+    let set = srcset(&metadata.content_type);
+    if !set.is_empty() {
+        html.push_str(&format!(
+            r#" srcset="{}, {} {}w" sizes="{}""#,
+            set,
+            fullsize,
+            metadata.width,
+            escape_html(sizes)
+        ));
+    }
+    html.push_str(&format!(
+        r#" width="{}" height="{}" alt="{}""#,
+        metadata.width,
+        metadata.height,
+        escape_html(alt)
+    ));
+    if !title.is_empty() {
+        html.push_str(&format!(r#" title="{}""#, escape_html(title)));
+    }
+    if let Some(class) = args.get("class") {
+        html.push_str(&format!(r#" class="{}""#, escape_html(class)));
+    }
+    html.push_str(r#" loading="lazy" decoding="async"></picture>"#);
+    html
+}
+
+/// The id in a `!!id?options` media image destination.
+pub(crate) fn media_id(dest_url: &str) -> anyhow::Result<i32> {
+    let id = dest_url
+        .trim_start_matches("!!")
+        .split('?')
+        .next()
+        .unwrap_or_default();
+    id.parse()
+        .map_err(|_| anyhow!("`{}` is not a media id", id))
+}
+
+/// The options after the `?` in a media image destination, e.g.
+/// `!!3?class=wide&sizes=50vw`.
+pub(crate) fn media_options(dest_url: &str) -> anyhow::Result<HashMap<String, String>> {
+    match dest_url.split_once('?') {
+        Some((_, options)) => {
+            serde_querystring::from_str(options, serde_querystring::ParseMode::UrlEncoded)
+                .map_err(|e| anyhow!("Could not read image options `{}`: {}", options, e))
+        }
+        None => Ok(HashMap::new()),
+    }
+}
+
+/// What's left of an image that can't be shown: its alt text, and in
+/// previews why it's missing.
+fn missing_media_html(alt: &str, error: &anyhow::Error, preview: bool) -> String {
+    if preview {
+        format!(
+            r#"<span class="media-error">{}: {}</span>"#,
+            escape_html(&error.to_string()),
+            escape_html(alt)
+        )
+    } else {
+        format!(r#"<span class="missing-media">{}</span>"#, escape_html(alt))
+    }
+}
+
+/// A post or page body ready for the markdown parser: the cut and
+/// references dealt with and shortcodes swapped for markers.
+fn prepare_markdown(
+    raw_content: String,
+    before_cut: bool,
+    options: Options,
+    citation_style: CitationStyle,
+    library: &ReferenceLibrary,
+    preview: bool,
+) -> anyhow::Result<(String, Shortcodes)> {
+    // The cut comes out afterwards so reference problems are reported where
+    // they are in the post as written.
     let content = match before_cut {
-        false => references_to_markdown(raw_content.replace("<blog-cut>", ""))
-            .map_err(|e| tera::Error::msg(e.to_string()))?,
+        false => references_to_markdown(raw_content, citation_style, library, preview)?
+            .replace("<blog-cut>", ""),
         true => remove_citations_and_references(raw_content),
     };
-    let mut current_image: Option<Tag> = None;
-    let mut image_text = String::new();
-    let mut in_codeblock = false;
+    Ok(Shortcodes::extract(&content, options))
+}
+
+/// The headings in a full post or page, with the ids `format_markdown`
+/// gives them.
+pub fn headings(
+    body: &str,
+    extensions: &[MarkdownExtension],
+    library: &ReferenceLibrary,
+) -> anyhow::Result<Vec<Heading>> {
+    let options = markdown_options(extensions);
+    let (content, _) = prepare_markdown(
+        body.to_string(),
+        false,
+        options,
+        CitationStyle::default(),
+        library,
+        false,
+    )?;
+    Ok(find_headings(Parser::new_ext(&content, options)))
+}
+
+/// The markdown extensions posts and pages are written with, along with
+/// the ones the site has switched on.
+pub(crate) fn markdown_options(extensions: &[MarkdownExtension]) -> Options {
     let mut options = Options::empty();
     options.insert(Options::ENABLE_STRIKETHROUGH);
     options.insert(Options::ENABLE_SMART_PUNCTUATION);
     options.insert(Options::ENABLE_FOOTNOTES);
+    for extension in extensions {
+        options.insert(match extension {
+            MarkdownExtension::Tables => Options::ENABLE_TABLES,
+            MarkdownExtension::TaskLists => Options::ENABLE_TASKLISTS,
+            MarkdownExtension::HeadingAttributes => Options::ENABLE_HEADING_ATTRIBUTES,
+            MarkdownExtension::DefinitionLists => Options::ENABLE_DEFINITION_LIST,
+            MarkdownExtension::Admonitions => Options::ENABLE_GFM,
+            MarkdownExtension::WikiLinks => Options::ENABLE_WIKILINKS,
+        });
+    }
+    options
+}
+
+/// A `[[slug]]` link to nothing keeps its text, and previews say why it
+/// isn't a link.
+fn missing_link_html(slug: &str, preview: bool) -> String {
+    if preview {
+        format!(
+            r#"<span class="link-error">Nothing has the slug <code>{}</code></span> <span class="missing-link">"#,
+            escape_html(slug)
+        )
+    } else {
+        r#"<span class="missing-link">"#.into()
+    }
+}
+
+fn admonition_html(kind: &BlockQuoteKind) -> String {
+    let (class, title) = match kind {
+        BlockQuoteKind::Note => ("note", "Note"),
+        BlockQuoteKind::Tip => ("tip", "Tip"),
+        BlockQuoteKind::Important => ("important", "Important"),
+        BlockQuoteKind::Warning => ("warning", "Warning"),
+        BlockQuoteKind::Caution => ("caution", "Caution"),
+    };
+    format!(
+        r#"<aside class="admonition admonition-{}"><p class="admonition-title">{}</p>
+"#,
+        class, title
+    )
+}
+
+/// The site settings `format_markdown` renders with.
+struct MarkdownSettings {
+    media_base_url: String,
+    media: HashMap<i32, Media>,
+    code_line_numbers: bool,
+    preview: bool,
+    shortcodes: Tera,
+    heading_anchors: bool,
+    options: Options,
+    link_targets: HashMap<String, LinkTarget>,
+    sidenotes: bool,
+    citation_style: CitationStyle,
+    reference_library: ReferenceLibrary,
+}
+
+fn format_markdown(
+    value: &Value,
+    args: &HashMap<String, Value>,
+    settings: &MarkdownSettings,
+) -> tera::Result<Value> {
+    let before_cut = args
+        .get("before_cut")
+        .and_then(|v| from_value::<bool>(v.clone()).ok())
+        .unwrap_or(false);
+    let raw_content: String = from_value(value.clone()).map_err(tera::Error::from)?;
+    let options = settings.options;
+    let (content, shortcodes) = prepare_markdown(
+        raw_content,
+        before_cut,
+        options,
+        settings.citation_style,
+        &settings.reference_library,
+        settings.preview,
+    )
+    .map_err(|e| tera::Error::msg(e.to_string()))?;
+    let mut current_image: Option<Tag> = None;
+    let mut image_text = String::new();
+    let mut code_block: Option<CodeBlockInfo> = None;
+    let mut math_block: Option<String> = None;
+    let mut data_block: Option<String> = None;
+    let mut code = String::new();
+    let mut equations = Equations::new(Parser::new_ext(&content, options), settings.preview);
+    // The excerpt before the cut is shown alongside other posts, so only the
+    // full post gets heading ids.
+    let mut heading_ids = match before_cut {
+        false => find_headings(Parser::new_ext(&content, options)),
+        true => Vec::new(),
+    }
+    .into_iter()
+    .map(|h| h.id);
+    let mut heading_id: Option<String> = None;
+    // The closing tag for the `[[slug]]` link being rendered, and whether
+    // its text is being replaced by the title of what it links to.
+    let mut wiki_link: Option<(&str, bool)> = None;
     let parser = Parser::new_ext(content.as_ref(), options).map(|event| match &event {
         /*
         An image is represented as three events: Start(Tag::Image) Text(alt text) End(Tag::Image)
@@ -517,27 +864,70 @@ fn format_markdown(
                 current_image = Some(tag.clone());
                 Event::Text("".into())
             }
-            Tag::CodeBlock(CodeBlockKind::Fenced(lang)) if !lang.is_empty() => {
-                in_codeblock = true;
-                Event::Html(
-                    format!(
-                        "<pre class=\"fenced-code language-{}\"><code class=\"language-{}\">",
-                        lang, lang
-                    )
-                    .into(),
-                )
+            Tag::Heading {
+                level,
+                id: _,
+                classes,
+                attrs,
+            } if !before_cut => {
+                heading_id = heading_ids.next();
+                Event::Start(Tag::Heading {
+                    level: *level,
+                    id: heading_id.clone().map(Into::into),
+                    classes: classes.clone(),
+                    attrs: attrs.clone(),
+                })
             }
+            Tag::CodeBlock(CodeBlockKind::Fenced(info)) if is_math_block(info) => {
+                math_block = Some(info.to_string());
+                Event::Text("".into())
+            }
+            Tag::CodeBlock(CodeBlockKind::Fenced(info)) if data_delimiter(info).is_some() => {
+                data_block = Some(info.to_string());
+                Event::Text("".into())
+            }
+            Tag::CodeBlock(CodeBlockKind::Fenced(info)) if !info.is_empty() => {
+                code_block = Some(CodeBlockInfo::parse(info));
+                Event::Text("".into())
+            }
+            Tag::BlockQuote(Some(kind)) => Event::Html(admonition_html(kind).into()),
+            Tag::Link {
+                link_type: LinkType::WikiLink { has_pothole },
+                dest_url,
+                ..
+            } => match settings.link_targets.get(dest_url.trim()) {
+                Some(target) => {
+                    wiki_link = Some(("</a>", !has_pothole));
+                    let mut html =
+                        format!(r#"<a class="wiki-link" href="{}">"#, escape_html(&target.url));
+                    if !has_pothole {
+                        html.push_str(&escape_html(&target.title));
+                    }
+                    Event::InlineHtml(html.into())
+                }
+                None => {
+                    wiki_link = Some(("</span>", false));
+                    Event::InlineHtml(missing_link_html(dest_url, settings.preview).into())
+                }
+            },
             _ => event,
         },
-        Event::Code(code) if code.starts_with("$$") && code.ends_with("$$") => {
-            let mathml = latex_to_mathml(code[2..(code.len() - 2)].into(), DisplayStyle::Inline)
-                .unwrap_or("Bad Math!".into());
-            Event::Html(mathml.into())
+        Event::Code(code) if code.len() >= 4 && code.starts_with("$$") && code.ends_with("$$") => {
+            Event::InlineHtml(equations.inline(&code[2..(code.len() - 2)]).into())
         }
+        Event::Text(txt) if code_block.is_some() || math_block.is_some() || data_block.is_some() => {
+            code.push_str(txt);
+            Event::Text("".into())
+        }
+        Event::Text(_) if wiki_link.is_some_and(|(_, replaced)| replaced) => Event::Text("".into()),
         Event::Text(txt) if current_image.is_some() => {
-            image_text.push_str(&format!(r#" alt="{}" "#, txt));
+            image_text.push_str(txt);
             Event::Text("".into())
         }
+        Event::Text(txt) => match equations.references(txt) {
+            Some(html) => Event::InlineHtml(html.into()),
+            None => event,
+        },
         Event::End(tag) => match tag {
             TagEnd::Image if current_image.is_some() => {
                 if let Some(Tag::Image {
@@ -545,41 +935,70 @@ fn format_markdown(
                     title,
                     id: _,
                     link_type: _,
-                }) = current_image.clone()
+                }) = current_image.take()
                 {
-                    let dest_args: Vec<&str> = dest_url.split("?").collect();
-                    let image_id: i32 = dest_args[0][2..].parse().unwrap();
-                    let args: HashMap<String, String> = if dest_args.len() > 1 {
-                        serde_querystring::from_str(
-                            dest_args[1],
-                            serde_querystring::ParseMode::UrlEncoded,
-                        )
-                        .unwrap()
-                    } else {
-                        HashMap::new()
+                    // Bad options are reported by validation, the image is
+                    // still worth showing without them.
+                    let html = match media_id(&dest_url).and_then(|id| {
+                        settings
+                            .media
+                            .get(&id)
+                            .ok_or(anyhow!("There is no media with id {}", id))
+                    }) {
+                        Ok(image) => picture_html(
+                            image,
+                            &settings.media_base_url,
+                            &image_text,
+                            &title,
+                            &media_options(&dest_url).unwrap_or_default(),
+                        ),
+                        Err(e) => missing_media_html(&image_text, &e, settings.preview),
                     };
-
-                    let image = media.get(&image_id).unwrap();
-                    let dest = format!("{}{}", media_base_url, image.metadata.fullsize_name);
-                    let mut html = String::new();
-
-                    html.push_str(&format!(r#"<img src="{}" title="{}""#, dest, title));
-
-                    if let Some(a) = args.get("class") {
-                        html.push_str(&format!(r#" class="{}" "#, a));
-                    }
-
-                    html.push_str("></picture>");
-                    current_image = None;
                     image_text = String::new();
                     Event::Html(html.into())
                 } else {
                     Event::Html("".into())
                 }
             }
-            TagEnd::CodeBlock if in_codeblock => {
-                in_codeblock = false;
-                Event::Html("</code></pre>".into())
+            TagEnd::Heading(level) if settings.heading_anchors && heading_id.is_some() => {
+                let id = heading_id.take().unwrap_or_default();
+                Event::Html(
+                    format!(
+                        r##"<a class="heading-anchor" href="#{}" aria-label="Link to this section">#</a></{}>
+"##,
+                        escape_html(&id),
+                        level
+                    )
+                    .into(),
+                )
+            }
+            TagEnd::Link if wiki_link.is_some() => {
+                Event::InlineHtml(wiki_link.take().unwrap_or_default().0.into())
+            }
+            TagEnd::BlockQuote(Some(_)) => Event::Html("</aside>\n".into()),
+            TagEnd::CodeBlock if math_block.is_some() => {
+                let html = math_block
+                    .take()
+                    .map(|info| equations.display(&info, &code))
+                    .unwrap_or_default();
+                code.clear();
+                Event::Html(html.into())
+            }
+            TagEnd::CodeBlock if data_block.is_some() => {
+                let html = data_block
+                    .take()
+                    .map(|info| data_table(&info, &code, settings.preview))
+                    .unwrap_or_default();
+                code.clear();
+                Event::Html(html.into())
+            }
+            TagEnd::CodeBlock if code_block.is_some() => {
+                let html = code_block
+                    .take()
+                    .map(|info| highlight_code(&code, &info, settings.code_line_numbers))
+                    .unwrap_or_default();
+                code.clear();
+                Event::Html(html.into())
             }
             _ => event,
         },
@@ -587,14 +1006,87 @@ fn format_markdown(
     });
     let mut html_output = String::new();
 
-    match before_cut {
-        true => pulldown_cmark::html::push_html(
+    let excerpt = |e: &Event| !matches!(e, Event::Html(node) if node.starts_with("<blog-cut>"));
+    match (before_cut, settings.sidenotes) {
+        // With sidenotes the footnotes would point past the end of an
+        // excerpt, so they're left out.
+        (true, true) => pulldown_cmark::html::push_html(
+            &mut html_output,
+            strip_footnotes(parser.take_while(excerpt)),
+        ),
+        (true, false) => {
+            pulldown_cmark::html::push_html(&mut html_output, parser.take_while(excerpt))
+        }
+        (false, true) => pulldown_cmark::html::push_html(
             &mut html_output,
-            parser
-                .take_while(|e| !matches!(e, Event::Html(node) if node.starts_with("<blog-cut>"))),
+            sidenotes(parser.collect()).into_iter(),
         ),
-        false => pulldown_cmark::html::push_html(&mut html_output, parser),
+        (false, false) => pulldown_cmark::html::push_html(&mut html_output, parser),
+    };
+
+    Ok(Value::String(shortcodes.render(
+        html_output,
+        &settings.shortcodes,
+        settings.preview,
+    )))
+}
+
+#[test]
+fn renders_markdown_extensions_when_enabled() {
+    let source = "> [!WARNING]\n> Mind the gap\n\n| a | b |\n|---|---|\n| 1 | 2 |\n\n- [x] done\n";
+    let render = |extensions: &str| {
+        let common = CommonData {
+            markdown_extensions: MarkdownExtension::parse_list(extensions),
+            ..Default::default()
+        };
+        let tera = build_templates(
+            &[("test.html", "{{ body | format_markdown | safe }}".into())],
+            &common,
+        )
+        .unwrap();
+        let mut context = tera::Context::new();
+        context.insert("body", source);
+        tera.render("test.html", &context).unwrap()
     };
 
-    Ok(Value::String(html_output))
+    let html = render("admonitions, tables,task_lists");
+    assert!(html.contains(r#"<aside class="admonition admonition-warning">"#));
+    assert!(html.contains("<table>"));
+    assert!(html.contains(r#"<input disabled="" type="checkbox" checked=""/>"#));
+
+    let html = render("");
+    assert!(html.contains("<blockquote>\n<p>[!WARNING]"));
+    assert!(!html.contains("<table>"));
+    assert!(html.contains("<li>[x] done</li>"));
+
+    assert_eq!(
+        MarkdownExtension::parse_list("tables, emoji,,task_lists"),
+        vec![MarkdownExtension::Tables, MarkdownExtension::TaskLists]
+    );
+}
+
+#[test]
+fn strips_footnotes_from_excerpts_only_with_sidenotes() {
+    let render = |sidenotes: bool| {
+        let common = CommonData {
+            sidenotes,
+            ..Default::default()
+        };
+        let tera = build_templates(
+            &[(
+                "test.html",
+                "{{ body | format_markdown(before_cut=true) | safe }}".into(),
+            )],
+            &common,
+        )
+        .unwrap();
+        let mut context = tera::Context::new();
+        context.insert("body", "Words[^1]\n\n<blog-cut>\n\n[^1]: A note\n");
+        tera.render("test.html", &context).unwrap()
+    };
+
+    assert!(
+        render(false).contains(r##"<sup class="footnote-reference"><a href="#1">1</a></sup>"##)
+    );
+    assert_eq!(render(true), "<p>Words</p>\n");
 }
@@ -1,12 +1,160 @@
+use std::{collections::HashMap, path::Path, sync::Mutex};
+
 use sqlx::PgPool;
 use tera::Tera;
+use tokio::{
+    fs::{File, create_dir_all, remove_file, try_exists},
+    io::AsyncWriteExt,
+};
 
 use crate::types::CommonData;
 
+use super::{
+    manifest::{Dependencies, Manifest, hash_str},
+    templates::{build_templates, load_template_sources},
+};
+
+/// Where shortcode partials live among the templates.
+const SHORTCODE_DIR: &str = "shortcodes/";
+
+/// Templates every HTML page is wrapped in.
+pub const LAYOUT_TEMPLATES: [&str; 2] = ["base.html", "macros.html"];
+
 pub struct Generator<'a> {
     pub output_path: &'a str,
     pub common: &'a CommonData,
     pub pool: &'a PgPool,
     pub tera: Tera,
     pub site_id: i32,
+    pub template_hashes: HashMap<String, String>,
+    pub common_hash: String,
+    pub manifest: Option<Mutex<Manifest>>,
+}
+
+impl<'a> Generator<'a> {
+    pub async fn new(
+        output_path: &'a str,
+        pool: &'a PgPool,
+        common: &'a CommonData,
+        site_id: i32,
+    ) -> anyhow::Result<Generator<'a>> {
+        let sources = load_template_sources(pool, site_id).await?;
+        let tera = build_templates(&sources, common)?;
+        let template_hashes = sources
+            .iter()
+            .map(|(name, content)| (name.to_string(), hash_str(content)))
+            .collect();
+        // Shortcode partials can be used by any markdown, on any page or
+        // feed, so they count as site-wide data rather than page templates.
+        let common_hash = sources
+            .iter()
+            .filter(|(name, _)| name.starts_with(SHORTCODE_DIR))
+            .fold(
+                Dependencies::new()
+                    .value(common)
+                    .value(common.timezone.name()),
+                |deps, (name, content)| deps.template(name, Some(&hash_str(content))),
+            )
+            .finish();
+
+        Ok(Generator {
+            output_path,
+            common,
+            pool,
+            tera,
+            site_id,
+            template_hashes,
+            common_hash,
+            manifest: None,
+        })
+    }
+
+    /// Tracks what gets written in `manifest`, skipping files whose inputs
+    /// haven't changed since it was saved.
+    pub fn with_manifest(mut self, manifest: Manifest) -> Self {
+        self.manifest = Some(Mutex::new(manifest));
+        self
+    }
+
+    pub fn into_manifest(self) -> Option<Manifest> {
+        self.manifest.and_then(|m| m.into_inner().ok())
+    }
+
+    /// Starts the dependencies of a file rendered from `templates`. Every
+    /// file also depends on the site-wide common data.
+    pub fn dependencies(&self, templates: &[&str]) -> Dependencies {
+        templates
+            .iter()
+            .fold(Dependencies::new().value(&self.common_hash), |deps, t| {
+                deps.template(t, self.template_hashes.get(*t))
+            })
+    }
+
+    /// Dependencies for an HTML page rendered from `template` inside the
+    /// site layout.
+    pub fn page_dependencies(&self, template: &str) -> Dependencies {
+        self.dependencies(&LAYOUT_TEMPLATES)
+            .template(template, self.template_hashes.get(template))
+    }
+
+    /// Whether `path`, relative to the output directory, is already on disk
+    /// and was rendered from the same inputs.
+    pub async fn is_current(&self, path: &str, deps: &Dependencies) -> anyhow::Result<bool> {
+        let Some(manifest) = &self.manifest else {
+            return Ok(false);
+        };
+        let matches = manifest
+            .lock()
+            .map_err(|_| anyhow::anyhow!("Manifest lock poisoned"))?
+            .is_current(path, &deps.finish());
+        Ok(matches && try_exists(self.full_path(path)).await?)
+    }
+
+    /// Writes `content` to `path`, relative to the output directory, and
+    /// records the inputs it came from.
+    pub async fn write_output(
+        &self,
+        path: &str,
+        deps: &Dependencies,
+        content: &str,
+    ) -> anyhow::Result<()> {
+        let full_path = self.full_path(path);
+        if let Some(parent) = Path::new(&full_path).parent() {
+            create_dir_all(parent).await?;
+        }
+        // The file may be hard linked into the live generation, so it has to
+        // be replaced rather than overwritten in place.
+        if try_exists(&full_path).await? {
+            remove_file(&full_path).await?;
+        }
+        let mut file = File::create(&full_path).await?;
+        file.write_all(content.as_bytes()).await?;
+
+        if let Some(manifest) = &self.manifest {
+            manifest
+                .lock()
+                .map_err(|_| anyhow::anyhow!("Manifest lock poisoned"))?
+                .record(path, deps.finish());
+        }
+        Ok(())
+    }
+
+    /// Renders `template` into `path` unless the file is already current.
+    pub async fn render_output(
+        &self,
+        path: &str,
+        deps: &Dependencies,
+        template: &str,
+        context: &tera::Context,
+    ) -> anyhow::Result<()> {
+        if self.is_current(path, deps).await? {
+            return Ok(());
+        }
+        let rendered = self.tera.render(template, context)?;
+        self.write_output(path, deps, &rendered).await
+    }
+
+    pub fn full_path(&self, path: &str) -> String {
+        format!("{}/{}", self.output_path, path)
+    }
 }
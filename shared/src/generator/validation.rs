@@ -0,0 +1,293 @@
+use std::{collections::HashMap, fmt};
+
+use latex2mathml::DisplayStyle;
+use pulldown_cmark::{CodeBlockKind, Event, Parser, Tag, TagEnd};
+use serde::Serialize;
+use sqlx::{PgPool, query};
+
+use super::{
+    data_tables::{data_delimiter, data_problems},
+    math::{Equations, is_math_block, latex_error},
+    shortcodes::find_shortcodes,
+    templates::{markdown_options, media_id, media_options, shortcode_names},
+    wiki_links::wiki_links,
+};
+use crate::{
+    referencing::{ReferenceLibrary, reference_problems},
+    types::{CommonData, LinkTarget, MarkdownExtension, Media},
+    utils::line_and_column,
+};
+
+/// The image options `picture_html` understands.
+const IMAGE_OPTIONS: [&str; 2] = ["class", "sizes"];
+
+#[derive(Serialize, Debug, PartialEq, Clone, Copy)]
+pub enum ContentKind {
+    Post,
+    Page,
+}
+
+impl fmt::Display for ContentKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ContentKind::Post => write!(f, "post"),
+            ContentKind::Page => write!(f, "page"),
+        }
+    }
+}
+
+/// Something in a post or page that won't render properly.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Diagnostic {
+    pub kind: ContentKind,
+    pub id: i32,
+    pub title: String,
+    pub line: usize,
+    pub column: usize,
+    pub problem: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {} \"{}\" line {} column {}: {}",
+            self.kind, self.id, self.title, self.line, self.column, self.problem
+        )
+    }
+}
+
+/// A problem in a piece of markdown, at a line and column counted from 1.
+#[derive(Debug, PartialEq)]
+pub struct Problem {
+    pub line: usize,
+    pub column: usize,
+    pub problem: String,
+}
+
+/// Where the line `lines` after the one `offset` is on starts.
+fn offset_of_line(source: &str, offset: usize, lines: usize) -> usize {
+    match lines {
+        0 => offset,
+        _ => source[offset..]
+            .match_indices('\n')
+            .nth(lines - 1)
+            .map(|(i, _)| offset + i + 1)
+            .unwrap_or(offset),
+    }
+}
+
+/// Checks a post or page body for media that doesn't exist, image options
+/// that can't be read, references that don't parse, citations and equation
+/// references with nothing to point at, LaTeX that won't convert,
+/// shortcodes that don't exist, `[[slug]]` links to nothing and data tables
+/// that don't parse.
+pub fn check_markdown(
+    source: &str,
+    media: &HashMap<i32, Media>,
+    extensions: &[MarkdownExtension],
+    link_targets: &HashMap<String, LinkTarget>,
+    library: &ReferenceLibrary,
+) -> Vec<Problem> {
+    let options = markdown_options(extensions);
+    let equations = Equations::new(Parser::new_ext(source, options), false);
+    let mut problems: Vec<(usize, String)> = reference_problems(source, library);
+
+    let (shortcodes, unmatched) = find_shortcodes(source, options);
+    for shortcode in shortcodes {
+        if !shortcode_names().any(|n| n == shortcode.name) {
+            problems.push((
+                shortcode.range.start,
+                format!("Unknown shortcode `{}`", shortcode.name),
+            ));
+        }
+    }
+    for close in unmatched {
+        problems.push((
+            close.range.start,
+            format!("`{{{{< /{} >}}}}` doesn't close anything", close.name),
+        ));
+    }
+    for (range, slug) in wiki_links(source, options) {
+        if !link_targets.contains_key(&slug) {
+            problems.push((
+                range.start,
+                format!("No post or page has the slug `{}`", slug),
+            ));
+        }
+    }
+    let mut math_block: Option<(usize, String)> = None;
+    let mut data_block: Option<(usize, String, String)> = None;
+
+    for (event, range) in Parser::new_ext(source, options).into_offset_iter() {
+        match event {
+            Event::Start(Tag::Image { dest_url, .. }) if dest_url.starts_with("!!") => {
+                match media_id(&dest_url) {
+                    Ok(id) if !media.contains_key(&id) => {
+                        problems.push((range.start, format!("There is no media with id {}", id)))
+                    }
+                    Ok(_) => {}
+                    Err(e) => problems.push((range.start, e.to_string())),
+                }
+                match media_options(&dest_url) {
+                    Ok(image_options) => {
+                        for name in image_options.keys() {
+                            if !IMAGE_OPTIONS.contains(&name.as_str()) {
+                                problems.push((
+                                    range.start,
+                                    format!("Unknown image option `{}`", name),
+                                ));
+                            }
+                        }
+                    }
+                    Err(e) => problems.push((range.start, e.to_string())),
+                }
+            }
+            Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(info))) if is_math_block(&info) => {
+                math_block = Some((range.start, String::new()));
+            }
+            Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(info)))
+                if data_delimiter(&info).is_some() =>
+            {
+                data_block = Some((range.start, info.to_string(), String::new()));
+            }
+            Event::End(TagEnd::CodeBlock) => {
+                if let Some((start, info, data)) = data_block.take() {
+                    for (line, problem) in data_problems(&info, &data) {
+                        problems.push((offset_of_line(source, start, line), problem));
+                    }
+                }
+                if let Some((start, latex)) = math_block.take()
+                    && let Some(error) = latex_error(&latex, DisplayStyle::Block)
+                {
+                    problems.push((start, format!("Could not read math: {}", error)));
+                }
+            }
+            Event::Text(text) if data_block.is_some() => {
+                if let Some((_, _, data)) = data_block.as_mut() {
+                    data.push_str(&text);
+                }
+            }
+            Event::Text(text) => match math_block.as_mut() {
+                Some((_, latex)) => latex.push_str(&text),
+                None => {
+                    for label in equations.unknown_labels(&text) {
+                        problems
+                            .push((range.start, format!("No equation is labelled `{}`", label)));
+                    }
+                }
+            },
+            Event::Code(code)
+                if code.len() >= 4 && code.starts_with("$$") && code.ends_with("$$") =>
+            {
+                if let Some(error) = latex_error(&code[2..(code.len() - 2)], DisplayStyle::Inline) {
+                    problems.push((range.start, format!("Could not read math: {}", error)));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    problems.sort_by_key(|(offset, _)| *offset);
+    problems
+        .into_iter()
+        .map(|(offset, problem)| {
+            let (line, column) = line_and_column(source, offset);
+            Problem {
+                line,
+                column,
+                problem,
+            }
+        })
+        .collect()
+}
+
+/// Checks every published post, including scheduled ones, and every page
+/// on the site.
+pub async fn validate_site(
+    connection: &PgPool,
+    common: &CommonData,
+) -> anyhow::Result<Vec<Diagnostic>> {
+    let site_id = common.site_id;
+    let posts = query!(
+        "SELECT id, title, body FROM posts WHERE site_id=$1 AND state='published' ORDER BY post_date DESC",
+        site_id
+    )
+    .fetch_all(connection)
+    .await?;
+    let pages = query!(
+        "SELECT id, title, body FROM pages WHERE site_id=$1 ORDER BY title",
+        site_id
+    )
+    .fetch_all(connection)
+    .await?;
+
+    let content = posts
+        .into_iter()
+        .map(|p| (ContentKind::Post, p.id, p.title, p.body))
+        .chain(
+            pages
+                .into_iter()
+                .map(|p| (ContentKind::Page, p.id, p.title, p.body)),
+        );
+
+    let mut diagnostics = Vec::new();
+    for (kind, id, title, body) in content {
+        for problem in check_markdown(
+            &body,
+            &common.media,
+            &common.markdown_extensions,
+            &common.link_targets,
+            &common.reference_library,
+        ) {
+            diagnostics.push(Diagnostic {
+                kind,
+                id,
+                title: title.clone(),
+                line: problem.line,
+                column: problem.column,
+                problem: problem.problem,
+            });
+        }
+    }
+    Ok(diagnostics)
+}
+
+#[test]
+fn finds_problems_by_line() {
+    let source = "Fine text.\n\n![Gone](!!7)\n\n![Bad](!!x?colour=red)\n\nSee \\ref{nope} and `$$\\frac{1$$`.\n\n```math\n\\sqrt{\n```\n\n\\cite{missing}\n\n{{< note >}}\n{{< nope >}}\n{{< /youtube >}}\n\n[[gone]]\n\n```csv\na,b\n1\n```\n";
+    let problems = check_markdown(
+        source,
+        &HashMap::new(),
+        &[MarkdownExtension::WikiLinks],
+        &HashMap::new(),
+        &ReferenceLibrary::new(),
+    );
+    let found: Vec<(usize, &str)> = problems
+        .iter()
+        .map(|p| (p.line, p.problem.as_str()))
+        .collect();
+    assert_eq!(found[0], (3, "There is no media with id 7"));
+    assert_eq!(found[1], (5, "`x` is not a media id"));
+    assert_eq!(found[2], (5, "Unknown image option `colour`"));
+    assert_eq!(found[3], (7, "No equation is labelled `nope`"));
+    assert_eq!(found[4].0, 7);
+    assert!(found[4].1.starts_with("Could not read math"));
+    assert_eq!(found[5].0, 9);
+    assert_eq!(
+        found[6],
+        (
+            13,
+            "Cited `missing` but there is no reference with that name"
+        )
+    );
+    assert_eq!(problems[6].column, 7);
+    assert_eq!(found[7], (16, "Unknown shortcode `nope`"));
+    assert_eq!(found[8], (17, "`{{< /youtube >}}` doesn't close anything"));
+    assert_eq!(found[9], (19, "No post or page has the slug `gone`"));
+    assert_eq!(
+        found[10],
+        (23, "This row has 1 cell but the first row has 2 cells")
+    );
+    assert_eq!(found.len(), 11);
+}
@@ -0,0 +1,77 @@
+use std::{collections::HashMap, ops::Range};
+
+use pulldown_cmark::{Event, LinkType, Options, Parser, Tag};
+
+use super::templates::blog_post_url;
+use crate::types::{CommonData, HydratedPost, LinkTarget};
+
+/// The slugs `source` links to with `[[slug]]` or `[[slug|text]]`, with
+/// where each link is.
+pub fn wiki_links(source: &str, options: Options) -> Vec<(Range<usize>, String)> {
+    Parser::new_ext(source, options)
+        .into_offset_iter()
+        .filter_map(|(event, range)| match event {
+            Event::Start(Tag::Link {
+                link_type: LinkType::WikiLink { .. },
+                dest_url,
+                ..
+            }) => Some((range, dest_url.trim().to_string())),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Where each of the links in `source` goes, or `None` for the ones that
+/// don't go anywhere. Pages depend on this so they're rewritten when a post
+/// they link to moves.
+pub fn resolved_links<'a>(
+    source: &str,
+    options: Options,
+    targets: &'a HashMap<String, LinkTarget>,
+) -> Vec<Option<&'a LinkTarget>> {
+    wiki_links(source, options)
+        .into_iter()
+        .map(|(_, slug)| targets.get(&slug))
+        .collect()
+}
+
+/// The posts that link to each post or page, keyed by the URL of the one
+/// they link to, oldest first.
+pub fn mentions(
+    posts: &[HydratedPost],
+    common: &CommonData,
+    options: Options,
+) -> anyhow::Result<HashMap<String, Vec<LinkTarget>>> {
+    let mut mentions: HashMap<String, Vec<LinkTarget>> = HashMap::new();
+    for post in posts.iter().rev() {
+        let url = blog_post_url(
+            post.url_slug.clone(),
+            post.post_date,
+            common.timezone,
+            common.base_url.clone(),
+        )?;
+        for target in resolved_links(&post.body, options, &common.link_targets)
+            .into_iter()
+            .flatten()
+        {
+            let mentioned_in = mentions.entry(target.url.clone()).or_default();
+            if target.url != url && !mentioned_in.iter().any(|m| m.url == url) {
+                mentioned_in.push(LinkTarget {
+                    title: post.title.clone(),
+                    url: url.clone(),
+                });
+            }
+        }
+    }
+    Ok(mentions)
+}
+
+#[test]
+fn finds_wiki_links_outside_code() {
+    let source = "See [[first-post]] and [[about|the about page]].\n\n`[[not-a-link]]`\n";
+    let links: Vec<String> = wiki_links(source, Options::ENABLE_WIKILINKS)
+        .into_iter()
+        .map(|(_, slug)| slug)
+        .collect();
+    assert_eq!(links, vec!["first-post", "about"]);
+}
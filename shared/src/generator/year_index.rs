@@ -4,10 +4,6 @@ use itertools::Itertools;
 use num_traits::FromPrimitive;
 use serde::Serialize;
 use tera::Context;
-use tokio::{
-    fs::{File, create_dir_all},
-    io::AsyncWriteExt,
-};
 
 use crate::types::{CommonData, HydratedPost};
 
@@ -31,17 +27,14 @@ pub async fn generate_year_index_pages(
     let now = Utc::now().date_naive();
 
     while current_date <= now {
-        let index_dir = format!("{}/{}", generator.output_path, current_date.year());
-        create_dir_all(&index_dir).await?;
-
-        let mut file = File::create(format!("{}/{}", index_dir, "index.html")).await?;
+        let index_path = format!("{}/index.html", current_date.year());
         let mut month_posts: Vec<&HydratedPost> = posts
             .iter()
             .filter(|p| {
                 p.post_date.with_timezone(&generator.common.timezone).year() == current_date.year()
             })
             .collect();
-        month_posts.sort_by(|a, b| a.post_date.cmp(&b.post_date));
+        month_posts.sort_by_key(|a| a.post_date);
         let mut grouped = Vec::new();
         for (key, group) in &month_posts
             .into_iter()
@@ -57,11 +50,24 @@ pub async fn generate_year_index_pages(
             common: generator.common,
             date: &current_date,
         };
+        let deps = generator
+            .page_dependencies("year_index.html")
+            .value(&current_date)
+            .posts(
+                page.posts_by_month
+                    .iter()
+                    .flat_map(|(_, p)| p.iter().copied()),
+                generator.common,
+            );
 
-        let rendered = generator
-            .tera
-            .render("year_index.html", &Context::from_serialize(page)?)?;
-        file.write_all(rendered.as_bytes()).await?;
+        generator
+            .render_output(
+                &index_path,
+                &deps,
+                "year_index.html",
+                &Context::from_serialize(page)?,
+            )
+            .await?;
 
         current_date = current_date
             .checked_add_months(chrono::Months::new(12))
@@ -2,7 +2,7 @@ pub mod activities;
 pub mod database;
 pub mod errors;
 pub mod generator;
-mod referencing;
+pub mod referencing;
 pub mod session;
 pub mod settings;
 pub mod types;
@@ -1,6 +1,6 @@
 use core::fmt;
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, HashMap, HashSet},
     fmt::Write,
     sync::LazyLock,
 };
@@ -8,20 +8,91 @@ use std::{
 use anyhow::anyhow;
 use regex::{Captures, Regex};
 use serde::{
-    Deserialize,
+    Deserialize, Serialize,
     de::{DeserializeSeed, IntoDeserializer, MapAccess, Visitor},
     forward_to_deserialize_any,
 };
+use tera::escape_html;
 
-#[derive(PartialEq, Eq, Clone, Debug, Deserialize)]
+use crate::utils::line_and_column;
+
+pub mod bibtex;
+pub mod csl_json;
+
+/// The site's shared references by name, which any post can cite. Sorted so
+/// it hashes the same way every time.
+pub type ReferenceLibrary = BTreeMap<String, Reference>;
+
+/// Reads a file of references for the library, as CSL-JSON if it looks like
+/// JSON and BibTeX otherwise.
+pub fn import_references(source: &str) -> anyhow::Result<Vec<anyhow::Result<Reference>>> {
+    match source.trim_start().starts_with(['[', '{']) {
+        true => csl_json::parse_csl_json(source),
+        false => Ok(bibtex::parse_bibtex(source)),
+    }
+}
+
+#[derive(PartialEq, Eq, Clone, Debug, Default, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 enum ReferenceType {
+    #[default]
     Journal,
     Website,
     Book,
+    Conference,
+    Thesis,
+    Report,
+    Preprint,
 }
 
-#[derive(PartialEq, Eq, Clone, Debug, Deserialize)]
+/// How citations and the reference list are written.
+#[derive(Serialize, PartialEq, Eq, Clone, Copy, Debug, Default)]
+pub enum CitationStyle {
+    /// Numbered citations, with references listed in the order they're first
+    /// cited.
+    #[default]
+    Vancouver,
+    Apa,
+    Harvard,
+    ChicagoAuthorDate,
+}
+
+impl CitationStyle {
+    pub const ALL: [CitationStyle; 4] = [
+        CitationStyle::Vancouver,
+        CitationStyle::Apa,
+        CitationStyle::Harvard,
+        CitationStyle::ChicagoAuthorDate,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            CitationStyle::Vancouver => "vancouver",
+            CitationStyle::Apa => "apa",
+            CitationStyle::Harvard => "harvard",
+            CitationStyle::ChicagoAuthorDate => "chicago",
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            CitationStyle::Vancouver => "Vancouver, numbered",
+            CitationStyle::Apa => "APA",
+            CitationStyle::Harvard => "Harvard",
+            CitationStyle::ChicagoAuthorDate => "Chicago author-date",
+        }
+    }
+
+    /// The style called `name`, or Vancouver if there isn't one.
+    pub fn parse(name: &str) -> CitationStyle {
+        CitationStyle::ALL
+            .into_iter()
+            .find(|s| s.name() == name.trim())
+            .unwrap_or_default()
+    }
+}
+
+#[derive(PartialEq, Eq, Clone, Debug, Default, Serialize, Deserialize)]
 pub struct Reference {
     name: String,
     kind: ReferenceType,
@@ -39,11 +110,283 @@ pub struct Reference {
     isbn: Option<String>,
     eprint: Option<String>,
     url: Option<String>,
+    publisher: Option<String>,
+    institution: Option<String>,
+    location: Option<String>,
+    /// An issue number for journals, or a report's number.
+    number: Option<String>,
+    /// The degree a thesis was for, like "PhD".
+    degree: Option<String>,
+    /// Where a preprint was posted, like "arXiv".
+    repository: Option<String>,
+    /// When a website was read.
+    accessed: Option<String>,
+    /// A copy of a website in an archive, in case the original goes away.
+    archive: Option<String>,
+}
+
+/// Adds a full stop unless `text` already ends a sentence.
+fn stop(text: &str) -> String {
+    let text = text.trim_end();
+    match text.ends_with(['.', '?', '!']) {
+        true => text.to_string(),
+        false => format!("{}.", text),
+    }
+}
+
+/// The pieces of a reference that are there, separated by spaces.
+fn clauses(parts: Vec<Option<String>>) -> String {
+    parts
+        .into_iter()
+        .flatten()
+        .filter(|p| !p.is_empty())
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+fn autolink(url: &str) -> String {
+    format!("<{}>", url)
+}
+
+/// A person's surname and given names, or an organisation's whole name with
+/// no given names.
+struct Name {
+    surname: String,
+    given: String,
+}
+
+impl Name {
+    /// Given names shortened to initials, so "Steve" and the Vancouver "SK"
+    /// become "S." and "S. K.".
+    fn initials(&self) -> String {
+        self.given
+            .split(|c: char| c.is_whitespace() || c == '.')
+            .filter(|w| !w.is_empty())
+            .flat_map(|w| match w.chars().all(char::is_uppercase) {
+                true => w.chars().collect::<Vec<char>>(),
+                false => w.chars().take(1).collect(),
+            })
+            .map(|c| format!("{}.", c))
+            .collect::<Vec<String>>()
+            .join(" ")
+    }
+
+    /// "Surname, I." as APA and Harvard list authors.
+    fn surname_initials(&self) -> String {
+        match self.given.is_empty() {
+            true => self.surname.clone(),
+            false => format!("{}, {}", self.surname, self.initials()),
+        }
+    }
+
+    /// "Surname, Given" as Chicago lists the first author.
+    fn surname_first(&self) -> String {
+        match self.given.is_empty() {
+            true => self.surname.clone(),
+            false => format!("{}, {}", self.surname, self.given),
+        }
+    }
+
+    /// "Given Surname" as Chicago lists the rest.
+    fn given_first(&self) -> String {
+        match self.given.is_empty() {
+            true => self.surname.clone(),
+            false => format!("{} {}", self.given, self.surname),
+        }
+    }
+}
+
+/// Reads a name written as "Surname, Given", the Vancouver "Surname AB" or
+/// "Given Surname". Names with a lower case word after the first, like
+/// "Ministry of Health", are taken to be organisations and kept whole.
+fn parse_name(name: &str) -> Name {
+    let name = name.trim();
+    let whole = |surname: &str| Name {
+        surname: surname.to_string(),
+        given: String::new(),
+    };
+    if !name.contains(',')
+        && name
+            .split_whitespace()
+            .skip(1)
+            .any(|w| w.starts_with(char::is_lowercase))
+    {
+        return whole(name);
+    }
+    if let Some((surname, given)) = name.split_once(',') {
+        return Name {
+            surname: surname.trim().to_string(),
+            given: given.trim().to_string(),
+        };
+    }
+    let words: Vec<&str> = name.split_whitespace().collect();
+    match words.split_last() {
+        Some((initials, surname))
+            if !surname.is_empty()
+                && initials.len() <= 3
+                && initials.chars().all(|c| c.is_uppercase()) =>
+        {
+            Name {
+                surname: surname.join(" "),
+                given: initials.to_string(),
+            }
+        }
+        Some((surname, given)) => Name {
+            surname: surname.to_string(),
+            given: given.join(" "),
+        },
+        None => whole(""),
+    }
+}
+
+/// Joins names into a list with `and` before the last, and a comma before
+/// that too for styles that use a serial comma.
+fn name_list(names: Vec<String>, and: &str, serial: bool) -> String {
+    match names.split_last() {
+        None => String::new(),
+        Some((last, [])) => last.clone(),
+        Some((last, rest)) => format!(
+            "{}{} {} {}",
+            rest.join(", "),
+            if serial { "," } else { "" },
+            and,
+            last
+        ),
+    }
 }
 
 impl Reference {
-    pub fn format_reference(&self) -> String {
-        match self.kind {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The authors, written either as BibTeX does with `and` between them or
+    /// as a Vancouver list separated by commas.
+    fn names(&self) -> Vec<Name> {
+        let author = self.author.trim();
+        let names: Vec<&str> = if author.contains(" and ") {
+            author.split(" and ").collect()
+        } else if author.contains(',')
+            && author.split(',').all(|n| n.split_whitespace().count() >= 2)
+        {
+            author.split(',').collect()
+        } else {
+            vec![author]
+        };
+        names
+            .into_iter()
+            .map(parse_name)
+            .filter(|n| !n.surname.is_empty())
+            .collect()
+    }
+
+    fn surnames(&self) -> Vec<String> {
+        self.names().into_iter().map(|n| n.surname).collect()
+    }
+
+    /// Who wrote it, as it appears at the start of a reference.
+    fn author_list(&self, style: CitationStyle) -> String {
+        let names = self.names();
+        if names.is_empty() {
+            return self.author.trim().to_string();
+        }
+        match style {
+            CitationStyle::Apa => name_list(
+                names.iter().map(Name::surname_initials).collect(),
+                "&",
+                true,
+            ),
+            CitationStyle::Harvard => name_list(
+                names.iter().map(Name::surname_initials).collect(),
+                "and",
+                false,
+            ),
+            CitationStyle::ChicagoAuthorDate => name_list(
+                names
+                    .iter()
+                    .enumerate()
+                    .map(|(i, name)| match i {
+                        0 => name.surname_first(),
+                        _ => name.given_first(),
+                    })
+                    .collect(),
+                "and",
+                true,
+            ),
+            CitationStyle::Vancouver => self.author.clone(),
+        }
+    }
+
+    /// Who wrote it, as it appears in an author-date citation.
+    fn author_label(&self, style: CitationStyle) -> String {
+        let surnames = self.surnames();
+        let (and, et_al_from) = match style {
+            CitationStyle::Apa => ("&", 3),
+            _ => ("and", 4),
+        };
+        match surnames.as_slice() {
+            [] => self.title.clone(),
+            [only] => only.clone(),
+            [first, second] => format!("{} {} {}", first, and, second),
+            [first, ..] if surnames.len() >= et_al_from => format!("{} et al.", first),
+            [rest @ .., last] => format!("{}, {} {}", rest.join(", "), and, last),
+        }
+    }
+
+    fn year_text(&self) -> String {
+        self.year
+            .map(|y| y.to_string())
+            .unwrap_or_else(|| "n.d.".into())
+    }
+
+    fn doi_link(&self) -> Option<String> {
+        self.doi
+            .as_ref()
+            .map(|doi| autolink(&format!("https://doi.org/{}", doi)))
+    }
+
+    /// The DOI if there is one, otherwise the URL.
+    fn link(&self) -> Option<String> {
+        self.doi_link().or(self.url.as_deref().map(autolink))
+    }
+
+    fn place_and_publisher(&self, publisher: Option<&String>) -> Option<String> {
+        match (&self.location, publisher) {
+            (Some(location), Some(publisher)) => Some(format!("{}: {}", location, publisher)),
+            (None, Some(publisher)) => Some(publisher.clone()),
+            (Some(location), None) => Some(location.clone()),
+            (None, None) => None,
+        }
+    }
+
+    fn publisher_or_institution(&self) -> Option<&String> {
+        self.publisher.as_ref().or(self.institution.as_ref())
+    }
+
+    pub fn format_reference(&self, style: CitationStyle) -> String {
+        self.format_with_year(style, &self.year_text())
+    }
+
+    /// Formats the reference with `year` in place of its year, so author-date
+    /// styles can tell apart works by the same people in the same year.
+    fn format_with_year(&self, style: CitationStyle, year: &str) -> String {
+        match style {
+            CitationStyle::Vancouver => self.vancouver(),
+            CitationStyle::Apa => self.apa(year),
+            CitationStyle::Harvard => self.harvard(year),
+            CitationStyle::ChicagoAuthorDate => self.chicago(year),
+        }
+    }
+
+    fn vancouver(&self) -> String {
+        let published = |publisher: Option<&String>| {
+            let year = self.year.map(|y| y.to_string());
+            match (self.place_and_publisher(publisher), year) {
+                (Some(publisher), Some(year)) => Some(format!("{}; {}", publisher, year)),
+                (publisher, year) => publisher.or(year),
+            }
+        };
+        let parts: Vec<Option<String>> = match self.kind {
             ReferenceType::Journal => {
                 let mut parts: Vec<Option<String>> =
                     vec![Some(self.author.clone()), Some(self.title.clone())];
@@ -81,41 +424,365 @@ impl Reference {
                     )
                 });
                 parts.push(pmc);
-
-                format!(
-                    "{}.",
-                    parts
-                        .into_iter()
-                        .flatten()
-                        .collect::<Vec<String>>()
-                        .join(". ")
-                )
+                parts
             }
-            ReferenceType::Book => {
-                let parts: Vec<Option<String>> = vec![
-                    Some(self.author.clone()),
-                    self.chapter.clone(),
-                    Some(self.title.clone()),
-                    self.booktitle.clone(),
-                    self.year.map(|y| y.to_string()),
-                    self.pages.clone(),
-                    self.doi
-                        .clone()
-                        .map(|doi| format!("[doi:{}](https://doi.org/{})", doi, doi)),
-                    self.isbn.clone().map(|isbn| format!("ISBN {}", isbn)),
-                ];
+            ReferenceType::Book => vec![
+                Some(self.author.clone()),
+                self.chapter.clone(),
+                Some(self.title.clone()),
+                self.booktitle.clone(),
+                self.edition.as_ref().map(|e| format!("{} ed", e)),
+                published(self.publisher.as_ref()),
+                self.pages.clone(),
+                self.doi
+                    .clone()
+                    .map(|doi| format!("[doi:{}](https://doi.org/{})", doi, doi)),
+                self.isbn.clone().map(|isbn| format!("ISBN {}", isbn)),
+            ],
+            ReferenceType::Website => vec![
+                Some(self.author.clone()),
+                Some(format!("{} [Internet]", self.title)),
+                match (published(self.publisher.as_ref()), &self.accessed) {
+                    (Some(published), Some(accessed)) => {
+                        Some(format!("{} [cited {}]", published, accessed))
+                    }
+                    (None, Some(accessed)) => Some(format!("[cited {}]", accessed)),
+                    (published, None) => published,
+                },
+                self.url
+                    .as_ref()
+                    .map(|url| format!("Available from: {}", autolink(url))),
+                self.archive
+                    .as_ref()
+                    .map(|url| format!("Archived at: {}", autolink(url))),
+            ],
+            ReferenceType::Conference => vec![
+                Some(self.author.clone()),
+                Some(self.title.clone()),
+                self.booktitle.as_ref().map(|b| format!("In: {}", b)),
+                published(self.publisher.as_ref()),
+                self.pages.as_ref().map(|p| format!("p. {}", p)),
+                self.link(),
+            ],
+            ReferenceType::Thesis => vec![
+                Some(self.author.clone()),
+                Some(format!(
+                    "{} [{} thesis]",
+                    self.title,
+                    self.degree.as_deref().unwrap_or("Doctoral")
+                )),
+                published(self.institution.as_ref()),
+                self.link(),
+            ],
+            ReferenceType::Report => vec![
+                Some(self.author.clone()),
+                Some(self.title.clone()),
+                published(self.publisher_or_institution()),
+                self.number.as_ref().map(|n| format!("Report No.: {}", n)),
+                self.link(),
+            ],
+            ReferenceType::Preprint => vec![
+                Some(self.author.clone()),
+                Some(format!("{} [Preprint]", self.title)),
+                published(self.repository.as_ref()),
+                self.eprint.clone(),
+                self.link(),
+            ],
+        };
+
+        format!(
+            "{}.",
+            parts
+                .into_iter()
+                .flatten()
+                .collect::<Vec<String>>()
+                .join(". ")
+        )
+    }
 
-                format!(
-                    "{}.",
-                    parts
-                        .into_iter()
-                        .flatten()
-                        .collect::<Vec<String>>()
-                        .join(". ")
-                )
-            }
-            ReferenceType::Website => "".into(),
-        }
+    fn apa(&self, year: &str) -> String {
+        let head = Some(format!(
+            "{} ({}).",
+            self.author_list(CitationStyle::Apa),
+            year
+        ));
+        let parts = match self.kind {
+            ReferenceType::Journal => vec![
+                head,
+                Some(stop(&self.title)),
+                self.journal.as_ref().map(|journal| {
+                    let mut source = format!("*{}*", journal);
+                    if let Some(volume) = &self.volume {
+                        source.push_str(&format!(", *{}*", volume));
+                    }
+                    if let Some(number) = &self.number {
+                        source.push_str(&format!("({})", number));
+                    }
+                    if let Some(pages) = &self.pages {
+                        source.push_str(&format!(", {}", pages));
+                    }
+                    stop(&source)
+                }),
+                self.link(),
+            ],
+            ReferenceType::Book => vec![
+                head,
+                self.chapter.as_ref().map(|chapter| stop(chapter)),
+                Some(stop(&format!(
+                    "{}*{}*{}",
+                    if self.chapter.is_some() { "In " } else { "" },
+                    self.title,
+                    match (&self.edition, &self.pages) {
+                        (Some(edition), Some(pages)) =>
+                            format!(" ({} ed., pp. {})", edition, pages),
+                        (Some(edition), None) => format!(" ({} ed.)", edition),
+                        (None, Some(pages)) => format!(" (pp. {})", pages),
+                        (None, None) => String::new(),
+                    }
+                ))),
+                self.publisher.as_ref().map(|p| stop(p)),
+                self.link(),
+            ],
+            ReferenceType::Website => vec![
+                head,
+                Some(stop(&format!("*{}*", self.title))),
+                self.publisher.as_ref().map(|p| stop(p)),
+                match (&self.accessed, &self.url) {
+                    (Some(accessed), Some(url)) => {
+                        Some(format!("Retrieved {}, from {}", accessed, autolink(url)))
+                    }
+                    (_, url) => url.as_deref().map(autolink),
+                },
+                self.archive
+                    .as_ref()
+                    .map(|url| format!("(Archived at {})", autolink(url))),
+            ],
+            ReferenceType::Conference => vec![
+                head,
+                Some(stop(&self.title)),
+                self.booktitle.as_ref().map(|booktitle| {
+                    stop(&match &self.pages {
+                        Some(pages) => format!("In *{}* (pp. {})", booktitle, pages),
+                        None => format!("In *{}*", booktitle),
+                    })
+                }),
+                self.publisher.as_ref().map(|p| stop(p)),
+                self.link(),
+            ],
+            ReferenceType::Thesis => vec![
+                head,
+                Some(stop(&format!(
+                    "*{}* [{} thesis{}]",
+                    self.title,
+                    self.degree.as_deref().unwrap_or("Doctoral"),
+                    self.institution
+                        .as_ref()
+                        .map(|i| format!(", {}", i))
+                        .unwrap_or_default()
+                ))),
+                self.link(),
+            ],
+            ReferenceType::Report => vec![
+                head,
+                Some(stop(&match &self.number {
+                    Some(number) => format!("*{}* (Report No. {})", self.title, number),
+                    None => format!("*{}*", self.title),
+                })),
+                self.publisher_or_institution().map(|p| stop(p)),
+                self.link(),
+            ],
+            ReferenceType::Preprint => vec![
+                head,
+                Some(stop(&format!("*{}* [Preprint]", self.title))),
+                self.repository.as_ref().map(|r| stop(r)),
+                self.link(),
+            ],
+        };
+        clauses(parts)
+    }
+
+    fn harvard(&self, year: &str) -> String {
+        let head = format!("{} ({})", self.author_list(CitationStyle::Harvard), year);
+        let available = self
+            .link()
+            .map(|link| stop(&format!("Available at: {}", link)));
+        let parts = match self.kind {
+            ReferenceType::Journal => vec![
+                Some(format!("{} '{}',", head, self.title)),
+                self.journal.as_ref().map(|journal| {
+                    let mut source = format!("*{}*", journal);
+                    if let Some(volume) = &self.volume {
+                        source.push_str(&format!(", {}", volume));
+                    }
+                    if let Some(number) = &self.number {
+                        source.push_str(&format!("({})", number));
+                    }
+                    if let Some(pages) = &self.pages {
+                        source.push_str(&format!(", pp. {}", pages));
+                    }
+                    stop(&source)
+                }),
+                available,
+            ],
+            ReferenceType::Book => vec![
+                Some(stop(&format!("{} *{}*", head, self.title))),
+                self.edition.as_ref().map(|e| format!("{} edn.", e)),
+                self.place_and_publisher(self.publisher.as_ref())
+                    .map(|p| stop(&p)),
+                available,
+            ],
+            ReferenceType::Website => vec![
+                Some(stop(&format!("{} *{}*", head, self.title))),
+                self.url.as_ref().map(|url| {
+                    stop(&match &self.accessed {
+                        Some(accessed) => {
+                            format!("Available at: {} (Accessed: {})", autolink(url), accessed)
+                        }
+                        None => format!("Available at: {}", autolink(url)),
+                    })
+                }),
+                self.archive
+                    .as_ref()
+                    .map(|url| stop(&format!("Archived at: {}", autolink(url)))),
+            ],
+            ReferenceType::Conference => vec![
+                Some(format!("{} '{}',", head, self.title)),
+                self.booktitle
+                    .as_ref()
+                    .map(|booktitle| stop(&format!("in *{}*", booktitle))),
+                match (
+                    self.place_and_publisher(self.publisher.as_ref()),
+                    &self.pages,
+                ) {
+                    (Some(publisher), Some(pages)) => {
+                        Some(format!("{}, pp. {}.", publisher, pages))
+                    }
+                    (Some(publisher), None) => Some(stop(&publisher)),
+                    (None, Some(pages)) => Some(format!("pp. {}.", pages)),
+                    (None, None) => None,
+                },
+                available,
+            ],
+            ReferenceType::Thesis => vec![
+                Some(stop(&format!("{} *{}*", head, self.title))),
+                Some(format!(
+                    "{} thesis.",
+                    self.degree.as_deref().unwrap_or("Doctoral")
+                )),
+                self.institution.as_ref().map(|i| stop(i)),
+                available,
+            ],
+            ReferenceType::Report => vec![
+                Some(stop(&format!("{} *{}*", head, self.title))),
+                self.number.as_ref().map(|n| stop(n)),
+                self.place_and_publisher(self.publisher_or_institution())
+                    .map(|p| stop(&p)),
+                available,
+            ],
+            ReferenceType::Preprint => vec![
+                Some(format!("{} '{}'.", head, self.title)),
+                Some(clauses(vec![
+                    Some("[Preprint]".into()),
+                    self.repository.as_ref().map(|r| stop(r)),
+                ])),
+                available,
+            ],
+        };
+        clauses(parts)
+    }
+
+    fn chicago(&self, year: &str) -> String {
+        let head = Some(format!(
+            "{}. {}",
+            self.author_list(CitationStyle::ChicagoAuthorDate)
+                .trim_end_matches('.'),
+            stop(year)
+        ));
+        let quoted = Some(format!("“{}”", stop(&self.title)));
+        let parts = match self.kind {
+            ReferenceType::Journal => vec![
+                head,
+                quoted,
+                self.journal.as_ref().map(|journal| {
+                    let mut source = format!("*{}*", journal);
+                    if let Some(volume) = &self.volume {
+                        source.push_str(&format!(" {}", volume));
+                    }
+                    if let Some(number) = &self.number {
+                        source.push_str(&format!(" ({})", number));
+                    }
+                    if let Some(pages) = &self.pages {
+                        source.push_str(&format!(": {}", pages));
+                    }
+                    stop(&source)
+                }),
+                self.link().map(|l| stop(&l)),
+            ],
+            ReferenceType::Book => vec![
+                head,
+                Some(stop(&format!("*{}*", self.title))),
+                self.edition.as_ref().map(|e| format!("{} ed.", e)),
+                self.place_and_publisher(self.publisher.as_ref())
+                    .map(|p| stop(&p)),
+                self.link().map(|l| stop(&l)),
+            ],
+            ReferenceType::Website => vec![
+                head,
+                quoted,
+                self.publisher.as_ref().map(|p| stop(p)),
+                self.accessed
+                    .as_ref()
+                    .map(|a| stop(&format!("Accessed {}", a))),
+                self.url.as_ref().map(|url| stop(&autolink(url))),
+                self.archive
+                    .as_ref()
+                    .map(|url| stop(&format!("Archived at {}", autolink(url)))),
+            ],
+            ReferenceType::Conference => vec![
+                head,
+                quoted,
+                self.booktitle.as_ref().map(|booktitle| {
+                    stop(&match &self.pages {
+                        Some(pages) => format!("In *{}*, {}", booktitle, pages),
+                        None => format!("In *{}*", booktitle),
+                    })
+                }),
+                self.place_and_publisher(self.publisher.as_ref())
+                    .map(|p| stop(&p)),
+                self.link().map(|l| stop(&l)),
+            ],
+            ReferenceType::Thesis => vec![
+                head,
+                quoted,
+                Some(stop(&format!(
+                    "{} diss.{}",
+                    self.degree.as_deref().unwrap_or("PhD"),
+                    self.institution
+                        .as_ref()
+                        .map(|i| format!(", {}", i))
+                        .unwrap_or_default()
+                ))),
+                self.link().map(|l| stop(&l)),
+            ],
+            ReferenceType::Report => vec![
+                head,
+                Some(stop(&format!("*{}*", self.title))),
+                self.number.as_ref().map(|n| stop(n)),
+                self.place_and_publisher(self.publisher_or_institution())
+                    .map(|p| stop(&p)),
+                self.link().map(|l| stop(&l)),
+            ],
+            ReferenceType::Preprint => vec![
+                head,
+                quoted,
+                Some(stop(&format!(
+                    "{} preprint",
+                    self.repository.as_deref().unwrap_or("Unpublished")
+                ))),
+                self.link().map(|l| stop(&l)),
+            ],
+        };
+        clauses(parts)
     }
 }
 
@@ -509,8 +1176,10 @@ static CITATION_RE: LazyLock<Regex> =
     LazyLock::new(|| Regex::new(r#"\\cite\{([^}]+)\}"#).expect("Could not create citation regex"));
 
 static REFERENCE_RE: LazyLock<Regex> = LazyLock::new(|| {
-    Regex::new(r"@(journal|website|book)\{.*?,(?s).*?(?-s)\}\r?\n?")
-        .expect("Could not create reference regex")
+    Regex::new(
+        r"@(journal|website|book|conference|thesis|report|preprint)\{.*?,(?s).*?(?-s)\}\r?\n?",
+    )
+    .expect("Could not create reference regex")
 });
 
 struct ProcessedReferences {
@@ -529,105 +1198,295 @@ fn references_and_citations(source: &str) -> anyhow::Result<ProcessedReferences>
     })
 }
 
-pub fn references_to_markdown(source: String) -> anyhow::Result<String> {
+/// The author and year each cited reference is shown with in author-date
+/// styles, keyed by its position in the citations. Works by the same
+/// authors in the same year get a letter after the year, in the order
+/// they're listed.
+fn author_date_labels(
+    cited: &[(usize, &Reference)],
+    style: CitationStyle,
+) -> HashMap<usize, (String, String)> {
+    let mut counts: HashMap<(String, String), usize> = HashMap::new();
+    for (_, reference) in cited {
+        *counts
+            .entry((reference.author_label(style), reference.year_text()))
+            .or_default() += 1;
+    }
+    let mut seen: HashMap<(String, String), u8> = HashMap::new();
+    cited
+        .iter()
+        .map(|(index, reference)| {
+            let key = (reference.author_label(style), reference.year_text());
+            let year = if counts[&key] > 1 {
+                let n = seen.entry(key.clone()).or_default();
+                *n += 1;
+                format!("{}{}", key.1, (b'a' + *n - 1) as char)
+            } else {
+                key.1.clone()
+            };
+            (*index, (key.0, year))
+        })
+        .collect()
+}
+
+/// How author-date styles order the reference list.
+fn sort_key(reference: &Reference) -> (String, Option<i16>, String) {
+    (
+        reference
+            .surnames()
+            .first()
+            .unwrap_or(&reference.title)
+            .to_lowercase(),
+        reference.year,
+        reference.title.to_lowercase(),
+    )
+}
+
+/// A citation's marker in the text. Each time a work is cited it gets the
+/// same number or label, with its own id for the reference to link back to.
+fn citation_marker(
+    style: CitationStyle,
+    index: usize,
+    time: usize,
+    label: Option<&(String, String)>,
+) -> String {
+    match (style, label) {
+        (CitationStyle::Vancouver, _) | (_, None) => format!(
+            r##"<sup id="cite_{}_{}"><a href="#reference_{}">{}</a></sup>"##,
+            index, time, index, index
+        ),
+        (_, Some((author, year))) => format!(
+            r##"<span class="citation" id="cite_{}_{}">(<a href="#reference_{}">{}{} {}</a>)</span>"##,
+            index,
+            time,
+            index,
+            author,
+            if style == CitationStyle::Apa { "," } else { "" },
+            year
+        ),
+    }
+}
+
+/// Links from a reference back to every place it's cited.
+fn backlinks(index: usize, times_cited: usize) -> String {
+    match times_cited {
+        0 => String::new(),
+        1 => format!(
+            r##" <a href="#cite_{}_1" class="backlink" aria-label="Back to the citation">↩</a>"##,
+            index
+        ),
+        _ => {
+            let links = (1..=times_cited).fold(String::new(), |mut output, time| {
+                let _ = write!(
+                    output,
+                    r##" <a href="#cite_{}_{}" aria-label="Back to citation {}">{}</a>"##,
+                    index, time, time, time
+                );
+                output
+            });
+            format!(r#" <span class="backlinks">↩{}</span>"#, links)
+        }
+    }
+}
+
+/// The problems `reference_problems` finds, for the admin preview.
+fn problems_html(source: &str, library: &ReferenceLibrary) -> String {
+    let problems = reference_problems(source, library);
+    if problems.is_empty() {
+        return String::new();
+    }
+    let items = problems
+        .iter()
+        .fold(String::new(), |mut output, (offset, problem)| {
+            let (line, column) = line_and_column(source, *offset);
+            let _ = write!(
+                output,
+                "<p>Line {}, column {}: {}</p>",
+                line,
+                column,
+                escape_html(problem)
+            );
+            output
+        });
+    format!("\n\n<div class=\"reference-error\">{}</div>\n", items)
+}
+
+/// Swaps citations for links to a reference list added at the end. References
+/// written in the post win over library ones with the same name. Citations
+/// to nothing are marked, and previews list every problem with the
+/// references below them.
+pub fn references_to_markdown(
+    source: String,
+    style: CitationStyle,
+    library: &ReferenceLibrary,
+    preview: bool,
+) -> anyhow::Result<String> {
     let ProcessedReferences {
         citations,
         references,
         document_without_references,
     } = references_and_citations(&source)?;
+    if citations.is_empty() && references.is_empty() {
+        return Ok(source);
+    }
 
-    if references.is_empty() {
-        Ok(source)
-    } else {
-        let mut reference_counter: HashMap<usize, i32> = HashMap::new();
-
-        let with_citations =
-            CITATION_RE.replace_all(&document_without_references, |m: &Captures| {
-                match m
-                    .get(1)
-                    .map(|c| c.as_str())
-                    .and_then(|c| citations.iter().position(|v| c == *v).map(|p| p + 1))
-                {
-                    Some(index) => {
-                        let cite_index = *reference_counter.get(&index).unwrap_or(&1);
-                        reference_counter.insert(index, cite_index + 1);
-                        format!(
-                            r##"<sup id="cite_{}_{}"><a href="#reference_{}">{}</a></sup>"##,
-                            index, cite_index, index, index
-                        )
-                    }
-                    None => "<sup>Unknown citation</sup>".into(),
-                }
-            });
+    // Only citations that go somewhere are numbered, in the order they're
+    // first cited.
+    let resolved: Vec<(&str, &Reference)> = citations
+        .iter()
+        .filter_map(|name| match references.get(name) {
+            Some(Ok(reference)) => Some((name.as_str(), reference)),
+            _ => library
+                .get(name)
+                .map(|reference| (name.as_str(), reference)),
+        })
+        .collect();
+    let numbers: HashMap<&str, usize> = resolved
+        .iter()
+        .enumerate()
+        .map(|(i, (name, _))| (*name, i + 1))
+        .collect();
 
-        // TODO surface errors
-        let ordered_references = citations
+    let mut cited: Vec<(usize, &Reference)> = resolved
+        .iter()
+        .enumerate()
+        .map(|(i, (_, reference))| (i + 1, *reference))
+        .collect();
+    if style != CitationStyle::Vancouver {
+        cited.sort_by_key(|(_, r)| sort_key(r));
+    }
+    let labels = author_date_labels(&cited, style);
+
+    let mut times_cited: HashMap<usize, usize> = HashMap::new();
+    let with_citations = CITATION_RE.replace_all(&document_without_references, |m: &Captures| {
+        let name = &m[1];
+        match numbers.get(name) {
+            Some(&index) => {
+                let time = times_cited.entry(index).or_default();
+                *time += 1;
+                citation_marker(style, index, *time, labels.get(&index))
+            }
+            None if preview => format!(
+                r#"<span class="reference-error">No reference is called <code>{}</code></span>"#,
+                escape_html(name)
+            ),
+            None => r#"<sup class="citation-missing">?</sup>"#.into(),
+        }
+    });
+
+    let listed = |index: usize, reference: &Reference, year: Option<&String>| {
+        format!(
+            "<span id=\"reference_{}\">{}</span>{}",
+            index,
+            match year {
+                Some(year) => reference.format_with_year(style, year),
+                None => reference.format_reference(style),
+            },
+            backlinks(index, times_cited.get(&index).copied().unwrap_or(0))
+        )
+    };
+    let reference_list = match style {
+        CitationStyle::Vancouver => cited
             .iter()
-            .enumerate()
-            .map(|(i, c)| {
-                let index = i + 1;
-                references
-                    .get(c)
-                    .map(|maybe_ref| match maybe_ref {
-                        Ok(r) => {
-                            let citation_count = *reference_counter.get(&index).unwrap_or(&0);
-                            let backlinks = if citation_count > 0 {
-                                let links: String =
-                                    (1..citation_count).fold(String::new(), |mut output, i| {
-                                        let _ = write!(
-                                            output,
-                                            r##"<a href="#cite_{}_{}">{}</a>"##,
-                                            index, i, i
-                                        );
-                                        output
-                                    });
-                                format!("<sup>{}</sup>", links)
-                            } else {
-                                "".to_string()
-                            };
-                            format!(
-                                "{}. <span id=\"reference_{}\">{}</span>{}\n",
-                                index,
-                                index,
-                                r.format_reference(),
-                                backlinks
-                            )
-                        }
-                        Err(e) => format!("Got error for a real reference {:?}", e),
-                    })
-                    .unwrap_or(format!(
-                        "{}. Could not find citation {} in keys {:?}\n\n",
-                        index,
-                        c,
-                        references.keys()
-                    ))
-            })
-            .collect::<String>();
-
-        let errors = references
-            .values()
-            .filter(|v| v.is_err())
-            .map(|shouldbe_err| match shouldbe_err {
-                Ok(_) => "Not possible".into(),
-                Err(e) => format!("\n* err: {}", e),
-            })
-            .collect::<String>();
+            .map(|(index, reference)| format!("{}. {}\n", index, listed(*index, reference, None)))
+            .collect::<String>(),
+        _ => format!(
+            "<div class=\"references author-date\">\n\n{}\n</div>\n",
+            cited
+                .iter()
+                .map(|(index, reference)| format!(
+                    "- {}\n",
+                    listed(*index, reference, labels.get(index).map(|(_, year)| year))
+                ))
+                .collect::<String>()
+        ),
+    };
 
-        Ok(format!(
+    let problems = match preview {
+        true => problems_html(&source, library),
+        false => String::new(),
+    };
+
+    match cited.is_empty() {
+        true => Ok(format!("{}{}", with_citations.trim_end(), problems)),
+        false => Ok(format!(
             "{}\n\n## References\n\n{}{}",
             with_citations.trim_end(),
-            ordered_references,
-            errors
-        ))
+            reference_list,
+            problems
+        )),
     }
 }
 
+/// References that don't parse, names used by more than one reference,
+/// references in the post that are never cited and citations with no
+/// reference in the post or the library, with the byte offset in `source`
+/// each problem starts at.
+pub fn reference_problems(source: &str, library: &ReferenceLibrary) -> Vec<(usize, String)> {
+    let cited: HashSet<&str> = CITATION_RE
+        .captures_iter(source)
+        .filter_map(|c| c.get(1))
+        .map(|m| m.as_str())
+        .collect();
+    let mut defined: HashSet<String> = HashSet::new();
+    let mut problems = Vec::new();
+    for m in REFERENCE_RE.find_iter(source) {
+        match reference_from_str::<Reference>(m.as_str()) {
+            Ok(reference) => {
+                if !cited.contains(reference.name.as_str()) {
+                    problems.push((m.start(), format!("`{}` is never cited", reference.name)));
+                }
+                if !defined.insert(reference.name.clone()) {
+                    problems.push((
+                        m.start(),
+                        format!("There's already a reference called `{}`", reference.name),
+                    ));
+                }
+            }
+            Err(e) => problems.push((m.start(), format!("Could not read reference: {}", e))),
+        }
+    }
+    for c in CITATION_RE.captures_iter(source) {
+        if let Some(name) = c.get(1)
+            && !defined.contains(name.as_str())
+            && !library.contains_key(name.as_str())
+        {
+            problems.push((
+                name.start(),
+                format!(
+                    "Cited `{}` but there is no reference with that name",
+                    name.as_str()
+                ),
+            ));
+        }
+    }
+    problems.sort_by_key(|(offset, _)| *offset);
+    problems
+}
+
 pub fn remove_citations_and_references(source: String) -> String {
     REFERENCE_RE
         .replace_all(&CITATION_RE.replace_all(&source, ""), "")
         .to_string()
 }
 
+/// The library entries `source` cites by name, with `None` for names the
+/// library doesn't have. Generated files depend on these rather than the
+/// whole library.
+pub fn cited_references<'a>(
+    source: &str,
+    library: &'a ReferenceLibrary,
+) -> Vec<(String, Option<&'a Reference>)> {
+    extract_citations(source)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|name| {
+            let reference = library.get(&name);
+            (name, reference)
+        })
+        .collect()
+}
+
 fn extract_citations(source: &str) -> anyhow::Result<Vec<String>> {
     let mut seen = HashSet::new();
     let mut captures: Vec<&str> = CITATION_RE
@@ -711,6 +1570,7 @@ this is a citation test \cite{test1}
             url: None,
             eprint: None,
             booktitle: None,
+            ..Default::default()
         }
     );
     assert_eq!(
@@ -732,6 +1592,7 @@ this is a citation test \cite{test1}
             url: None,
             eprint: None,
             booktitle: None,
+            ..Default::default()
         }
     );
 }
@@ -765,9 +1626,10 @@ pub fn test_format_journal_reference() {
         url: None,
         eprint: None,
         booktitle: None,
+        ..Default::default()
     };
     let expected = "Limb L, Limb P, Limb A. Another test article. A journal. 2024 Apr:122-143.";
-    let formatted = test_reference.format_reference();
+    let formatted = test_reference.format_reference(CitationStyle::Vancouver);
 
     assert_eq!(expected, formatted);
 }
@@ -791,9 +1653,10 @@ pub fn test_format_journal_reference_with_doi() {
         url: None,
         eprint: None,
         booktitle: None,
+        ..Default::default()
     };
     let expected = "Limb L, Limb P, Limb A. Another test article. A journal. 2024 Apr:122-143. [doi:10.1000/182](https://doi.org/10.1000/182).";
-    let formatted = test_reference.format_reference();
+    let formatted = test_reference.format_reference(CitationStyle::Vancouver);
 
     assert_eq!(expected, formatted);
 }
@@ -819,7 +1682,13 @@ year = 2023
 }
 
 "#;
-    let result = references_to_markdown(input.into()).unwrap();
+    let result = references_to_markdown(
+        input.into(),
+        CitationStyle::Vancouver,
+        &ReferenceLibrary::new(),
+        false,
+    )
+    .unwrap();
     assert_eq!(
         result,
         r##"
@@ -827,8 +1696,160 @@ this is a citation test<sup id="cite_1_1"><a href="#reference_1">1</a></sup> and
 
 ## References
 
-1. <span id="reference_1">test 1. A test article. A journal. 2023.</span><sup><a href="#cite_1_1">1</a><a href="#cite_1_2">2</a></sup>
-2. <span id="reference_2">test 2. Another test article. A journal. 2024.</span><sup><a href="#cite_2_1">1</a></sup>
+1. <span id="reference_1">test 1. A test article. A journal. 2023.</span> <span class="backlinks">↩ <a href="#cite_1_1" aria-label="Back to citation 1">1</a> <a href="#cite_1_2" aria-label="Back to citation 2">2</a></span>
+2. <span id="reference_2">test 2. Another test article. A journal. 2024.</span> <a href="#cite_2_1" class="backlink" aria-label="Back to the citation">↩</a>
 "##
     );
 }
+
+#[test]
+pub fn test_author_date_references() {
+    let input = r#"Sites\cite{site} and papers\cite{b}\cite{a} agree\cite{site}.
+
+@website{site,
+author = "Klabnik, Steve and Nichols, Carol",
+title = "The Rust Book",
+publisher = "rust-lang.org",
+url = "https://doc.rust-lang.org/book/",
+accessed = "1 May 2025",
+archive = "https://web.archive.org/web/2025/https://doc.rust-lang.org/book/",
+year = 2024
+}
+
+@conference{a,
+author = "Smith, Jo and Jones, Al",
+title = "First paper",
+booktitle = "Proceedings",
+pages = "1-10",
+year = 2020
+}
+
+@preprint{b,
+author = "Smith, Jo and Jones, Al",
+title = "Second paper",
+repository = "arXiv",
+eprint = "2001.00001",
+year = 2020
+}
+"#;
+    let result = references_to_markdown(
+        input.into(),
+        CitationStyle::Apa,
+        &ReferenceLibrary::new(),
+        false,
+    )
+    .unwrap();
+    assert_eq!(
+        result,
+        r##"Sites<span class="citation" id="cite_1_1">(<a href="#reference_1">Klabnik & Nichols, 2024</a>)</span> and papers<span class="citation" id="cite_2_1">(<a href="#reference_2">Smith & Jones, 2020b</a>)</span><span class="citation" id="cite_3_1">(<a href="#reference_3">Smith & Jones, 2020a</a>)</span> agree<span class="citation" id="cite_1_2">(<a href="#reference_1">Klabnik & Nichols, 2024</a>)</span>.
+
+## References
+
+<div class="references author-date">
+
+- <span id="reference_1">Klabnik, S., & Nichols, C. (2024). *The Rust Book*. rust-lang.org. Retrieved 1 May 2025, from <https://doc.rust-lang.org/book/> (Archived at <https://web.archive.org/web/2025/https://doc.rust-lang.org/book/>)</span> <span class="backlinks">↩ <a href="#cite_1_1" aria-label="Back to citation 1">1</a> <a href="#cite_1_2" aria-label="Back to citation 2">2</a></span>
+- <span id="reference_3">Smith, J., & Jones, A. (2020a). First paper. In *Proceedings* (pp. 1-10).</span> <a href="#cite_3_1" class="backlink" aria-label="Back to the citation">↩</a>
+- <span id="reference_2">Smith, J., & Jones, A. (2020b). *Second paper* [Preprint]. arXiv.</span> <a href="#cite_2_1" class="backlink" aria-label="Back to the citation">↩</a>
+
+</div>
+"##
+    );
+
+    let harvard = references_to_markdown(
+        input.into(),
+        CitationStyle::Harvard,
+        &ReferenceLibrary::new(),
+        false,
+    )
+    .unwrap();
+    assert!(
+        harvard.contains(
+            r#"<span id="reference_1">Klabnik, S. and Nichols, C. (2024) *The Rust Book*."#
+        )
+    );
+    let chicago = references_to_markdown(
+        input.into(),
+        CitationStyle::ChicagoAuthorDate,
+        &ReferenceLibrary::new(),
+        false,
+    )
+    .unwrap();
+    assert!(chicago.contains(r#"<span id="reference_1">Klabnik, Steve, and Carol Nichols. 2024."#));
+}
+
+#[test]
+pub fn test_library_references() {
+    let library: ReferenceLibrary = bibtex::parse_bibtex(
+        "@book{shared, author = {Knuth, Donald}, title = {From the library}, year = 1997}\n@book{unused, title = {Unused}}",
+    )
+    .into_iter()
+    .map(|r| r.map(|r| (r.name.clone(), r)))
+    .collect::<anyhow::Result<_>>()
+    .unwrap();
+
+    let cited = references_to_markdown(
+        "See\\cite{shared}.".into(),
+        CitationStyle::Vancouver,
+        &library,
+        false,
+    )
+    .unwrap();
+    assert!(cited.ends_with(
+        "1. <span id=\"reference_1\">Knuth, Donald. From the library. 1997.</span> <a href=\"#cite_1_1\" class=\"backlink\" aria-label=\"Back to the citation\">↩</a>\n"
+    ));
+    assert!(!cited.contains("Unused"));
+
+    let overridden = references_to_markdown(
+        "See\\cite{shared}.\n\n@book{shared,\nauthor = \"Knuth D\",\ntitle = \"In the post\",\nyear = 1998\n}\n".into(),
+        CitationStyle::Vancouver,
+        &library,
+        false,
+    )
+    .unwrap();
+    assert!(overridden.contains("Knuth D. In the post. 1998."));
+    assert!(reference_problems("\\cite{shared}", &library).is_empty());
+}
+
+#[test]
+pub fn test_reference_diagnostics() {
+    let input = "One\\cite{a} two\\cite{nope} three\\cite{a}.\n\n@book{a,\nauthor = \"A\",\ntitle = \"First\"\n}\n@book{a,\nauthor = \"A\",\ntitle = \"Again\"\n}\n@book{unused,\nauthor = \"U\",\ntitle = \"Unused\"\n}\n";
+    let problems: Vec<(usize, usize, String)> = reference_problems(input, &ReferenceLibrary::new())
+        .into_iter()
+        .map(|(offset, problem)| {
+            let (line, column) = line_and_column(input, offset);
+            (line, column, problem)
+        })
+        .collect();
+    assert_eq!(
+        problems,
+        vec![
+            (
+                1,
+                22,
+                "Cited `nope` but there is no reference with that name".to_string()
+            ),
+            (7, 1, "There's already a reference called `a`".to_string()),
+            (11, 1, "`unused` is never cited".to_string()),
+        ]
+    );
+
+    let preview = references_to_markdown(
+        input.into(),
+        CitationStyle::Vancouver,
+        &ReferenceLibrary::new(),
+        true,
+    )
+    .unwrap();
+    assert!(preview.starts_with(r##"One<sup id="cite_1_1"><a href="#reference_1">1</a></sup> two<span class="reference-error">No reference is called <code>nope</code></span> three<sup id="cite_1_2"><a href="#reference_1">1</a></sup>."##));
+    assert!(preview.contains("<p>Line 11, column 1: `unused` is never cited</p>"));
+
+    let published = references_to_markdown(
+        input.into(),
+        CitationStyle::Vancouver,
+        &ReferenceLibrary::new(),
+        false,
+    )
+    .unwrap();
+    assert!(published.contains(r#" two<sup class="citation-missing">?</sup> three"#));
+    assert!(!published.contains("reference-error"));
+}
@@ -0,0 +1,366 @@
+use std::{collections::HashMap, sync::LazyLock};
+
+use anyhow::{anyhow, bail};
+use regex::{Captures, Regex};
+use unicode_normalization::UnicodeNormalization;
+
+use super::{Reference, ReferenceType};
+
+/// Entries that aren't references. `@string` macros are read separately.
+const SKIPPED_ENTRIES: [&str; 2] = ["comment", "preamble"];
+
+/// The macros BibTeX defines for month names.
+const MONTHS: [(&str, &str); 12] = [
+    ("jan", "January"),
+    ("feb", "February"),
+    ("mar", "March"),
+    ("apr", "April"),
+    ("may", "May"),
+    ("jun", "June"),
+    ("jul", "July"),
+    ("aug", "August"),
+    ("sep", "September"),
+    ("oct", "October"),
+    ("nov", "November"),
+    ("dec", "December"),
+];
+
+/// LaTeX accent commands and the combining marks they stand for.
+const ACCENTS: [(&str, char); 13] = [
+    ("\"", '\u{308}'),
+    ("'", '\u{301}'),
+    ("`", '\u{300}'),
+    ("^", '\u{302}'),
+    ("~", '\u{303}'),
+    ("=", '\u{304}'),
+    (".", '\u{307}'),
+    ("c", '\u{327}'),
+    ("u", '\u{306}'),
+    ("v", '\u{30C}'),
+    ("H", '\u{30B}'),
+    ("k", '\u{328}'),
+    ("r", '\u{30A}'),
+];
+
+/// Letters LaTeX writes as commands of their own. Like any LaTeX command
+/// made of letters, the spaces after one are part of it.
+const LETTERS: [(&str, &str); 12] = [
+    ("ss", "ß"),
+    ("aa", "å"),
+    ("AA", "Å"),
+    ("ae", "æ"),
+    ("AE", "Æ"),
+    ("oe", "œ"),
+    ("OE", "Œ"),
+    ("o", "ø"),
+    ("O", "Ø"),
+    ("l", "ł"),
+    ("L", "Ł"),
+    ("i", "ı"),
+];
+
+// `\"o`, `\"{o}` and `\c{c}` or `\c c`. Accented dotless i's are written
+// with the dot, so the accent goes over a plain i.
+static SYMBOL_ACCENT_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"\\(["'`^~=.])\s*(?:\{\s*(\\i|[A-Za-z])\s*\}|(\\i|[A-Za-z]))"#)
+        .expect("Could not create accent regex")
+});
+static LETTER_ACCENT_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"\\([cuvHkr])(?:\s*\{\s*(\\i|[A-Za-z])\s*\}|\s+(\\i|[A-Za-z]))")
+        .expect("Could not create accent regex")
+});
+static LETTER_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"\\(ss|aa|AA|ae|AE|oe|OE|o|O|l|L|i)(?:\{\}|\b\s*)")
+        .expect("Could not create letter regex")
+});
+
+/// Reads every entry in a BibTeX file. Each entry is read on its own, so one
+/// that can't be read doesn't stop the rest being imported. `@string` macros
+/// are filled in wherever they're used after they're defined.
+pub fn parse_bibtex(source: &str) -> Vec<anyhow::Result<Reference>> {
+    let mut references = Vec::new();
+    let mut strings: HashMap<String, String> = MONTHS
+        .iter()
+        .map(|(name, month)| (name.to_string(), month.to_string()))
+        .collect();
+    let mut rest = source;
+    while let Some(at) = rest.find('@') {
+        rest = &rest[at + 1..];
+        let kind_end = rest
+            .find(|c: char| !c.is_alphanumeric())
+            .unwrap_or(rest.len());
+        let kind = rest[..kind_end].to_lowercase();
+        let after_kind = rest[kind_end..].trim_start();
+        let close = match after_kind.chars().next() {
+            Some('{') => '}',
+            Some('(') => ')',
+            _ => continue,
+        };
+        let body = &after_kind[1..];
+        match closing(body, close) {
+            Some(end) => {
+                if kind == "string" {
+                    read_strings(&body[..end], &mut strings);
+                } else if !SKIPPED_ENTRIES.contains(&kind.as_str()) {
+                    references.push(entry(&kind, &body[..end], &strings));
+                }
+                rest = &body[end + 1..];
+            }
+            None => {
+                references.push(Err(anyhow!("The @{} entry is never closed", kind)));
+                break;
+            }
+        }
+    }
+    references
+}
+
+/// Where an entry's body ends, skipping over braces inside it.
+fn closing(body: &str, close: char) -> Option<usize> {
+    let mut depth = 0;
+    for (i, c) in body.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' if depth > 0 => depth -= 1,
+            c if c == close && depth == 0 => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Splits on `separator` where it isn't in braces or quotes.
+fn split_top_level(text: &str, separator: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut depth = 0;
+    let mut quoted = false;
+    let mut start = 0;
+    for (i, c) in text.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' if depth > 0 => depth -= 1,
+            '"' if depth == 0 => quoted = !quoted,
+            c if c == separator && depth == 0 && !quoted => {
+                parts.push(&text[start..i]);
+                start = i + c.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    parts.push(&text[start..]);
+    parts
+}
+
+/// Adds the macros a `@string` entry defines, usually just the one.
+fn read_strings(body: &str, strings: &mut HashMap<String, String>) {
+    for definition in split_top_level(body, ',') {
+        if let Some((name, value)) = definition.split_once('=') {
+            let value = field_value(value, strings);
+            strings.insert(name.trim().to_lowercase(), value);
+        }
+    }
+}
+
+/// A field's text, with the pieces joined by `#` run together, macros filled
+/// in, braces taken out and the common LaTeX escapes and accents undone.
+fn field_value(value: &str, strings: &HashMap<String, String>) -> String {
+    let joined: String = split_top_level(value, '#')
+        .into_iter()
+        .map(|part| {
+            let part = part.trim();
+            part.strip_prefix('{')
+                .and_then(|p| p.strip_suffix('}'))
+                .or_else(|| part.strip_prefix('"').and_then(|p| p.strip_suffix('"')))
+                .or_else(|| strings.get(&part.to_lowercase()).map(String::as_str))
+                .unwrap_or(part)
+        })
+        .collect();
+    let unescaped = undo_accents(&joined)
+        .replace(['{', '}'], "")
+        .replace("\\&", "&")
+        .replace("\\%", "%")
+        .replace("\\_", "_")
+        .replace("\\$", "$")
+        .replace("\\#", "#");
+    unescaped
+        .split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ")
+}
+
+/// Turns accent commands like `\"o` and letters like `\ss` into the
+/// characters they stand for.
+fn undo_accents(text: &str) -> String {
+    let accent = |c: &Captures| {
+        let letter = match c.get(2).or(c.get(3)).map(|m| m.as_str()) {
+            Some("\\i") => "i",
+            Some(letter) => letter,
+            None => "",
+        };
+        match ACCENTS.iter().find(|(command, _)| *command == &c[1]) {
+            Some((_, mark)) => format!("{}{}", letter, mark),
+            None => letter.to_string(),
+        }
+    };
+    let text = SYMBOL_ACCENT_RE.replace_all(text, accent);
+    let text = LETTER_ACCENT_RE.replace_all(&text, accent);
+    let text = LETTER_RE.replace_all(&text, |c: &Captures| {
+        LETTERS
+            .iter()
+            .find(|(command, _)| *command == &c[1])
+            .map(|(_, letter)| letter.to_string())
+            .unwrap_or_default()
+    });
+    text.nfc().collect()
+}
+
+fn entry(kind: &str, body: &str, strings: &HashMap<String, String>) -> anyhow::Result<Reference> {
+    let mut parts = split_top_level(body, ',').into_iter();
+    let name = parts
+        .next()
+        .map(str::trim)
+        .filter(|n| !n.is_empty())
+        .ok_or(anyhow!("A @{} entry has no key", kind))?
+        .to_string();
+    let mut fields: HashMap<String, String> = HashMap::new();
+    for field in parts.map(str::trim).filter(|f| !f.is_empty()) {
+        let (field_name, value) = field.split_once('=').ok_or(anyhow!(
+            "`{}` has a field with no value: {}",
+            name,
+            field
+        ))?;
+        fields.insert(
+            field_name.trim().to_lowercase(),
+            field_value(value, strings),
+        );
+    }
+    let field = |n: &str| fields.get(n).filter(|v| !v.is_empty()).cloned();
+
+    let title = field("title").ok_or(anyhow!("`{}` has no title", name))?;
+    let journal = field("journal").or(field("journaltitle"));
+    let has_eprint = field("eprint").is_some() || field("archiveprefix").is_some();
+    let (reference_kind, degree) = match kind {
+        "article" if journal.is_none() && has_eprint => (ReferenceType::Preprint, None),
+        "article" => (ReferenceType::Journal, None),
+        "book" | "booklet" | "inbook" | "incollection" => (ReferenceType::Book, None),
+        "inproceedings" | "conference" | "proceedings" => (ReferenceType::Conference, None),
+        "phdthesis" => (ReferenceType::Thesis, Some("PhD".to_string())),
+        "mastersthesis" => (ReferenceType::Thesis, Some("Master's".to_string())),
+        "thesis" => (ReferenceType::Thesis, field("type")),
+        "techreport" | "report" => (ReferenceType::Report, None),
+        "online" | "electronic" | "www" | "webpage" => (ReferenceType::Website, None),
+        "unpublished" => (ReferenceType::Preprint, None),
+        "misc" if has_eprint => (ReferenceType::Preprint, None),
+        "misc" => (ReferenceType::Website, None),
+        other => bail!("`{}` is a @{} entry, which can't be imported", name, other),
+    };
+
+    // A chapter's title is the chapter, and the book it's in is the booktitle.
+    let (chapter, title, booktitle) = match (kind, field("booktitle")) {
+        ("incollection" | "inbook", Some(book)) => (Some(title), book, None),
+        (_, booktitle) => (field("chapter"), title, booktitle),
+    };
+    let repository = field("archiveprefix").or(field("eprinttype"));
+    let eprint = match (&repository, field("eprint")) {
+        (Some(repository), Some(eprint)) => Some(format!("{}:{}", repository, eprint)),
+        (_, eprint) => eprint,
+    };
+
+    Ok(Reference {
+        name,
+        kind: reference_kind,
+        author: field("author").or(field("editor")).unwrap_or_default(),
+        title,
+        booktitle,
+        journal,
+        year: field("year")
+            .or(field("date"))
+            .and_then(|y| y.get(..4).and_then(|y| y.parse().ok())),
+        edition: field("edition"),
+        volume: field("volume"),
+        chapter,
+        pages: field("pages"),
+        doi: field("doi"),
+        pmid: field("pmid"),
+        isbn: field("isbn"),
+        eprint,
+        url: field("url")
+            .or(field("howpublished").and_then(|h| h.strip_prefix("\\url").map(str::to_string))),
+        publisher: field("publisher"),
+        institution: field("institution")
+            .or(field("school"))
+            .or(field("organization")),
+        location: field("address").or(field("location")),
+        number: field("number").or(field("issue")),
+        degree,
+        repository,
+        accessed: field("urldate"),
+        archive: field("archiveurl").or(field("archive")),
+    })
+}
+
+#[test]
+fn reads_bibtex_entries() {
+    let source = r#"
+% Exported from somewhere
+@string{ nat = "Nature" }
+@comment{ not a reference }
+
+@article{smith2020,
+  author = {Smith, Jo and Jones, Al},
+  title = {{A} study of \& things},
+  journal = nat,
+  year = 2020,
+  volume = {12},
+  number = 3,
+  pages = {1--10},
+  doi = {10.1000/1},
+}
+
+@incollection(lee2019,
+  author = "Lee, Ann",
+  title = "A chapter",
+  booktitle = "Big " # "Book",
+  publisher = {Otago Press},
+  year = {2019}
+)
+
+@phdthesis{doe2018, author = {G{\"o}del, Kurt and Erd\H{o}s, Paul and S{\o}rensen, S\o ren}, title = {My thesis}, school = {University of Otago}, year = 2018}
+@misc{turing, author = {Turing, Alan}, title = {Numbers} # " (" # Dec # ")", eprint = {1234.5678}, archivePrefix = {arXiv}}
+@patent{p1, title = {A patent}}
+@article{notitle, author = {Nobody}}
+"#;
+    let references = parse_bibtex(source);
+    assert_eq!(references.len(), 6);
+
+    let article = references[0].as_ref().unwrap();
+    assert_eq!(article.name, "smith2020");
+    assert_eq!(article.kind, ReferenceType::Journal);
+    assert_eq!(article.title, "A study of & things");
+    assert_eq!(article.journal.as_deref(), Some("Nature"));
+    assert_eq!(article.year, Some(2020));
+    assert_eq!(article.number.as_deref(), Some("3"));
+    assert_eq!(article.pages.as_deref(), Some("1--10"));
+
+    let chapter = references[1].as_ref().unwrap();
+    assert_eq!(chapter.kind, ReferenceType::Book);
+    assert_eq!(chapter.chapter.as_deref(), Some("A chapter"));
+    assert_eq!(chapter.title, "Big Book");
+
+    let thesis = references[2].as_ref().unwrap();
+    assert_eq!(thesis.kind, ReferenceType::Thesis);
+    assert_eq!(
+        thesis.author,
+        "Gödel, Kurt and Erdős, Paul and Sørensen, Søren"
+    );
+    assert_eq!(thesis.degree.as_deref(), Some("PhD"));
+    assert_eq!(thesis.institution.as_deref(), Some("University of Otago"));
+
+    let preprint = references[3].as_ref().unwrap();
+    assert_eq!(preprint.kind, ReferenceType::Preprint);
+    assert_eq!(preprint.title, "Numbers (December)");
+    assert_eq!(preprint.eprint.as_deref(), Some("arXiv:1234.5678"));
+
+    assert!(references[4].is_err());
+    assert!(references[5].is_err());
+}
@@ -0,0 +1,203 @@
+use anyhow::{anyhow, bail};
+use serde_json::Value;
+
+use super::{Reference, ReferenceType};
+
+/// Reads a CSL-JSON file, as exported by Zotero and most other reference
+/// managers: a list of items, or a single one. Each item is read on its own,
+/// so one that can't be read doesn't stop the rest being imported.
+pub fn parse_csl_json(source: &str) -> anyhow::Result<Vec<anyhow::Result<Reference>>> {
+    let items = match serde_json::from_str(source)? {
+        Value::Array(items) => items,
+        item @ Value::Object(_) => vec![item],
+        _ => bail!("CSL-JSON should be a list of items"),
+    };
+    Ok(items.iter().map(item).collect())
+}
+
+/// Volumes, pages, ids and so on can be either strings or numbers.
+fn text(item: &Value, field: &str) -> Option<String> {
+    match item.get(field)? {
+        Value::String(s) if !s.trim().is_empty() => Some(s.trim().to_string()),
+        Value::Number(n) => Some(n.to_string()),
+        _ => None,
+    }
+}
+
+/// Names as "Family, Given", with `and` between them the way BibTeX has them.
+fn names(item: &Value, field: &str) -> Option<String> {
+    let names: Vec<String> = item
+        .get(field)?
+        .as_array()?
+        .iter()
+        .filter_map(|name| {
+            match (
+                text(name, "family"),
+                text(name, "given"),
+                text(name, "literal"),
+            ) {
+                (Some(family), Some(given), _) => Some(format!("{}, {}", family, given)),
+                (Some(family), None, _) => Some(family),
+                (None, _, literal) => literal,
+            }
+        })
+        .collect();
+    (!names.is_empty()).then(|| names.join(" and "))
+}
+
+/// The parts of a date, year first, from either `date-parts` or a raw
+/// `2020-05-01` style date.
+fn date_parts(item: &Value, field: &str) -> Option<Vec<i64>> {
+    let date = item.get(field)?;
+    match date
+        .get("date-parts")
+        .and_then(|p| p.get(0))
+        .and_then(Value::as_array)
+    {
+        Some(parts) => Some(
+            parts
+                .iter()
+                .filter_map(|p| p.as_i64().or_else(|| p.as_str()?.parse().ok()))
+                .collect(),
+        ),
+        None => Some(
+            text(date, "raw")
+                .or(text(date, "literal"))?
+                .split('-')
+                .filter_map(|p| p.trim().parse().ok())
+                .collect(),
+        ),
+    }
+}
+
+fn item(item: &Value) -> anyhow::Result<Reference> {
+    let name = text(item, "id").ok_or(anyhow!("An item has no id"))?;
+    let title = text(item, "title").ok_or(anyhow!("`{}` has no title", name))?;
+    let csl_type = text(item, "type").unwrap_or_default();
+    let kind = match csl_type.as_str() {
+        "article-journal" | "article-magazine" | "article-newspaper" => ReferenceType::Journal,
+        "book" | "chapter" => ReferenceType::Book,
+        "paper-conference" => ReferenceType::Conference,
+        "thesis" => ReferenceType::Thesis,
+        "report" => ReferenceType::Report,
+        "webpage" | "post" | "post-weblog" => ReferenceType::Website,
+        "article" => ReferenceType::Preprint,
+        other => bail!(
+            "`{}` is a CSL `{}` item, which can't be imported",
+            name,
+            other
+        ),
+    };
+    let container = text(item, "container-title");
+    let publisher = text(item, "publisher");
+
+    // A chapter's title is the chapter, and the book it's in is the title.
+    let (chapter, title) = match (csl_type.as_str(), &container) {
+        ("chapter", Some(book)) => (Some(title), book.clone()),
+        _ => (None, title),
+    };
+
+    Ok(Reference {
+        name,
+        author: names(item, "author")
+            .or(names(item, "editor"))
+            .unwrap_or_default(),
+        title,
+        booktitle: (kind == ReferenceType::Conference)
+            .then(|| container.clone())
+            .flatten(),
+        journal: (kind == ReferenceType::Journal)
+            .then(|| container.clone())
+            .flatten(),
+        year: date_parts(item, "issued")
+            .and_then(|d| d.first().copied())
+            .and_then(|y| i16::try_from(y).ok()),
+        edition: text(item, "edition"),
+        volume: text(item, "volume"),
+        chapter,
+        pages: text(item, "page"),
+        doi: text(item, "DOI"),
+        pmid: text(item, "PMID"),
+        isbn: text(item, "ISBN"),
+        eprint: None,
+        url: text(item, "URL"),
+        publisher: match kind {
+            ReferenceType::Thesis => None,
+            ReferenceType::Website => container.clone().or(publisher.clone()),
+            _ => publisher.clone(),
+        },
+        institution: (kind == ReferenceType::Thesis)
+            .then(|| publisher.clone())
+            .flatten(),
+        location: text(item, "publisher-place"),
+        number: text(item, "issue").or(text(item, "number")),
+        degree: (kind == ReferenceType::Thesis)
+            .then(|| text(item, "genre"))
+            .flatten(),
+        repository: (kind == ReferenceType::Preprint)
+            .then(|| publisher.or(container))
+            .flatten(),
+        accessed: date_parts(item, "accessed").map(|parts| {
+            parts
+                .iter()
+                .enumerate()
+                .map(|(i, p)| match i {
+                    0 => p.to_string(),
+                    _ => format!("{:02}", p),
+                })
+                .collect::<Vec<String>>()
+                .join("-")
+        }),
+        archive: text(item, "archive_location").filter(|a| a.starts_with("http")),
+        kind,
+    })
+}
+
+#[test]
+fn reads_csl_json_items() {
+    let source = r#"[
+        {
+            "id": "smith2020",
+            "type": "article-journal",
+            "title": "A study",
+            "container-title": "Nature",
+            "author": [{"family": "Smith", "given": "Jo"}, {"literal": "Ministry of Health"}],
+            "issued": {"date-parts": [[2020, 5]]},
+            "volume": 12,
+            "issue": "3",
+            "page": "1-10",
+            "DOI": "10.1000/1"
+        },
+        {
+            "id": "page",
+            "type": "webpage",
+            "title": "A page",
+            "container-title": "Example",
+            "URL": "https://example.com",
+            "accessed": {"date-parts": [["2025", "6", "2"]]},
+            "issued": {"raw": "2022-01-01"}
+        },
+        {"id": "map", "type": "map", "title": "A map"},
+        {"type": "book", "title": "No id"}
+    ]"#;
+    let references = parse_csl_json(source).unwrap();
+    assert_eq!(references.len(), 4);
+
+    let article = references[0].as_ref().unwrap();
+    assert_eq!(article.kind, ReferenceType::Journal);
+    assert_eq!(article.author, "Smith, Jo and Ministry of Health");
+    assert_eq!(article.journal.as_deref(), Some("Nature"));
+    assert_eq!(article.year, Some(2020));
+    assert_eq!(article.volume.as_deref(), Some("12"));
+    assert_eq!(article.number.as_deref(), Some("3"));
+
+    let page = references[1].as_ref().unwrap();
+    assert_eq!(page.kind, ReferenceType::Website);
+    assert_eq!(page.publisher.as_deref(), Some("Example"));
+    assert_eq!(page.accessed.as_deref(), Some("2025-06-02"));
+    assert_eq!(page.year, Some(2022));
+
+    assert!(references[2].is_err());
+    assert!(references[3].is_err());
+    assert!(parse_csl_json("\"nope\"").is_err());
+}
@@ -1,6 +1,8 @@
 use chrono::{DateTime, Utc};
 use sqlx::{PgPool, query};
 
+use crate::{referencing::CitationStyle, types::MarkdownExtension};
+
 use std::{
     collections::HashMap,
     fmt::{self, Display},
@@ -25,6 +27,14 @@ pub enum SettingNames {
     ProfileLastUpdated,
     BskyUsername,
     BskyPassword,
+    ImageWidths,
+    CodeTheme,
+    CodeThemeDark,
+    CodeLineNumbers,
+    HeadingAnchors,
+    MarkdownExtensions,
+    Sidenotes,
+    CitationStyle,
 }
 const BLOG_NAME: &str = "blog_name";
 const ACTOR_NAME: &str = "actor_name";
@@ -42,6 +52,20 @@ const TIMEZONE: &str = "timezone";
 const PROFILE_LAST_UPDATED: &str = "profile_last_updated";
 const BSKY_USERNAME: &str = "bsky_username";
 const BSKY_PASSWORD: &str = "bsky_password";
+const IMAGE_WIDTHS: &str = "image_widths";
+const CODE_THEME: &str = "code_theme";
+const CODE_THEME_DARK: &str = "code_theme_dark";
+const CODE_LINE_NUMBERS: &str = "code_line_numbers";
+const HEADING_ANCHORS: &str = "heading_anchors";
+const MARKDOWN_EXTENSIONS: &str = "markdown_extensions";
+const SIDENOTES: &str = "sidenotes";
+const CITATION_STYLE: &str = "citation_style";
+
+/// Widths uploaded images are resized to when the site hasn't chosen any.
+pub const DEFAULT_IMAGE_WIDTHS: [u32; 4] = [480, 800, 1200, 1600];
+/// The syntect theme code blocks are highlighted with when the site hasn't
+/// chosen one.
+pub const DEFAULT_CODE_THEME: &str = "InspiredGitHub";
 
 impl Display for SettingNames {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -62,6 +86,14 @@ impl Display for SettingNames {
             SettingNames::ProfileLastUpdated => PROFILE_LAST_UPDATED,
             SettingNames::BskyUsername => BSKY_USERNAME,
             SettingNames::BskyPassword => BSKY_PASSWORD,
+            SettingNames::ImageWidths => IMAGE_WIDTHS,
+            SettingNames::CodeTheme => CODE_THEME,
+            SettingNames::CodeThemeDark => CODE_THEME_DARK,
+            SettingNames::CodeLineNumbers => CODE_LINE_NUMBERS,
+            SettingNames::HeadingAnchors => HEADING_ANCHORS,
+            SettingNames::MarkdownExtensions => MARKDOWN_EXTENSIONS,
+            SettingNames::Sidenotes => SIDENOTES,
+            SettingNames::CitationStyle => CITATION_STYLE,
         };
         write!(f, "{}", name)
     }
@@ -91,6 +123,14 @@ impl FromStr for SettingNames {
             PROFILE_LAST_UPDATED => Ok(SettingNames::ProfileLastUpdated),
             BSKY_USERNAME => Ok(SettingNames::BskyUsername),
             BSKY_PASSWORD => Ok(SettingNames::BskyPassword),
+            IMAGE_WIDTHS => Ok(SettingNames::ImageWidths),
+            CODE_THEME => Ok(SettingNames::CodeTheme),
+            CODE_THEME_DARK => Ok(SettingNames::CodeThemeDark),
+            CODE_LINE_NUMBERS => Ok(SettingNames::CodeLineNumbers),
+            HEADING_ANCHORS => Ok(SettingNames::HeadingAnchors),
+            MARKDOWN_EXTENSIONS => Ok(SettingNames::MarkdownExtensions),
+            SIDENOTES => Ok(SettingNames::Sidenotes),
+            CITATION_STYLE => Ok(SettingNames::CitationStyle),
             _ => Err(ParseSettingNamesError),
         }
     }
@@ -114,6 +154,14 @@ pub struct Settings {
     pub profile_last_updated: chrono::DateTime<Utc>,
     pub bsky_username: Option<String>,
     pub bsky_password: Option<String>,
+    pub image_widths: Vec<u32>,
+    pub code_theme: String,
+    pub code_theme_dark: Option<String>,
+    pub code_line_numbers: bool,
+    pub heading_anchors: bool,
+    pub markdown_extensions: Vec<MarkdownExtension>,
+    pub sidenotes: bool,
+    pub citation_style: CitationStyle,
 }
 
 impl Settings {
@@ -214,5 +262,41 @@ pub async fn get_settings_struct(connection: &PgPool, site_id: i32) -> anyhow::R
             .unwrap_or(Utc::now()),
         bsky_username: all_settings.get(&SettingNames::BskyUsername).cloned(),
         bsky_password: all_settings.get(&SettingNames::BskyPassword).cloned(),
+        image_widths: all_settings
+            .get(&SettingNames::ImageWidths)
+            .map(|w| {
+                w.split(',')
+                    .filter_map(|n| n.trim().parse::<u32>().ok())
+                    .filter(|n| *n > 0)
+                    .collect::<Vec<u32>>()
+            })
+            .filter(|w| !w.is_empty())
+            .unwrap_or(DEFAULT_IMAGE_WIDTHS.to_vec()),
+        code_theme: all_settings
+            .get(&SettingNames::CodeTheme)
+            .filter(|t| !t.is_empty())
+            .unwrap_or(&DEFAULT_CODE_THEME.into())
+            .into(),
+        code_theme_dark: all_settings
+            .get(&SettingNames::CodeThemeDark)
+            .filter(|t| !t.is_empty())
+            .cloned(),
+        code_line_numbers: all_settings
+            .get(&SettingNames::CodeLineNumbers)
+            .is_some_and(|v| v == "true"),
+        heading_anchors: all_settings
+            .get(&SettingNames::HeadingAnchors)
+            .is_some_and(|v| v == "true"),
+        markdown_extensions: all_settings
+            .get(&SettingNames::MarkdownExtensions)
+            .map(|e| MarkdownExtension::parse_list(e))
+            .unwrap_or_default(),
+        sidenotes: all_settings
+            .get(&SettingNames::Sidenotes)
+            .is_some_and(|v| v == "true"),
+        citation_style: all_settings
+            .get(&SettingNames::CitationStyle)
+            .map(|s| CitationStyle::parse(s))
+            .unwrap_or_default(),
     })
 }
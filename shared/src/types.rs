@@ -2,10 +2,13 @@ use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use std::{collections::HashMap, fmt};
 
+use crate::referencing::{CitationStyle, ReferenceLibrary};
+
 #[derive(PartialEq, Clone, Serialize, Deserialize)]
 pub struct HydratedPost {
     pub id: i32,
     pub post_date: DateTime<Utc>,
+    pub updated_date: DateTime<Utc>,
     pub url_slug: String,
     pub title: String,
     pub body: String,
@@ -16,6 +19,8 @@ pub struct HydratedPost {
     pub summary: Option<String>,
     pub tags: Option<Vec<String>>,
     pub site_id: i32,
+    /// Number of the edition the post was published in, if any.
+    pub edition: Option<i32>,
 }
 
 #[derive(Serialize)]
@@ -37,8 +42,32 @@ pub struct PageLink {
     pub url_slug: String,
 }
 
-#[derive(Serialize)]
+/// A feed a page can advertise, for `<link rel="alternate">` and subscribe
+/// links.
+#[derive(Serialize, Debug)]
+pub struct FeedLink {
+    pub title: String,
+    pub url: String,
+    pub mime_type: &'static str,
+}
+
+/// The feeds for one tag, so any page can offer to subscribe to them.
+#[derive(Serialize, Debug)]
+pub struct TagFeeds {
+    pub tag: String,
+    pub feeds: Vec<FeedLink>,
+}
+
+/// A post or page a `[[slug]]` link can point at.
+#[derive(Serialize, Debug, PartialEq, Clone)]
+pub struct LinkTarget {
+    pub title: String,
+    pub url: String,
+}
+
+#[derive(Serialize, Default)]
 pub struct CommonData {
+    pub site_id: i32,
     pub base_url: String,
     pub static_base_url: String,
     pub media_base_url: String,
@@ -47,7 +76,31 @@ pub struct CommonData {
     pub archive_years: Vec<i32>,
     pub links: Vec<Link>,
     pub page_links: Vec<PageLink>,
+    /// Every tag with published posts, by name.
+    pub tag_feeds: Vec<TagFeeds>,
+    /// Left out of the site-wide hash like `link_targets`, pages depend on
+    /// the media they embed instead.
+    #[serde(skip_serializing)]
     pub media: HashMap<i32, Media>,
+    pub editions_enabled: bool,
+    pub code_theme: String,
+    pub code_theme_dark: Option<String>,
+    pub code_line_numbers: bool,
+    pub heading_anchors: bool,
+    pub markdown_extensions: Vec<MarkdownExtension>,
+    pub sidenotes: bool,
+    pub citation_style: CitationStyle,
+    /// Set when rendering an admin preview, so problems can be shown to the
+    /// author instead of hidden from readers.
+    pub preview: bool,
+    /// Posts and pages by slug. Left out of the site-wide hash, pages depend
+    /// on the links they actually use instead.
+    #[serde(skip_serializing)]
+    pub link_targets: HashMap<String, LinkTarget>,
+    /// Also left out of the site-wide hash, pages depend on the entries they
+    /// cite.
+    #[serde(skip_serializing)]
+    pub reference_library: ReferenceLibrary,
     #[serde(skip_serializing)]
     pub timezone: chrono_tz::Tz,
 }
@@ -66,6 +119,18 @@ pub struct ImageMetadata {
     pub content_type: String,
     pub fullsize_name: String,
     pub thumbnail_name: String,
+    /// Resized and re-encoded copies for `srcset`, made by `publish` after
+    /// the upload. Until it has run there are none.
+    #[serde(default)]
+    pub variants: Vec<ImageVariant>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct ImageVariant {
+    pub width: u32,
+    pub height: u32,
+    pub content_type: String,
+    pub file_name: String,
 }
 
 #[derive(serde::Deserialize, sqlx::Type, fmt::Debug, PartialEq, Clone)]
@@ -83,6 +148,67 @@ impl fmt::Display for PostStatus {
     }
 }
 
+/// Markdown syntax a site can switch on. They're all off until turned on,
+/// so old posts keep rendering the way they always have.
+#[derive(Serialize, Debug, PartialEq, Clone, Copy)]
+pub enum MarkdownExtension {
+    Tables,
+    TaskLists,
+    HeadingAttributes,
+    DefinitionLists,
+    Admonitions,
+    WikiLinks,
+}
+
+impl MarkdownExtension {
+    pub const ALL: [MarkdownExtension; 6] = [
+        MarkdownExtension::Tables,
+        MarkdownExtension::TaskLists,
+        MarkdownExtension::HeadingAttributes,
+        MarkdownExtension::DefinitionLists,
+        MarkdownExtension::Admonitions,
+        MarkdownExtension::WikiLinks,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            MarkdownExtension::Tables => "tables",
+            MarkdownExtension::TaskLists => "task_lists",
+            MarkdownExtension::HeadingAttributes => "heading_attributes",
+            MarkdownExtension::DefinitionLists => "definition_lists",
+            MarkdownExtension::Admonitions => "admonitions",
+            MarkdownExtension::WikiLinks => "wiki_links",
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            MarkdownExtension::Tables => "Tables",
+            MarkdownExtension::TaskLists => "Task lists, like - [x] done",
+            MarkdownExtension::HeadingAttributes => {
+                "Heading attributes, like ## Title {#id .class}"
+            }
+            MarkdownExtension::DefinitionLists => {
+                "Definition lists, a term followed by : definition"
+            }
+            MarkdownExtension::Admonitions => "Admonitions, like > [!NOTE]",
+            MarkdownExtension::WikiLinks => "Links to posts and pages, like [[slug]]",
+        }
+    }
+
+    /// Reads the comma separated list the setting is stored as, skipping
+    /// anything it doesn't recognise.
+    pub fn parse_list(list: &str) -> Vec<MarkdownExtension> {
+        list.split(',')
+            .filter_map(|name| {
+                MarkdownExtension::ALL
+                    .into_iter()
+                    .find(|e| e.name() == name.trim())
+            })
+            .collect()
+    }
+}
+
 #[derive(serde::Deserialize, sqlx::Type, fmt::Debug, PartialEq, Clone)]
 #[sqlx(type_name = "comment_status")] // only for PostgreSQL to match a type definition
 #[sqlx(rename_all = "lowercase")]
@@ -35,6 +35,17 @@ pub fn parse_into<T: FromStr>(s: &str) -> anyhow::Result<T> {
     s.parse().map_err(|_| anyhow!("Failed to parse string"))
 }
 
+/// The line and column `offset` is at, both counted from 1. Columns count
+/// characters, not bytes.
+pub(crate) fn line_and_column(source: &str, offset: usize) -> (usize, usize) {
+    let before = &source[..offset];
+    let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+    (
+        before.matches('\n').count() + 1,
+        before[line_start..].chars().count() + 1,
+    )
+}
+
 pub fn render_redirect(action: &str, site_id: i32) -> anyhow::Result<cgi::Response> {
     let body: Vec<u8> = "Redirecting".as_bytes().to_vec();
     let response = cgi::http::response::Builder::new()